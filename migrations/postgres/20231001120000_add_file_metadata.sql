@@ -0,0 +1,3 @@
+ALTER TABLE files ADD COLUMN IF NOT EXISTS size BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS mime TEXT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS hash TEXT;
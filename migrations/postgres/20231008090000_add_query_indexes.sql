@@ -0,0 +1,21 @@
+-- pg_trgm は部分一致検索を速くするだけなので、拡張を作れない権限で動かす場合は索引なしで続ける
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+EXCEPTION WHEN insufficient_privilege OR undefined_file THEN
+    RAISE NOTICE 'pg_trgm is not available, skipping trigram indexes';
+END
+$$;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm') THEN
+        EXECUTE 'CREATE INDEX IF NOT EXISTS files_file_name_trgm_idx ON files USING gin (file_name gin_trgm_ops)';
+        EXECUTE 'CREATE INDEX IF NOT EXISTS files_origin_file_path_trgm_idx ON files USING gin (origin_file_path gin_trgm_ops)';
+    END IF;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS files_file_name_pattern_idx ON files (file_name text_pattern_ops);
+CREATE INDEX IF NOT EXISTS files_created_at_idx ON files (created_at);
+CREATE INDEX IF NOT EXISTS files_size_idx ON files (size);
@@ -0,0 +1,15 @@
+ALTER TABLE files ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE IF NOT EXISTS file_versions (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    version INTEGER NOT NULL,
+    file_url TEXT NOT NULL,
+    space_id TEXT NOT NULL,
+    block_id TEXT NOT NULL,
+    origin_file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    size BIGINT,
+    mime TEXT,
+    hash TEXT,
+    PRIMARY KEY (file_name, version)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_name, tag)
+);
+
+CREATE INDEX IF NOT EXISTS file_tags_tag_idx ON file_tags (tag);
+
+CREATE TABLE IF NOT EXISTS file_meta (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (file_name, key)
+);
+
+CREATE INDEX IF NOT EXISTS file_meta_key_value_idx ON file_meta (key, value);
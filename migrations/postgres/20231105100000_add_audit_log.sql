@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL,
+    os_user TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    yukumo_version TEXT NOT NULL,
+    action TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    file_version INTEGER,
+    block_id TEXT,
+    succeeded BOOLEAN NOT NULL,
+    detail TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_file_name_idx ON audit_log (file_name);
+CREATE INDEX IF NOT EXISTS audit_log_created_at_idx ON audit_log (created_at);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
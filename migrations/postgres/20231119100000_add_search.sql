@@ -0,0 +1,51 @@
+ALTER TABLE files ADD COLUMN IF NOT EXISTS search tsvector;
+
+-- パスの区切りや拡張子でも語を分ける (そのままだとパス全体が 1 語になる)
+CREATE OR REPLACE FUNCTION files_search_document(name TEXT, origin TEXT) RETURNS tsvector AS $$
+    SELECT
+        setweight(to_tsvector('simple', regexp_replace(name, '[/\\._]+', ' ', 'g')), 'A')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(tag, '[/\\._]+', ' ', 'g'), ' ') FROM file_tags WHERE file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(key || ' ' || value, '[/\\._]+', ' ', 'g'), ' ') FROM file_meta WHERE file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', regexp_replace(origin, '[/\\._]+', ' ', 'g')), 'C')
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION files_search_update() RETURNS trigger AS $$
+BEGIN
+    NEW.search := files_search_document(NEW.file_name, NEW.origin_file_path);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS files_search_update ON files;
+CREATE TRIGGER files_search_update
+    BEFORE INSERT OR UPDATE OF file_name, origin_file_path ON files
+    FOR EACH ROW EXECUTE FUNCTION files_search_update();
+
+-- タグとメタデータが変わったらファイルの search を作り直す (名前を変えたときの ON UPDATE CASCADE も含む)
+CREATE OR REPLACE FUNCTION file_tags_search_update() RETURNS trigger AS $$
+BEGIN
+    UPDATE files SET search = files_search_document(file_name, origin_file_path)
+    WHERE file_name = CASE WHEN TG_OP = 'DELETE' THEN OLD.file_name ELSE NEW.file_name END;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS file_tags_search_update ON file_tags;
+CREATE TRIGGER file_tags_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON file_tags
+    FOR EACH ROW EXECUTE FUNCTION file_tags_search_update();
+
+DROP TRIGGER IF EXISTS file_meta_search_update ON file_meta;
+CREATE TRIGGER file_meta_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON file_meta
+    FOR EACH ROW EXECUTE FUNCTION file_tags_search_update();
+
+UPDATE files SET search = files_search_document(file_name, origin_file_path);
+
+CREATE INDEX IF NOT EXISTS files_search_idx ON files USING gin (search);
@@ -0,0 +1,14 @@
+-- put したときの元ファイルの状態
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_host TEXT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_mtime TIMESTAMP;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_mode INTEGER;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_uid BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_gid BIGINT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS source_symlink TEXT;
+
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_host TEXT;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_mtime TIMESTAMP;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_mode INTEGER;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_uid BIGINT;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_gid BIGINT;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS source_symlink TEXT;
@@ -0,0 +1,71 @@
+-- 行はバケットと名前で決まる (これまでの行は default バケットに入る)
+ALTER TABLE file_versions DROP CONSTRAINT IF EXISTS file_versions_file_name_fkey;
+ALTER TABLE file_tags DROP CONSTRAINT IF EXISTS file_tags_file_name_fkey;
+ALTER TABLE file_meta DROP CONSTRAINT IF EXISTS file_meta_file_name_fkey;
+
+ALTER TABLE files ADD COLUMN IF NOT EXISTS bucket TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS bucket TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE file_tags ADD COLUMN IF NOT EXISTS bucket TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE file_meta ADD COLUMN IF NOT EXISTS bucket TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS bucket TEXT NOT NULL DEFAULT 'default';
+
+ALTER TABLE files DROP CONSTRAINT files_pkey, ADD PRIMARY KEY (bucket, file_name);
+ALTER TABLE file_versions DROP CONSTRAINT file_versions_pkey, ADD PRIMARY KEY (bucket, file_name, version);
+ALTER TABLE file_tags DROP CONSTRAINT file_tags_pkey, ADD PRIMARY KEY (bucket, file_name, tag);
+ALTER TABLE file_meta DROP CONSTRAINT file_meta_pkey, ADD PRIMARY KEY (bucket, file_name, key);
+
+ALTER TABLE file_versions ADD FOREIGN KEY (bucket, file_name)
+    REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE;
+ALTER TABLE file_tags ADD FOREIGN KEY (bucket, file_name)
+    REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE;
+ALTER TABLE file_meta ADD FOREIGN KEY (bucket, file_name)
+    REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE;
+
+CREATE INDEX IF NOT EXISTS audit_log_bucket_file_name_idx ON audit_log (bucket, file_name);
+
+-- 全文検索のタグも同じバケットのものだけを見る
+DROP TRIGGER IF EXISTS files_search_update ON files;
+DROP TRIGGER IF EXISTS file_tags_search_update ON file_tags;
+DROP TRIGGER IF EXISTS file_meta_search_update ON file_meta;
+DROP FUNCTION IF EXISTS files_search_document(TEXT, TEXT);
+
+CREATE OR REPLACE FUNCTION files_search_document(b TEXT, name TEXT, origin TEXT) RETURNS tsvector AS $$
+    SELECT
+        setweight(to_tsvector('simple', regexp_replace(name, '[/\\._]+', ' ', 'g')), 'A')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(tag, '[/\\._]+', ' ', 'g'), ' ') FROM file_tags WHERE bucket = b AND file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(key || ' ' || value, '[/\\._]+', ' ', 'g'), ' ') FROM file_meta WHERE bucket = b AND file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', regexp_replace(origin, '[/\\._]+', ' ', 'g')), 'C')
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION files_search_update() RETURNS trigger AS $$
+BEGIN
+    NEW.search := files_search_document(NEW.bucket, NEW.file_name, NEW.origin_file_path);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_search_update
+    BEFORE INSERT OR UPDATE OF bucket, file_name, origin_file_path ON files
+    FOR EACH ROW EXECUTE FUNCTION files_search_update();
+
+CREATE OR REPLACE FUNCTION file_tags_search_update() RETURNS trigger AS $$
+BEGIN
+    UPDATE files SET search = files_search_document(bucket, file_name, origin_file_path)
+    WHERE (bucket, file_name) = CASE WHEN TG_OP = 'DELETE' THEN (OLD.bucket, OLD.file_name) ELSE (NEW.bucket, NEW.file_name) END;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER file_tags_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON file_tags
+    FOR EACH ROW EXECUTE FUNCTION file_tags_search_update();
+
+CREATE TRIGGER file_meta_search_update
+    AFTER INSERT OR UPDATE OR DELETE ON file_meta
+    FOR EACH ROW EXECUTE FUNCTION file_tags_search_update();
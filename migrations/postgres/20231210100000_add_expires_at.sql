@@ -0,0 +1,4 @@
+-- この日時を過ぎたら gc --expired で消す
+ALTER TABLE files ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS files_expires_at_idx ON files (expires_at) WHERE expires_at IS NOT NULL;
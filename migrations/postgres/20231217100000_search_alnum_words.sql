@@ -0,0 +1,17 @@
+-- 検索語 (search.rs の lexemes) と同じく、英数字でない文字はすべて語の区切りにする
+-- これまでは `/\._` だけだったので、`2023-10` のような名前が 1 語のままで検索できなかった
+CREATE OR REPLACE FUNCTION files_search_document(b TEXT, name TEXT, origin TEXT) RETURNS tsvector AS $$
+    SELECT
+        setweight(to_tsvector('simple', regexp_replace(name, '[^[:alnum:]]+', ' ', 'g')), 'A')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(tag, '[^[:alnum:]]+', ' ', 'g'), ' ') FROM file_tags WHERE bucket = b AND file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', coalesce(
+            (SELECT string_agg(regexp_replace(key || ' ' || value, '[^[:alnum:]]+', ' ', 'g'), ' ') FROM file_meta WHERE bucket = b AND file_name = name),
+            ''
+        )), 'B')
+        || setweight(to_tsvector('simple', regexp_replace(origin, '[^[:alnum:]]+', ' ', 'g')), 'C')
+$$ LANGUAGE sql STABLE;
+
+UPDATE files SET search = files_search_document(bucket, file_name, origin_file_path);
@@ -0,0 +1,8 @@
+-- どの問い合わせもバケットで絞るので、索引もバケットを先頭にする
+DROP INDEX IF EXISTS files_file_name_pattern_idx;
+DROP INDEX IF EXISTS files_created_at_idx;
+DROP INDEX IF EXISTS files_size_idx;
+
+CREATE INDEX IF NOT EXISTS files_bucket_file_name_pattern_idx ON files (bucket, file_name text_pattern_ops);
+CREATE INDEX IF NOT EXISTS files_bucket_created_at_idx ON files (bucket, created_at);
+CREATE INDEX IF NOT EXISTS files_bucket_size_idx ON files (bucket, size);
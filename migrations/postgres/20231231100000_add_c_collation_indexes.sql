@@ -0,0 +1,8 @@
+-- 一覧は名前をバイト順 (COLLATE "C") で並べるので、同じ照合順序の索引を使えるようにする
+CREATE INDEX IF NOT EXISTS files_bucket_file_name_c_idx ON files (bucket, file_name COLLATE "C");
+
+-- 日時とサイズで並べたときも、同じ値の中の名前順まで索引で決める
+DROP INDEX IF EXISTS files_bucket_created_at_idx;
+DROP INDEX IF EXISTS files_bucket_size_idx;
+CREATE INDEX IF NOT EXISTS files_bucket_created_at_idx ON files (bucket, created_at, file_name COLLATE "C");
+CREATE INDEX IF NOT EXISTS files_bucket_size_idx ON files (bucket, size, file_name COLLATE "C");
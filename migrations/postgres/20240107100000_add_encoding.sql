@@ -0,0 +1,3 @@
+-- put したときにかけた圧縮と暗号化 (`zstd+xchacha20poly1305` など、かけていなければ NULL)
+ALTER TABLE files ADD COLUMN IF NOT EXISTS encoding TEXT;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS encoding TEXT;
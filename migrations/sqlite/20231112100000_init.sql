@@ -0,0 +1,76 @@
+CREATE TABLE IF NOT EXISTS files (
+    file_name TEXT NOT NULL,
+    file_url TEXT NOT NULL,
+    space_id TEXT NOT NULL,
+    block_id TEXT NOT NULL,
+    origin_file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL default CURRENT_TIMESTAMP,
+    size INTEGER,
+    mime TEXT,
+    hash TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TIMESTAMP,
+    PRIMARY KEY (file_name)
+);
+
+CREATE INDEX IF NOT EXISTS files_created_at_idx ON files (created_at);
+CREATE INDEX IF NOT EXISTS files_size_idx ON files (size);
+CREATE INDEX IF NOT EXISTS files_deleted_at_idx ON files (deleted_at) WHERE deleted_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS file_versions (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    version INTEGER NOT NULL,
+    file_url TEXT NOT NULL,
+    space_id TEXT NOT NULL,
+    block_id TEXT NOT NULL,
+    origin_file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    size INTEGER,
+    mime TEXT,
+    hash TEXT,
+    PRIMARY KEY (file_name, version)
+);
+
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_name, tag)
+);
+
+CREATE INDEX IF NOT EXISTS file_tags_tag_idx ON file_tags (tag);
+
+CREATE TABLE IF NOT EXISTS file_meta (
+    file_name TEXT NOT NULL REFERENCES files (file_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (file_name, key)
+);
+
+CREATE INDEX IF NOT EXISTS file_meta_key_value_idx ON file_meta (key, value);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at TIMESTAMP NOT NULL,
+    os_user TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    yukumo_version TEXT NOT NULL,
+    action TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    file_version INTEGER,
+    block_id TEXT,
+    succeeded BOOLEAN NOT NULL,
+    detail TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_file_name_idx ON audit_log (file_name);
+CREATE INDEX IF NOT EXISTS audit_log_created_at_idx ON audit_log (created_at);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
@@ -0,0 +1,75 @@
+-- rowid は files の rowid と同じにする
+-- tags はタグとメタデータ (`key value`) を空白でつないだもの
+CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(file_name, origin_file_path, tags);
+
+INSERT INTO files_fts (rowid, file_name, origin_file_path, tags)
+SELECT rowid, file_name, origin_file_path, coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = files.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = files.file_name), '')
+FROM files;
+
+CREATE TRIGGER IF NOT EXISTS files_fts_insert AFTER INSERT ON files
+BEGIN
+    INSERT INTO files_fts (rowid, file_name, origin_file_path, tags)
+    VALUES (NEW.rowid, NEW.file_name, NEW.origin_file_path, '');
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_fts_update AFTER UPDATE OF file_name, origin_file_path ON files
+BEGIN
+    UPDATE files_fts SET file_name = NEW.file_name, origin_file_path = NEW.origin_file_path
+    WHERE rowid = NEW.rowid;
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_fts_delete AFTER DELETE ON files
+BEGIN
+    DELETE FROM files_fts WHERE rowid = OLD.rowid;
+END;
+
+-- タグとメタデータが変わったら tags を作り直す (名前を変えたときの ON UPDATE CASCADE も含む)
+
+CREATE TRIGGER IF NOT EXISTS file_tags_fts_insert AFTER INSERT ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_tags_fts_update AFTER UPDATE ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_tags_fts_delete AFTER DELETE ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = OLD.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = OLD.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = OLD.file_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_meta_fts_insert AFTER INSERT ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_meta_fts_update AFTER UPDATE ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_meta_fts_delete AFTER DELETE ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.file_name = OLD.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.file_name = OLD.file_name), '')
+    WHERE rowid = (SELECT rowid FROM files WHERE file_name = OLD.file_name);
+END;
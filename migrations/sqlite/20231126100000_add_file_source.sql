@@ -0,0 +1,14 @@
+-- put したときの元ファイルの状態
+ALTER TABLE files ADD COLUMN source_host TEXT;
+ALTER TABLE files ADD COLUMN source_mtime TIMESTAMP;
+ALTER TABLE files ADD COLUMN source_mode INTEGER;
+ALTER TABLE files ADD COLUMN source_uid INTEGER;
+ALTER TABLE files ADD COLUMN source_gid INTEGER;
+ALTER TABLE files ADD COLUMN source_symlink TEXT;
+
+ALTER TABLE file_versions ADD COLUMN source_host TEXT;
+ALTER TABLE file_versions ADD COLUMN source_mtime TIMESTAMP;
+ALTER TABLE file_versions ADD COLUMN source_mode INTEGER;
+ALTER TABLE file_versions ADD COLUMN source_uid INTEGER;
+ALTER TABLE file_versions ADD COLUMN source_gid INTEGER;
+ALTER TABLE file_versions ADD COLUMN source_symlink TEXT;
@@ -0,0 +1,183 @@
+-- files_fts の行を files の明示的な id に結びつける
+-- 暗黙の rowid は VACUUM で振り直されることがあり、そうなると検索結果が別のファイルになる
+-- INTEGER PRIMARY KEY なら振り直されないので、(bucket, file_name) は UNIQUE にして id を主キーにする
+-- 子の表が files_old を参照するように書き換わらないよう、add_buckets と同じくすべて作り直す
+
+DROP TRIGGER IF EXISTS files_fts_insert;
+DROP TRIGGER IF EXISTS files_fts_update;
+DROP TRIGGER IF EXISTS files_fts_delete;
+DROP TRIGGER IF EXISTS file_tags_fts_insert;
+DROP TRIGGER IF EXISTS file_tags_fts_update;
+DROP TRIGGER IF EXISTS file_tags_fts_delete;
+DROP TRIGGER IF EXISTS file_meta_fts_insert;
+DROP TRIGGER IF EXISTS file_meta_fts_update;
+DROP TRIGGER IF EXISTS file_meta_fts_delete;
+
+ALTER TABLE files RENAME TO files_old;
+ALTER TABLE file_versions RENAME TO file_versions_old;
+ALTER TABLE file_tags RENAME TO file_tags_old;
+ALTER TABLE file_meta RENAME TO file_meta_old;
+
+CREATE TABLE files (
+    id INTEGER PRIMARY KEY,
+    bucket TEXT NOT NULL DEFAULT 'default',
+    file_name TEXT NOT NULL,
+    file_url TEXT NOT NULL,
+    space_id TEXT NOT NULL,
+    block_id TEXT NOT NULL,
+    origin_file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL default CURRENT_TIMESTAMP,
+    size INTEGER,
+    mime TEXT,
+    hash TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TIMESTAMP,
+    source_host TEXT,
+    source_mtime TIMESTAMP,
+    source_mode INTEGER,
+    source_uid INTEGER,
+    source_gid INTEGER,
+    source_symlink TEXT,
+    expires_at TIMESTAMP,
+    UNIQUE (bucket, file_name)
+);
+
+CREATE TABLE file_versions (
+    bucket TEXT NOT NULL DEFAULT 'default',
+    file_name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    file_url TEXT NOT NULL,
+    space_id TEXT NOT NULL,
+    block_id TEXT NOT NULL,
+    origin_file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    size INTEGER,
+    mime TEXT,
+    hash TEXT,
+    source_host TEXT,
+    source_mtime TIMESTAMP,
+    source_mode INTEGER,
+    source_uid INTEGER,
+    source_gid INTEGER,
+    source_symlink TEXT,
+    PRIMARY KEY (bucket, file_name, version),
+    FOREIGN KEY (bucket, file_name) REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE file_tags (
+    bucket TEXT NOT NULL DEFAULT 'default',
+    file_name TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (bucket, file_name, tag),
+    FOREIGN KEY (bucket, file_name) REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE file_meta (
+    bucket TEXT NOT NULL DEFAULT 'default',
+    file_name TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (bucket, file_name, key),
+    FOREIGN KEY (bucket, file_name) REFERENCES files (bucket, file_name) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+INSERT INTO files (id, bucket, file_name, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash, version, deleted_at,
+    source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink, expires_at)
+SELECT rowid, bucket, file_name, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash, version, deleted_at,
+    source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink, expires_at
+FROM files_old;
+
+INSERT INTO file_versions (bucket, file_name, version, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash,
+    source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink)
+SELECT bucket, file_name, version, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash,
+    source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink
+FROM file_versions_old;
+
+INSERT INTO file_tags (bucket, file_name, tag) SELECT bucket, file_name, tag FROM file_tags_old;
+INSERT INTO file_meta (bucket, file_name, key, value) SELECT bucket, file_name, key, value FROM file_meta_old;
+
+DROP TABLE file_meta_old;
+DROP TABLE file_tags_old;
+DROP TABLE file_versions_old;
+DROP TABLE files_old;
+
+CREATE INDEX IF NOT EXISTS files_created_at_idx ON files (created_at);
+CREATE INDEX IF NOT EXISTS files_size_idx ON files (size);
+CREATE INDEX IF NOT EXISTS files_deleted_at_idx ON files (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS files_expires_at_idx ON files (expires_at) WHERE expires_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS file_tags_tag_idx ON file_tags (tag);
+CREATE INDEX IF NOT EXISTS file_meta_key_value_idx ON file_meta (key, value);
+
+-- これまでの行がずれていても直るように、全文検索の行も作り直す
+DELETE FROM files_fts;
+
+INSERT INTO files_fts (rowid, file_name, origin_file_path, tags)
+SELECT id, file_name, origin_file_path,
+    coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = files.bucket AND t.file_name = files.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = files.bucket AND m.file_name = files.file_name), '')
+FROM files;
+
+CREATE TRIGGER files_fts_insert AFTER INSERT ON files
+BEGIN
+    INSERT INTO files_fts (rowid, file_name, origin_file_path, tags)
+    VALUES (NEW.id, NEW.file_name, NEW.origin_file_path, '');
+END;
+
+CREATE TRIGGER files_fts_update AFTER UPDATE OF file_name, origin_file_path ON files
+BEGIN
+    UPDATE files_fts SET file_name = NEW.file_name, origin_file_path = NEW.origin_file_path
+    WHERE rowid = NEW.id;
+END;
+
+CREATE TRIGGER files_fts_delete AFTER DELETE ON files
+BEGIN
+    DELETE FROM files_fts WHERE rowid = OLD.id;
+END;
+
+CREATE TRIGGER file_tags_fts_insert AFTER INSERT ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = NEW.bucket AND t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = NEW.bucket AND m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = NEW.bucket AND file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER file_tags_fts_update AFTER UPDATE ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = NEW.bucket AND t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = NEW.bucket AND m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = NEW.bucket AND file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER file_tags_fts_delete AFTER DELETE ON file_tags
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = OLD.bucket AND t.file_name = OLD.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = OLD.bucket AND m.file_name = OLD.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = OLD.bucket AND file_name = OLD.file_name);
+END;
+
+CREATE TRIGGER file_meta_fts_insert AFTER INSERT ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = NEW.bucket AND t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = NEW.bucket AND m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = NEW.bucket AND file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER file_meta_fts_update AFTER UPDATE ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = NEW.bucket AND t.file_name = NEW.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = NEW.bucket AND m.file_name = NEW.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = NEW.bucket AND file_name = NEW.file_name);
+END;
+
+CREATE TRIGGER file_meta_fts_delete AFTER DELETE ON file_meta
+BEGIN
+    UPDATE files_fts SET tags =
+        coalesce((SELECT group_concat(tag, ' ') FROM file_tags t WHERE t.bucket = OLD.bucket AND t.file_name = OLD.file_name), '')
+        || ' ' || coalesce((SELECT group_concat(key || ' ' || value, ' ') FROM file_meta m WHERE m.bucket = OLD.bucket AND m.file_name = OLD.file_name), '')
+    WHERE rowid = (SELECT id FROM files WHERE bucket = OLD.bucket AND file_name = OLD.file_name);
+END;
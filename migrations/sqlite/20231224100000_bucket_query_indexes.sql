@@ -0,0 +1,6 @@
+-- どの問い合わせもバケットで絞るので、索引もバケットを先頭にする
+DROP INDEX IF EXISTS files_created_at_idx;
+DROP INDEX IF EXISTS files_size_idx;
+
+CREATE INDEX IF NOT EXISTS files_bucket_created_at_idx ON files (bucket, created_at);
+CREATE INDEX IF NOT EXISTS files_bucket_size_idx ON files (bucket, size);
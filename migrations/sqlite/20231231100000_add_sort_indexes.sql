@@ -0,0 +1,6 @@
+-- 日時とサイズで並べたときも、同じ値の中の名前順まで索引で決める
+-- (名前順は UNIQUE (bucket, file_name) の索引を使う)
+DROP INDEX IF EXISTS files_bucket_created_at_idx;
+DROP INDEX IF EXISTS files_bucket_size_idx;
+CREATE INDEX IF NOT EXISTS files_bucket_created_at_idx ON files (bucket, created_at, file_name);
+CREATE INDEX IF NOT EXISTS files_bucket_size_idx ON files (bucket, size, file_name);
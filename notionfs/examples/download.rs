@@ -61,7 +61,7 @@ async fn main() -> Result<()> {
         if let Some(s) = res
             .url()
             .path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
         {
             let path = path.join(s);
             let bytes = res.bytes().await?;
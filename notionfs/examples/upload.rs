@@ -59,10 +59,7 @@ async fn main() -> Result<()> {
 
     log::debug!("page_id = {page_id}");
     log::debug!("space_id = {space_id}");
-    log::debug!(
-        "owner_user_id = {}",
-        owner_user_id.as_ref().map(String::as_str).unwrap_or("")
-    );
+    log::debug!("owner_user_id = {}", owner_user_id.as_deref().unwrap_or(""));
 
     // 最初にブロックを作っとかないといけないっぽい
     let new_block_id = create_new_block(&client, &space_id, &page_id).await?;
@@ -1,21 +1,24 @@
 pub mod notion;
 
-use std::path::Path;
+use std::{collections::HashMap, ops::Range, path::Path};
 
 use anyhow::{bail, ensure, Context, Result};
-use reqwest::header;
+use reqwest::{header, StatusCode};
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::notion::{
     client::Notion,
     types::{
-        GetSignedFileUrlsRequest, GetSignedFileUrlsRequestUrl, GetSignedFileUrlsResponse,
-        GetUploadFileUrlResponse, Operation, OperationCommand, OperationPointer, Transaction,
+        BlockValue, CollectionPointer, GetSignedFileUrlsRequest, GetSignedFileUrlsRequestUrl,
+        GetSignedFileUrlsResponse, GetUploadFileUrlResponse, Operation, OperationCommand,
+        OperationPointer, QueryCollectionLoader, QueryCollectionReducer, QueryCollectionRequest,
+        QueryCollectionResponse, RecordRequest, SyncRecordValuesRequest, SyncRecordValuesResponse,
+        Transaction,
     },
 };
 
-pub use reqwest::{Body, Response};
+pub use reqwest::{Body, Client, Response};
 
 /// 署名付きURLを取得する
 /// urls の各要素は `(url, block_id, space_id)` であること
@@ -24,7 +27,7 @@ pub async fn get_signed_file_urls(
     urls: &[(&str, &str, &str)],
 ) -> Result<Vec<String>> {
     let urls = urls
-        .into_iter()
+        .iter()
         .map(|(url, block_id, space_id)| GetSignedFileUrlsRequestUrl {
             url: url.to_string(),
             use_s3_url: false,
@@ -55,6 +58,213 @@ pub async fn get_file_by_signed_url(url: &str, file_token: &str) -> Result<Respo
     Ok(res)
 }
 
+/// 署名付きURLを使ってファイルを取得する
+/// range は Range ヘッダーの値をそのまま送り、206 や 416 の応答もそのまま返す
+pub async fn get_file_with_range_by_signed_url(
+    client: &Client,
+    url: &str,
+    file_token: &str,
+    range: Option<&str>,
+) -> Result<Response> {
+    let mut req = client
+        .get(url)
+        .header(header::COOKIE, format!("file_token={file_token}"));
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    let res = req.send().await?;
+    let status = res.status();
+    ensure!(
+        status.is_success() || status == StatusCode::RANGE_NOT_SATISFIABLE,
+        "Failed to get file: {status}"
+    );
+    Ok(res)
+}
+
+/// 署名付きURLを使ってファイルの range の部分だけを取得する
+/// ファイルの終わりを越えた部分は返さない (range が終わりより後ろなら空)
+pub async fn get_file_range_by_signed_url(
+    client: &Client,
+    url: &str,
+    file_token: &str,
+    range: Range<u64>,
+) -> Result<Vec<u8>> {
+    if range.is_empty() {
+        return Ok(Vec::new());
+    }
+    let res = client
+        .get(url)
+        .header(header::COOKIE, format!("file_token={file_token}"))
+        .header(
+            header::RANGE,
+            format!("bytes={}-{}", range.start, range.end - 1),
+        )
+        .send()
+        .await?;
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => Ok(res.bytes().await?.to_vec()),
+        StatusCode::RANGE_NOT_SATISFIABLE => Ok(Vec::new()),
+        // Range に対応していなければ全体が返ってくるので切り出す
+        status if status.is_success() => {
+            let bytes = res.bytes().await?;
+            let len = bytes.len() as u64;
+            Ok(bytes[range.start.min(len) as usize..range.end.min(len) as usize].to_vec())
+        }
+        status => bail!("Failed to get file range: {status}"),
+    }
+}
+
+/// ブロックを取得する
+/// 存在しないか見る権限がなければ None を返す
+pub async fn get_block(
+    client: &Notion,
+    block_id: &str,
+    space_id: &str,
+) -> Result<Option<BlockValue>> {
+    let SyncRecordValuesResponse { mut record_map } =
+        sync_record(client, "block", block_id, space_id)
+            .await
+            .context("Failed to get block")?;
+    Ok(record_map
+        .blocks
+        .remove(block_id)
+        .and_then(|record| record.value))
+}
+
+/// ブロックをまとめて 1 回で取得する
+/// blocks の各要素は `(block_id, space_id)` であること
+/// 存在しないか見る権限がないブロックは結果に入らない
+pub async fn get_blocks(
+    client: &Notion,
+    blocks: &[(&str, &str)],
+) -> Result<HashMap<String, BlockValue>> {
+    if blocks.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let requests = blocks
+        .iter()
+        .map(|(block_id, space_id)| RecordRequest {
+            pointer: OperationPointer {
+                table: "block".to_string(),
+                id: block_id.to_string(),
+                space_id: space_id.to_string(),
+            },
+            version: -1,
+        })
+        .collect();
+    let SyncRecordValuesResponse { record_map } = client
+        .sync_record_values(&SyncRecordValuesRequest { requests })
+        .await
+        .context("Failed to get blocks")?;
+    Ok(record_map
+        .blocks
+        .into_iter()
+        .filter_map(|(block_id, record)| record.value.map(|value| (block_id, value)))
+        .collect())
+}
+
+/// データベース (コレクション) を取得する
+/// プロパティの定義は `schema` に入っている
+pub async fn get_collection(
+    client: &Notion,
+    collection_id: &str,
+    space_id: &str,
+) -> Result<Option<BlockValue>> {
+    let SyncRecordValuesResponse { mut record_map } =
+        sync_record(client, "collection", collection_id, space_id)
+            .await
+            .context("Failed to get collection")?;
+    Ok(record_map
+        .collections
+        .remove(collection_id)
+        .and_then(|record| record.value))
+}
+
+async fn sync_record(
+    client: &Notion,
+    table: &str,
+    id: &str,
+    space_id: &str,
+) -> Result<SyncRecordValuesResponse> {
+    client
+        .sync_record_values(&SyncRecordValuesRequest {
+            requests: vec![RecordRequest {
+                pointer: OperationPointer {
+                    table: table.to_string(),
+                    id: id.to_string(),
+                    space_id: space_id.to_string(),
+                },
+                version: -1,
+            }],
+        })
+        .await
+}
+
+/// データベースの行をすべて取得する (ビューの並び順)
+pub async fn query_collection(
+    client: &Notion,
+    collection_id: &str,
+    view_id: &str,
+    space_id: &str,
+) -> Result<Vec<BlockValue>> {
+    const RESULTS: &str = "collection_group_results";
+    // ページングできないので十分大きくしておく
+    const LIMIT: usize = 100_000;
+
+    let QueryCollectionResponse {
+        mut result,
+        mut record_map,
+    } = client
+        .query_collection(&QueryCollectionRequest {
+            collection: CollectionPointer {
+                id: collection_id.to_string(),
+                space_id: space_id.to_string(),
+            },
+            collection_view: CollectionPointer {
+                id: view_id.to_string(),
+                space_id: space_id.to_string(),
+            },
+            loader: QueryCollectionLoader {
+                r#type: "reducer".to_string(),
+                reducers: [(
+                    RESULTS.to_string(),
+                    QueryCollectionReducer {
+                        r#type: "results".to_string(),
+                        limit: LIMIT,
+                    },
+                )]
+                .into(),
+                search_query: String::new(),
+                user_time_zone: "UTC".to_string(),
+            },
+        })
+        .await
+        .context("Failed to query collection")?;
+    let results = result
+        .reducer_results
+        .remove(RESULTS)
+        .context("Failed to get results of collection")?;
+    if results.has_more {
+        log::warn!("Collection {collection_id} has more than {LIMIT} rows; the rest are ignored.");
+    }
+    Ok(results
+        .block_ids
+        .iter()
+        .filter_map(|id| record_map.blocks.remove(id))
+        .map(|block| block.value)
+        .filter(|block| block.alive)
+        .collect())
+}
+
+/// ブロックの notion.so 上の URL
+pub fn block_url(parent_id: &str, block_id: &str) -> String {
+    format!(
+        "https://www.notion.so/{}#{}",
+        parent_id.replace('-', ""),
+        block_id.replace('-', "")
+    )
+}
+
 /// 新しいブロックを生成する
 pub async fn create_new_block(client: &Notion, space_id: &str, page_id: &str) -> Result<String> {
     let new_block_id = Uuid::new_v4().to_string();
@@ -77,7 +287,7 @@ pub async fn create_new_block(client: &Notion, space_id: &str, page_id: &str) ->
                     command: OperationCommand::Set,
                     args: [
                         ("type".to_string(), json!("embed")),
-                        ("space_id".to_string(), json!(space_id.clone())),
+                        ("space_id".to_string(), json!(space_id)),
                         ("id".to_string(), json!(new_block_id.clone())),
                         ("version".to_string(), json!(1)),
                     ]
@@ -136,6 +346,173 @@ pub async fn create_new_block(client: &Notion, space_id: &str, page_id: &str) ->
     Ok(new_block_id)
 }
 
+/// データベースに行 (ページ) を追加する
+pub async fn create_collection_row(
+    client: &Notion,
+    space_id: &str,
+    collection_id: &str,
+    title: &str,
+) -> Result<String> {
+    let new_block_id = Uuid::new_v4().to_string();
+    client
+        .save_transactions(vec![Transaction {
+            id: Uuid::new_v4().to_string(),
+            space_id: space_id.to_string(),
+            debug: Default::default(),
+            operations: vec![Operation {
+                pointer: OperationPointer {
+                    table: "block".to_string(),
+                    id: new_block_id.clone(),
+                    space_id: space_id.to_string(),
+                },
+                path: Default::default(),
+                command: OperationCommand::Set,
+                args: [
+                    ("type".to_string(), json!("page")),
+                    ("space_id".to_string(), json!(space_id)),
+                    ("id".to_string(), json!(new_block_id.clone())),
+                    ("version".to_string(), json!(1)),
+                    ("parent_id".to_string(), json!(collection_id)),
+                    ("parent_table".to_string(), json!("collection")),
+                    ("alive".to_string(), json!(true)),
+                    (
+                        "properties".to_string(),
+                        json!({ "title": [[title.to_string()]] }),
+                    ),
+                ]
+                .into(),
+            }],
+        }])
+        .await
+        .context("Failed to create collection row")?;
+    log::debug!("New row {new_block_id} created.");
+
+    Ok(new_block_id)
+}
+
+/// データベースの行 (ページ) をアーカイブする
+pub async fn archive_collection_row(client: &Notion, block_id: &str, space_id: &str) -> Result<()> {
+    client
+        .save_transactions(vec![Transaction {
+            id: Uuid::new_v4().to_string(),
+            space_id: space_id.to_string(),
+            debug: Default::default(),
+            operations: vec![Operation {
+                pointer: OperationPointer {
+                    table: "block".to_string(),
+                    id: block_id.to_string(),
+                    space_id: space_id.to_string(),
+                },
+                path: Default::default(),
+                command: OperationCommand::Update,
+                args: [("alive".to_string(), json!(false))].into(),
+            }],
+        }])
+        .await
+        .context("Failed to archive collection row")?;
+    log::debug!("Row {block_id} archived.");
+
+    Ok(())
+}
+
+/// ブロックをアーカイブする (ゴミ箱に入れる)
+/// parent_id のページの content からも取り除く
+pub async fn archive_block(
+    client: &Notion,
+    block_id: &str,
+    space_id: &str,
+    parent_id: &str,
+) -> Result<()> {
+    client
+        .save_transactions(vec![Transaction {
+            id: Uuid::new_v4().to_string(),
+            space_id: space_id.to_string(),
+            debug: Default::default(),
+            operations: vec![
+                Operation {
+                    pointer: OperationPointer {
+                        table: "block".to_string(),
+                        id: block_id.to_string(),
+                        space_id: space_id.to_string(),
+                    },
+                    path: Default::default(),
+                    command: OperationCommand::Update,
+                    args: [("alive".to_string(), json!(false))].into(),
+                },
+                Operation {
+                    pointer: OperationPointer {
+                        table: "block".to_string(),
+                        id: parent_id.to_string(),
+                        space_id: space_id.to_string(),
+                    },
+                    path: ["content".to_string()].into(),
+                    command: OperationCommand::ListRemove,
+                    args: [("id".to_string(), json!(block_id))].into(),
+                },
+            ],
+        }])
+        .await
+        .context("Failed to archive block")?;
+    log::debug!("Block {block_id} archived.");
+
+    Ok(())
+}
+
+/// ブロックのタイトルを変える
+pub async fn rename_block(
+    client: &Notion,
+    block_id: &str,
+    space_id: &str,
+    title: &str,
+) -> Result<()> {
+    set_block_property(client, block_id, space_id, "title", title).await
+}
+
+/// ブロックの文字列のプロパティ (title や caption) を書き換える
+pub async fn set_block_property(
+    client: &Notion,
+    block_id: &str,
+    space_id: &str,
+    name: &str,
+    value: &str,
+) -> Result<()> {
+    let properties = [(name.to_string(), json!([[value.to_string()]]))].into();
+    set_block_properties(client, block_id, space_id, properties)
+        .await
+        .with_context(|| format!("Failed to set {name} of block"))
+}
+
+/// ブロックのプロパティをまとめて書き換える
+/// 値は Notion の形 (`[["text"]]` など) で渡す
+pub async fn set_block_properties(
+    client: &Notion,
+    block_id: &str,
+    space_id: &str,
+    properties: HashMap<String, serde_json::Value>,
+) -> Result<()> {
+    client
+        .save_transactions(vec![Transaction {
+            id: Uuid::new_v4().to_string(),
+            space_id: space_id.to_string(),
+            debug: Default::default(),
+            operations: vec![Operation {
+                pointer: OperationPointer {
+                    table: "block".to_string(),
+                    id: block_id.to_string(),
+                    space_id: space_id.to_string(),
+                },
+                path: ["properties".to_string()].into(),
+                command: OperationCommand::Update,
+                args: properties,
+            }],
+        }])
+        .await
+        .context("Failed to set properties of block")?;
+    log::debug!("Block {block_id} properties updated.");
+
+    Ok(())
+}
+
 /// ファイル名を取得する
 pub fn get_file_stem(path: &Path) -> Result<String> {
     let Some(name) = path
@@ -162,7 +539,7 @@ pub async fn get_signed_put_file(
         .await
         .context("Failed to get metadata")?
         .len();
-    let mime = mime_guess::from_path(&path);
+    let mime = mime_guess::from_path(path);
     let mime = mime.first_or_text_plain().to_string();
     let GetUploadFileUrlResponse {
         signed_get_url,
@@ -282,6 +659,54 @@ pub fn to_dashed_id(id: &str) -> Result<String> {
     Ok(format!("{a}-{b}-{c}-{d}-{e}"))
 }
 
+/// ブロックのプロパティ (`[["text"]]` の形) から文字列を取り出す
+pub fn get_block_property(block: &BlockValue, name: &str) -> Option<String> {
+    block
+        .rest
+        .get("properties")?
+        .get(name)?
+        .get(0)?
+        .get(0)?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// ページの ID か notion.so のページの URL からダッシュつきのページ ID を取り出す
+pub fn to_page_id(text: &str) -> Result<String> {
+    let text = text.trim();
+    // URL なら最後のパスの末尾 32 文字が ID
+    let last = text
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .replace('-', "");
+    let Some(id) = last.get(last.len().saturating_sub(32)..) else {
+        bail!("Invalid page id: {text}");
+    };
+    ensure!(
+        id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()),
+        "Invalid page id: {text}"
+    );
+    to_dashed_id(id)
+}
+
+#[test]
+fn test_to_page_id() {
+    const DASHED: &str = "2131b10c-ebf6-4938-a127-7089ff02dbe4";
+    assert_eq!(to_page_id(DASHED).ok().as_deref(), Some(DASHED));
+    assert_eq!(
+        to_page_id("https://www.notion.so/team/Files-2131b10cebf64938a1277089ff02dbe4?pvs=4")
+            .ok()
+            .as_deref(),
+        Some(DASHED)
+    );
+    assert!(to_page_id("https://www.notion.so/team/Files").is_err());
+}
+
 #[test]
 fn test_to_dashed_id() {
     const ID: &str = "2131b10cebf64938a1277089ff02dbe4";
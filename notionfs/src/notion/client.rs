@@ -90,6 +90,20 @@ impl Notion {
         self.request(Method::POST, "/getSignedFileUrls", req).await
     }
 
+    pub async fn sync_record_values(
+        &self,
+        req: &SyncRecordValuesRequest,
+    ) -> Result<SyncRecordValuesResponse> {
+        self.request(Method::POST, "/syncRecordValues", req).await
+    }
+
+    pub async fn query_collection(
+        &self,
+        req: &QueryCollectionRequest,
+    ) -> Result<QueryCollectionResponse> {
+        self.request(Method::POST, "/queryCollection", req).await
+    }
+
     pub async fn request<R: DeserializeOwned>(
         &self,
         method: Method,
@@ -119,9 +133,6 @@ impl Notion {
     }
 
     pub fn user_agent(&self) -> &str {
-        self.user_agent
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or(DEFAULT_USER_AGENT)
+        self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT)
     }
 }
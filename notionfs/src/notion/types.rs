@@ -36,7 +36,7 @@ pub struct Block {
 #[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct RecordMap {
-    #[serde(rename = "block")]
+    #[serde(rename = "block", default)]
     pub blocks: HashMap<String, Block>,
 }
 
@@ -124,6 +124,7 @@ pub enum OperationCommand {
     Set,
     Update,
     ListAfter,
+    ListRemove,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
@@ -170,3 +171,92 @@ pub struct GetSignedFileUrlsRequest {
 pub struct GetSignedFileUrlsResponse {
     pub signed_urls: Vec<String>,
 }
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordRequest {
+    pub pointer: OperationPointer,
+    pub version: i64,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRecordValuesRequest {
+    pub requests: Vec<RecordRequest>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordValue {
+    pub role: String,
+    /// 権限がなかったり存在しなかったりすると返ってこない
+    pub value: Option<BlockValue>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRecordMap {
+    #[serde(rename = "block", default)]
+    pub blocks: HashMap<String, RecordValue>,
+    #[serde(rename = "collection", default)]
+    pub collections: HashMap<String, RecordValue>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRecordValuesResponse {
+    pub record_map: SyncRecordMap,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionPointer {
+    pub id: String,
+    pub space_id: String,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCollectionReducer {
+    pub r#type: String,
+    pub limit: usize,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCollectionLoader {
+    pub r#type: String,
+    pub reducers: HashMap<String, QueryCollectionReducer>,
+    pub search_query: String,
+    pub user_time_zone: String,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCollectionRequest {
+    pub collection: CollectionPointer,
+    pub collection_view: CollectionPointer,
+    pub loader: QueryCollectionLoader,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReducerResult {
+    #[serde(default)]
+    pub block_ids: Vec<String>,
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCollectionResult {
+    pub reducer_results: HashMap<String, ReducerResult>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCollectionResponse {
+    pub result: QueryCollectionResult,
+    pub record_map: RecordMap,
+}
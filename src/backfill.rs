@@ -0,0 +1,72 @@
+use std::{io::Write, pin::pin};
+
+use anyhow::{bail, Result};
+use futures::TryStreamExt as _;
+use indicatif::ProgressBar;
+
+use crate::{
+    database::FileRow,
+    output::{FileEntry, Printer},
+    plan::{Action, Change},
+    storage::Storage,
+};
+
+/// prefix 以下で size、mime、hash がない行を埋める
+/// 1 行ずつカタログに書くので、途中で止めても次はまだ埋まっていない行から続けられる
+/// 行はカタログから少しずつ読むので、行がいくら多くてもメモリは増えない
+pub async fn backfill(
+    storage: &Storage,
+    prefix: &str,
+    skip_on_failure: bool,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let mut rows = pin!(FileRow::incomplete(&storage.pool, prefix));
+    if storage.dry_run {
+        while let Some(row) = rows.try_next().await? {
+            let reason = format!("version {}, missing {}", row.version, missing(&row));
+            printer
+                .print(&Change::new(Action::Overwrite, &row.file_name, reason).size(row.size))?;
+        }
+        return Ok(());
+    }
+
+    let pb = ProgressBar::new(FileRow::count_incomplete(&storage.pool, prefix).await? as u64);
+    let (mut filled, mut failed) = (0, 0);
+    while let Some(row) = rows.try_next().await? {
+        let name = row.file_name.clone();
+        match storage.backfill(row).await {
+            Ok(row) => {
+                pb.suspend(|| printer.print(&FileEntry::from(row)))?;
+                filled += 1;
+            }
+            Err(e) => {
+                failed += 1;
+                pb.suspend(|| {
+                    log::error!("Failed to backfill {name}");
+                    log::error!("{e:#?}");
+                });
+                if !skip_on_failure {
+                    pb.abandon();
+                    bail!("Aborted by error. Run backfill again to resume.");
+                }
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    log::info!("{filled} filled, {failed} failed");
+    Ok(())
+}
+
+/// 空いているカラム
+fn missing(row: &FileRow) -> String {
+    [
+        ("size", row.size.is_none()),
+        ("mime", row.mime.is_none()),
+        ("hash", row.hash.is_none()),
+    ]
+    .into_iter()
+    .filter_map(|(column, missing)| missing.then_some(column))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
@@ -0,0 +1,396 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use chacha20poly1305::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        OsRng,
+    },
+    XChaCha20Poly1305,
+};
+
+use crate::config::{BucketConfig, Compression};
+
+/// 暗号文の先頭に置く印
+const MAGIC: &[u8; 8] = b"YUKUMO\x00\x01";
+
+/// 暗号化する単位 (平文のバイト数)
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// 暗号文の単位ごとに付く認証タグの大きさ
+const TAG_SIZE: usize = 16;
+
+/// XChaCha20 の 24 バイトの nonce から、STREAM の番号と最後の印の 5 バイトを除いたもの
+const NONCE_PREFIX_SIZE: usize = 19;
+
+const ZSTD: &str = "zstd";
+const XCHACHA20POLY1305: &str = "xchacha20poly1305";
+
+/// バケットの設定に従って、put するときにファイルを圧縮、暗号化し、読むときに戻す
+///
+/// かけた方式は行の encoding に `zstd+xchacha20poly1305` のように順に記録する
+/// size と hash は元のファイルのもので、Notion にあるのは変換したあとのもの
+#[derive(Clone, Default)]
+pub struct Codec {
+    compression: Option<Compression>,
+    key: Option<chacha20poly1305::Key>,
+}
+
+impl Codec {
+    /// 鍵ファイルを読む
+    pub fn open(bucket: &BucketConfig) -> Result<Codec> {
+        let key = bucket
+            .encryption_key_file
+            .as_deref()
+            .map(read_key)
+            .transpose()?;
+        Ok(Codec {
+            compression: bucket.compression,
+            key,
+        })
+    }
+
+    /// put するときにかける方式 (何もしないなら None)
+    pub fn encoding(&self) -> Option<String> {
+        let mut steps = Vec::new();
+        if self.compression == Some(Compression::Zstd) {
+            steps.push(ZSTD);
+        }
+        if self.key.is_some() {
+            steps.push(XCHACHA20POLY1305);
+        }
+        (!steps.is_empty()).then(|| steps.join("+"))
+    }
+
+    /// source を変換して dest に書く
+    pub async fn encode(&self, source: &Path, dest: &Path) -> Result<()> {
+        let codec = self.clone();
+        let (source, dest) = (source.to_path_buf(), dest.to_path_buf());
+        tokio::task::spawn_blocking(move || codec.encode_file(&source, &dest)).await?
+    }
+
+    fn encode_file(&self, source: &Path, dest: &Path) -> Result<()> {
+        let mut input = BufReader::new(
+            File::open(source).with_context(|| format!("Failed to open {source:?}"))?,
+        );
+        let output = BufWriter::new(
+            File::create(dest).with_context(|| format!("Failed to create {dest:?}"))?,
+        );
+        let mut sink = match &self.key {
+            Some(key) => Sink::Encrypted(EncryptWriter::new(key, output)?),
+            None => Sink::Plain(output),
+        };
+        match self.compression {
+            Some(Compression::Zstd) => {
+                let mut encoder = zstd::Encoder::new(sink, 0)?;
+                io::copy(&mut input, &mut encoder)?;
+                sink = encoder.finish()?;
+            }
+            None => {
+                io::copy(&mut input, &mut sink)?;
+            }
+        }
+        sink.finish()?;
+        Ok(())
+    }
+
+    /// encoding で変換された source を戻して writer に書く (書き終えた writer を返す)
+    pub async fn decode<W: Write + Send + 'static>(
+        &self,
+        encoding: &str,
+        source: &Path,
+        writer: W,
+    ) -> Result<W> {
+        let codec = self.clone();
+        let encoding = encoding.to_string();
+        let source = source.to_path_buf();
+        tokio::task::spawn_blocking(move || codec.decode_file(&encoding, &source, writer)).await?
+    }
+
+    fn decode_file<W: Write>(&self, encoding: &str, source: &Path, mut writer: W) -> Result<W> {
+        let file = File::open(source).with_context(|| format!("Failed to open {source:?}"))?;
+        let mut reader: Box<dyn Read> = Box::new(BufReader::new(file));
+        // かけたときと逆の順に戻す
+        for step in encoding.split('+').rev() {
+            reader = match step {
+                ZSTD => Box::new(zstd::Decoder::new(reader)?),
+                XCHACHA20POLY1305 => {
+                    let key = self.key.as_ref().with_context(|| {
+                        "The file is encrypted. Set encryption-key-file of the bucket."
+                    })?;
+                    Box::new(DecryptReader::new(key, reader)?)
+                }
+                step => bail!("Unknown encoding {step}"),
+            };
+        }
+        io::copy(&mut reader, &mut writer).context("Failed to decode file")?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+/// 鍵ファイル (32 バイトを 16 進で書いたもの) を読む
+fn read_key(path: &Path) -> Result<chacha20poly1305::Key> {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => home::home_dir()
+            .context("Failed to get homedir")?
+            .join(rest),
+        Err(_) => path.to_path_buf(),
+    };
+    let text =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read key {path:?}"))?;
+    let bytes = hex::decode(text.trim()).with_context(|| format!("Invalid key in {path:?}"))?;
+    ensure!(
+        bytes.len() == 32,
+        "The key in {path:?} must be 32 bytes (64 hex digits)."
+    );
+    Ok(*chacha20poly1305::Key::from_slice(&bytes))
+}
+
+enum Sink<W: Write> {
+    Plain(W),
+    Encrypted(EncryptWriter<W>),
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(w) => w.write(buf),
+            Sink::Encrypted(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.flush(),
+            Sink::Encrypted(w) => w.flush(),
+        }
+    }
+}
+
+impl<W: Write> Sink<W> {
+    fn finish(self) -> Result<()> {
+        let mut inner = match self {
+            Sink::Plain(w) => w,
+            Sink::Encrypted(w) => w.finish()?,
+        };
+        inner.flush()?;
+        Ok(())
+    }
+}
+
+/// CHUNK_SIZE ずつ暗号化して書く
+/// 最後の単位には印が付くので、末尾を切り詰められても気付ける
+struct EncryptWriter<W: Write> {
+    inner: W,
+    stream: EncryptorBE32<XChaCha20Poly1305>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn new(key: &chacha20poly1305::Key, mut inner: W) -> Result<EncryptWriter<W>> {
+        let mut prefix = [0; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut prefix);
+        inner.write_all(MAGIC)?;
+        inner.write_all(&prefix)?;
+        let stream = EncryptorBE32::new(key, (&prefix).into());
+        Ok(EncryptWriter {
+            inner,
+            stream,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    fn finish(mut self) -> Result<W> {
+        let chunk = self
+            .stream
+            .encrypt_last(self.buf.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt"))?;
+        self.inner.write_all(&chunk)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // 最後の単位は finish で印を付けて書くので、CHUNK_SIZE ちょうどでも残しておく
+        if self.buf.len() == CHUNK_SIZE {
+            let chunk = self
+                .stream
+                .encrypt_next(self.buf.as_slice())
+                .map_err(|_| io::Error::other("Failed to encrypt"))?;
+            self.inner.write_all(&chunk)?;
+            self.buf.clear();
+        }
+        let n = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// EncryptWriter で書いたものを読みながら戻す
+struct DecryptReader<R: Read> {
+    inner: R,
+    stream: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// まだ戻していない暗号文
+    encrypted: Vec<u8>,
+    /// 戻した平文と、そのうち読まれた位置
+    plain: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    fn new(key: &chacha20poly1305::Key, mut inner: R) -> Result<DecryptReader<R>> {
+        let mut header = [0; MAGIC.len() + NONCE_PREFIX_SIZE];
+        inner
+            .read_exact(&mut header)
+            .context("The file is too short to be encrypted")?;
+        ensure!(
+            header.starts_with(MAGIC),
+            "The file is not encrypted by yukumo."
+        );
+        let prefix: [u8; NONCE_PREFIX_SIZE] = header[MAGIC.len()..].try_into()?;
+        Ok(DecryptReader {
+            inner,
+            stream: Some(DecryptorBE32::new(key, (&prefix).into())),
+            encrypted: Vec::new(),
+            plain: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// 次の単位を戻す (最後の単位を戻したら false)
+    fn fill(&mut self) -> io::Result<bool> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(false);
+        };
+        // 1 単位より多く読めれば、それは最後の単位ではない
+        let want = CHUNK_SIZE + TAG_SIZE + 1;
+        while self.encrypted.len() < want {
+            let start = self.encrypted.len();
+            self.encrypted.resize(want, 0);
+            let n = self.inner.read(&mut self.encrypted[start..])?;
+            self.encrypted.truncate(start + n);
+            if n == 0 {
+                break;
+            }
+        }
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "Failed to decrypt");
+        if self.encrypted.len() == want {
+            let rest = self.encrypted.split_off(CHUNK_SIZE + TAG_SIZE);
+            self.plain = stream
+                .decrypt_next(self.encrypted.as_slice())
+                .map_err(invalid)?;
+            self.encrypted = rest;
+        } else {
+            let stream = self.stream.take().unwrap();
+            self.plain = stream
+                .decrypt_last(self.encrypted.as_slice())
+                .map_err(invalid)?;
+            self.encrypted.clear();
+        }
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plain.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.plain.len() - self.position);
+        buf[..n].copy_from_slice(&self.plain[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// 変換の途中に使う一時ファイル (なくなるときに消す)
+/// 中身は平文のファイルとは限らないので、MIME を推測されたときに application/octet-stream になる名前にする
+pub struct Spool(PathBuf);
+
+impl Spool {
+    pub fn new() -> Spool {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        Spool(std::env::temp_dir().join(format!(
+            "yukumo-{}-{}.bin",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        )))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn test_codec_roundtrip() -> Result<()> {
+    use sha2::{Digest, Sha256};
+
+    let source = Spool::new();
+    // 単位の境目をまたぐ大きさと、ちょうど割り切れる大きさ
+    for size in [0, 1, CHUNK_SIZE, CHUNK_SIZE * 3 + 7] {
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        std::fs::write(source.path(), &data)?;
+        for (compression, key) in [
+            (Some(Compression::Zstd), None),
+            (None, Some([7; 32].into())),
+            (Some(Compression::Zstd), Some([7; 32].into())),
+        ] {
+            let codec = Codec { compression, key };
+            let encoding = codec.encoding().unwrap();
+            let encoded = Spool::new();
+            codec.encode(source.path(), encoded.path()).await?;
+            let decoded = codec.decode(&encoding, encoded.path(), Vec::new()).await?;
+            assert_eq!(
+                Sha256::digest(&decoded),
+                Sha256::digest(&data),
+                "{encoding} {size}"
+            );
+        }
+    }
+
+    // 鍵が違うものと、末尾を切り詰めたものは戻せない
+    std::fs::write(source.path(), vec![1; CHUNK_SIZE * 2])?;
+    let codec = Codec {
+        compression: None,
+        key: Some([7; 32].into()),
+    };
+    let encoded = Spool::new();
+    codec.encode(source.path(), encoded.path()).await?;
+    let other = Codec {
+        compression: None,
+        key: Some([8; 32].into()),
+    };
+    assert!(other
+        .decode(XCHACHA20POLY1305, encoded.path(), Vec::new())
+        .await
+        .is_err());
+    let data = std::fs::read(encoded.path())?;
+    std::fs::write(encoded.path(), &data[..data.len() - TAG_SIZE - 1])?;
+    assert!(codec
+        .decode(XCHACHA20POLY1305, encoded.path(), Vec::new())
+        .await
+        .is_err());
+    Ok(())
+}
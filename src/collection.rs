@@ -0,0 +1,362 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike as _, Utc};
+use notionfs::{
+    archive_collection_row, create_collection_row, get_block, get_block_property, get_collection,
+    notion::{client::Notion, types::BlockValue},
+    query_collection, set_block_properties, to_page_id,
+};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::Config,
+    database::FileRow,
+    hash::hash_file,
+    plan::{Action, Change},
+    source::FileSource,
+    storage::{download_file, upload_file},
+};
+
+/// データベースに必要なプロパティ (名前と種類)
+/// 名前はタイトルのプロパティに入れる
+const PROPERTIES: [(&str, &str); 5] = [
+    ("Origin", "text"),
+    ("Size", "number"),
+    ("Hash", "text"),
+    ("Created", "date"),
+    ("File", "file"),
+];
+
+/// Notion のデータベース (コレクション) をカタログにする
+/// 1 ファイルが 1 行になり、ファイル自体は File プロパティに添付する
+///
+/// PostgreSQL を使わずに済む代わりに、バージョン、タグ、ゴミ箱、監査ログはない
+pub struct Collection {
+    pub client: Notion,
+    /// Notion にもデータベースにも書き込まない
+    pub dry_run: bool,
+    file_token: String,
+    space_id: String,
+    collection_id: String,
+    view_id: String,
+    /// PROPERTIES と同じ順のプロパティの ID
+    property_ids: Vec<String>,
+    /// 名前から行を引く表
+    /// 行を探すたびにデータベース全体を問い合わせないよう、最初に探すときに 1 回だけ作る
+    index: Mutex<Option<HashMap<String, FileRow>>>,
+}
+
+impl Collection {
+    /// page は `database.host` の `notion://` のあとの、データベースのページの ID か URL
+    pub async fn open(config: &Config, page: &str, dry_run: bool) -> Result<Collection> {
+        let client = Notion::new(
+            config.notion.token_v2.clone(),
+            config.notion.user_agent.clone(),
+        );
+        let page_id = to_page_id(page)?;
+        let space_id = client
+            .get_page_data(page_id.clone())
+            .await
+            .with_context(|| format!("Failed to get notion page {page}"))?
+            .space_id;
+        let Some(block) = get_block(&client, &page_id, &space_id).await? else {
+            bail!("Notion page {page} is not found.");
+        };
+        let collection_id = block.rest.get("collection_id").and_then(Value::as_str);
+        let view_id = block
+            .rest
+            .get("view_ids")
+            .and_then(|ids| ids.get(0))
+            .and_then(Value::as_str);
+        let (Some(collection_id), Some(view_id)) = (collection_id, view_id) else {
+            bail!("Notion page {page} is not a database.");
+        };
+        log::debug!("collection_id = {collection_id}");
+        log::debug!("view_id = {view_id}");
+
+        let Some(collection) = get_collection(&client, collection_id, &space_id).await? else {
+            bail!("Database of notion page {page} is not found.");
+        };
+        let property_ids = property_ids(&collection)?;
+
+        Ok(Collection {
+            client,
+            dry_run,
+            file_token: config.notion.file_token.clone(),
+            space_id,
+            collection_id: collection_id.to_string(),
+            view_id: view_id.to_string(),
+            property_ids,
+            index: Mutex::new(None),
+        })
+    }
+
+    /// ファイルが添付されている行
+    pub async fn rows(&self) -> Result<Vec<FileRow>> {
+        let blocks = query_collection(
+            &self.client,
+            &self.collection_id,
+            &self.view_id,
+            &self.space_id,
+        )
+        .await?;
+        Ok(blocks
+            .iter()
+            .filter_map(|block| {
+                let row = self.to_row(block);
+                if row.is_none() {
+                    log::debug!("Row {} has no file; skipped.", block.id);
+                }
+                row
+            })
+            .collect())
+    }
+
+    pub async fn find(&self, name: &str) -> Result<Option<FileRow>> {
+        let mut index = self.index.lock().await;
+        if index.is_none() {
+            let mut rows = HashMap::new();
+            for row in self.rows().await? {
+                // 同じ名前の行があれば最初のものを使う
+                rows.entry(row.file_name.clone()).or_insert(row);
+            }
+            *index = Some(rows);
+        }
+        Ok(index.as_ref().and_then(|rows| rows.get(name)).cloned())
+    }
+
+    pub async fn find_one(&self, name: &str) -> Result<FileRow> {
+        self.find(name)
+            .await?
+            .with_context(|| format!("file_name ({name}) is not found."))
+    }
+
+    /// アップロードして行を追加する
+    /// overwrite なら同じ名前の行のファイルを差し替える (古いファイルはページの履歴に残る)
+    /// 新しく作った行はアップロードに失敗したらアーカイブする
+    pub async fn put(&self, source: &Path, name: &str, overwrite: bool) -> Result<FileRow> {
+        ensure!(!self.dry_run, "Refused to write in dry-run mode.");
+        let (block_id, created) = match self.find(name).await? {
+            Some(_) if !overwrite => bail!("file_name ({name}) is already exists."),
+            Some(row) => (row.block_id, false),
+            None => {
+                let block_id =
+                    create_collection_row(&self.client, &self.space_id, &self.collection_id, name)
+                        .await?;
+                (block_id, true)
+            }
+        };
+
+        let result = self.attach(source, name, block_id.clone()).await;
+        match &result {
+            Ok(row) => {
+                if let Some(rows) = self.index.lock().await.as_mut() {
+                    rows.insert(name.to_string(), row.clone());
+                }
+            }
+            Err(_) if created => {
+                if let Err(e) =
+                    archive_collection_row(&self.client, &block_id, &self.space_id).await
+                {
+                    log::warn!("Failed to archive empty row {block_id}: {e:#}");
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// ファイルを行にアップロードしてプロパティを書く
+    async fn attach(&self, source: &Path, name: &str, block_id: String) -> Result<FileRow> {
+        let hash = hash_file(source).await?;
+        let (url, mime, content_length) =
+            upload_file(&self.client, source, name, &block_id, &self.space_id).await?;
+        let row = FileRow {
+            file_url: url,
+            space_id: self.space_id.clone(),
+            block_id,
+            file_name: name.to_string(),
+            origin_file_path: source
+                .canonicalize()
+                .unwrap_or_else(|_| source.to_path_buf())
+                .to_string_lossy()
+                .to_string(),
+            // Notion の日時は分までしか持てない
+            created_at: Utc::now()
+                .naive_utc()
+                .with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+                .context("Failed to truncate created_at")?,
+            size: Some(content_length as i64),
+            mime: Some(mime),
+            hash: Some(hash),
+            version: 1,
+            // Notion のデータベースには元ファイルの状態を置く場所がない
+            source: FileSource::default(),
+            encoding: None,
+            deleted_at: None,
+            expires_at: None,
+        };
+        set_block_properties(
+            &self.client,
+            &row.block_id,
+            &self.space_id,
+            self.to_properties(&row),
+        )
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn plan_put(
+        &self,
+        source: &Path,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<Vec<Change>> {
+        let size = source
+            .metadata()
+            .with_context(|| format!("Failed to read {source:?}"))?
+            .len() as i64;
+        let change = match (self.find(name).await?.is_some(), overwrite) {
+            (false, _) => Change::new(Action::Create, name, "new file"),
+            (true, true) => Change::new(Action::Overwrite, name, "already exists"),
+            (true, false) => Change::new(Action::Skip, name, "already exists (use --overwrite)"),
+        };
+        Ok(vec![change.path(source.to_string_lossy()).size(Some(size))])
+    }
+
+    /// ファイルをダウンロードして output に保存する
+    pub async fn download(&self, row: &FileRow, output: &Path) -> Result<()> {
+        download_file(&self.client, &self.file_token, row, output).await
+    }
+
+    fn to_properties(&self, row: &FileRow) -> HashMap<String, Value> {
+        let [origin, size, hash, created_at, file] = self.ids();
+        let text = |text: &str| json!([[text]]);
+        let mut properties: HashMap<_, _> = [
+            ("title".to_string(), text(&row.file_name)),
+            (origin.to_string(), text(&row.origin_file_path)),
+            (
+                created_at.to_string(),
+                json!([["‣", [["d", {
+                    "type": "datetime",
+                    "start_date": row.created_at.format("%Y-%m-%d").to_string(),
+                    "start_time": row.created_at.format("%H:%M").to_string(),
+                    "time_zone": "UTC",
+                }]]]]),
+            ),
+            (
+                file.to_string(),
+                json!([[row.file_name, [["a", row.file_url]]]]),
+            ),
+        ]
+        .into();
+        if let Some(value) = row.size {
+            properties.insert(size.to_string(), text(&value.to_string()));
+        }
+        if let Some(value) = &row.hash {
+            properties.insert(hash.to_string(), text(value));
+        }
+        properties
+    }
+
+    /// File プロパティがない行は None
+    fn to_row(&self, block: &BlockValue) -> Option<FileRow> {
+        let [origin, size, hash, created_at, file] = self.ids();
+        let properties = block.rest.get("properties")?;
+        // [["name", [["a", url]]]]
+        let file_url = properties
+            .get(file)?
+            .get(0)?
+            .get(1)?
+            .get(0)?
+            .get(1)?
+            .as_str()?
+            .to_string();
+        let created_at = properties
+            .get(created_at)
+            .and_then(parse_date)
+            .or_else(|| {
+                // 日時がなければ行を作った日時にする
+                let millis = block.rest.get("created_time")?.as_i64()?;
+                NaiveDateTime::from_timestamp_millis(millis)
+            })
+            .unwrap_or_default();
+        Some(FileRow {
+            file_name: get_block_property(block, "title").unwrap_or_default(),
+            file_url,
+            space_id: self.space_id.clone(),
+            block_id: block.id.clone(),
+            origin_file_path: get_block_property(block, origin).unwrap_or_default(),
+            created_at,
+            size: get_block_property(block, size).and_then(|size| size.parse().ok()),
+            mime: None,
+            hash: get_block_property(block, hash),
+            version: 1,
+            source: FileSource::default(),
+            encoding: None,
+            deleted_at: None,
+            expires_at: None,
+        })
+    }
+
+    fn ids(&self) -> [&str; 5] {
+        let id = |i: usize| self.property_ids[i].as_str();
+        [id(0), id(1), id(2), id(3), id(4)]
+    }
+}
+
+/// スキーマから PROPERTIES の ID を探す
+fn property_ids(collection: &BlockValue) -> Result<Vec<String>> {
+    let schema = collection
+        .rest
+        .get("schema")
+        .and_then(Value::as_object)
+        .context("Failed to get schema of database")?;
+    let mut ids = Vec::new();
+    let mut missing = Vec::new();
+    for (name, kind) in PROPERTIES {
+        let id = schema.iter().find_map(|(id, property)| {
+            (property.get("name")? == name && property.get("type")? == kind).then_some(id)
+        });
+        match id {
+            Some(id) => ids.push(id.clone()),
+            None => missing.push(format!("{name} ({kind})")),
+        }
+    }
+    ensure!(
+        missing.is_empty(),
+        "The database is missing properties: {}. Add them in Notion.",
+        missing.join(", ")
+    );
+    Ok(ids)
+}
+
+/// [["‣", [["d", {"start_date": "2023-10-01", "start_time": "09:08"}]]]]
+fn parse_date(value: &Value) -> Option<NaiveDateTime> {
+    let date = value.get(0)?.get(1)?.get(0)?.get(1)?;
+    let start_date = date.get("start_date")?.as_str()?;
+    let start_date = NaiveDate::parse_from_str(start_date, "%Y-%m-%d").ok()?;
+    let start_time = match date.get("start_time").and_then(Value::as_str) {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M").ok()?,
+        None => NaiveTime::MIN,
+    };
+    Some(start_date.and_time(start_time))
+}
+
+#[test]
+fn test_parse_date() {
+    let value = json!([["‣", [["d", {
+        "type": "datetime",
+        "start_date": "2023-10-01",
+        "start_time": "09:08",
+        "time_zone": "UTC",
+    }]]]]);
+    assert_eq!(
+        parse_date(&value),
+        NaiveDate::from_ymd_opt(2023, 10, 1).and_then(|d| d.and_hms_opt(9, 8, 0))
+    );
+    assert_eq!(parse_date(&json!([["2023-10-01"]])), None);
+}
@@ -1,34 +1,345 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, PartialEq, Clone, Debug)]
+use crate::parse::parse_duration;
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub database: DatabaseConfig,
     pub notion: NotionConfig,
+    #[serde(default)]
+    pub versions: VersionsConfig,
+    #[serde(default)]
+    pub tags: TagsConfig,
+    /// 名前ごとに別のページに置くバケット
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub buckets: BTreeMap<String, BucketConfig>,
+    /// 名前の前置きごとの保持の決まり
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retention: Vec<RetentionConfig>,
 }
 
+/// --bucket を指定しないときのバケット
+pub const DEFAULT_BUCKET: &str = "default";
+
 impl Config {
     pub fn open(path: &Path) -> Result<Config> {
         let text = std::fs::read_to_string(path).context("Failed to read file")?;
-        let config = toml::from_str(&text).context("Failed to parse config")?;
+        let config: Config = toml::from_str(&text).context("Failed to parse config")?;
+        for rule in &config.retention {
+            rule.delete_after()
+                .with_context(|| format!("Invalid retention for {:?}", rule.prefix))?;
+        }
         Ok(config)
     }
+
+    /// 本人だけが読めるパーミッションで書き出す
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self).context("Failed to serialize config")?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // 既にあるファイルは mode が効かないので明示的に絞る
+            if path.exists() {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                    .context("Failed to set permissions")?;
+            }
+        }
+        let mut file = options.open(path).context("Failed to open file")?;
+        file.write_all(text.as_bytes())
+            .context("Failed to write file")?;
+        Ok(())
+    }
+
+    /// バケットの設定
+    /// default バケットは、設定がなければ notion.page-id に置く
+    pub fn bucket(&self, name: &str) -> Result<BucketConfig> {
+        match self.buckets.get(name) {
+            Some(bucket) => Ok(bucket.clone()),
+            None if name == DEFAULT_BUCKET => Ok(BucketConfig::new(self.notion.page_id.clone())),
+            None => bail!("Bucket {name} is not defined. Add [buckets.{name}] to the config."),
+        }
+    }
+
+    /// name に当てはまる保持の決まり (前置きがいちばん長いもの)
+    pub fn retention(&self, name: &str) -> Option<&RetentionConfig> {
+        self.retention
+            .iter()
+            .filter(|rule| name.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len())
+    }
+
+    /// name の古いバージョンを残す数 (決まりになければ versions.keep)
+    pub fn keep_versions(&self, name: &str) -> Option<u32> {
+        self.retention(name)
+            .and_then(|rule| rule.keep_versions)
+            .or(self.versions.keep)
+    }
 }
 
-#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct DatabaseConfig {
+    /// `postgres://`、`sqlite://` か `notion://` の URL
     pub host: String,
+    /// 接続の数の上限 (指定しなければ 5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// 空いている接続を待つ秒数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acquire_timeout: Option<u64>,
+    /// 使われていない接続を閉じるまでの秒数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    /// PostgreSQL の sslmode (URL の `?sslmode=` より優先する)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sslmode: Option<SslMode>,
+    /// サーバーの証明書を検証する CA 証明書
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_root_cert: Option<PathBuf>,
+    /// クライアント証明書
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_client_cert: Option<PathBuf>,
+    /// クライアント証明書の秘密鍵
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_client_key: Option<PathBuf>,
+    /// 接続したときにマイグレーションを走らせる
+    /// スキーマを変えられないユーザーなら false にして、`yukumo migrate` を別のユーザーで実行する
+    #[serde(
+        default = "default_run_migrations",
+        skip_serializing_if = "Clone::clone"
+    )]
+    pub run_migrations: bool,
+}
+
+impl DatabaseConfig {
+    pub fn new(host: String) -> DatabaseConfig {
+        DatabaseConfig {
+            host,
+            max_connections: None,
+            acquire_timeout: None,
+            idle_timeout: None,
+            sslmode: None,
+            ssl_root_cert: None,
+            ssl_client_cert: None,
+            ssl_client_key: None,
+            run_migrations: default_run_migrations(),
+        }
+    }
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+/// PostgreSQL の sslmode
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
 }
 
-#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct NotionConfig {
     pub token_v2: String,
     pub file_token: String,
     pub page_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user_agent: Option<String>,
 }
+
+/// 書き間違えた設定で平文のまま置かれることがないように、知らないキーはエラーにする
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BucketConfig {
+    /// ファイルを置くページの ID か URL (ほかのスペースのページでもよい)
+    pub page_id: String,
+    /// put で --prefix を指定しないときに名前の前に付ける
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// put するときに圧縮する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// put するときにこの鍵 (32 バイトを 16 進で書いたファイル) で暗号化する
+    /// 読むときも同じ鍵がいるので、鍵をなくすと取り出せなくなる
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption_key_file: Option<PathBuf>,
+}
+
+impl BucketConfig {
+    pub fn new(page_id: String) -> BucketConfig {
+        BucketConfig {
+            page_id,
+            prefix: None,
+            compression: None,
+            encryption_key_file: None,
+        }
+    }
+}
+
+/// 圧縮の方式
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    Zstd,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct VersionsConfig {
+    /// 上書きしたときに残す古いバージョンの数 (指定しなければすべて残す)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetentionConfig {
+    /// この前置きで始まる名前に当てはめる (空文字列ならすべて)
+    #[serde(default)]
+    pub prefix: String,
+    /// versions.keep の代わりに残す古いバージョンの数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_versions: Option<u32>,
+    /// put してからこの期間 (`30d` など) が過ぎたら gc --expired で消す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_after: Option<String>,
+}
+
+impl RetentionConfig {
+    pub fn delete_after(&self) -> Result<Option<Duration>> {
+        self.delete_after.as_deref().map(parse_duration).transpose()
+    }
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagsConfig {
+    /// タグを Notion のブロックのキャプションにも書く
+    #[serde(default)]
+    pub caption: bool,
+}
+
+#[test]
+fn test_database_config_defaults() {
+    let config: DatabaseConfig = toml::from_str(r#"host = "postgres://localhost/yukumo""#).unwrap();
+    assert_eq!(
+        config,
+        DatabaseConfig::new("postgres://localhost/yukumo".to_string())
+    );
+
+    let config: DatabaseConfig = toml::from_str(
+        r#"
+        host = "postgres://db/yukumo"
+        sslmode = "verify-full"
+        run-migrations = false
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.sslmode, Some(SslMode::VerifyFull));
+    assert!(!config.run_migrations);
+}
+
+#[test]
+fn test_bucket() {
+    let config: Config = toml::from_str(
+        r#"
+        [database]
+        host = "postgres://localhost/yukumo"
+
+        [notion]
+        token-v2 = ""
+        file-token = ""
+        page-id = "main"
+
+        [buckets.photos]
+        page-id = "photos"
+        prefix = "camera/"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.bucket(DEFAULT_BUCKET).unwrap().page_id, "main");
+    let photos = config.bucket("photos").unwrap();
+    assert_eq!(photos.page_id, "photos");
+    assert_eq!(photos.prefix.as_deref(), Some("camera/"));
+    assert!(config.bucket("music").is_err());
+
+    let result = toml::from_str::<BucketConfig>(
+        r#"
+        page-id = "photos"
+        encryption = "age"
+        "#,
+    );
+    assert!(result.is_err());
+    let secret: BucketConfig = toml::from_str(
+        r#"
+        page-id = "secret"
+        compression = "zstd"
+        encryption-key-file = "~/.yukumo/secret.key"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(secret.compression, Some(Compression::Zstd));
+    assert!(secret.encryption_key_file.is_some());
+}
+
+#[test]
+fn test_example_config_roundtrip() {
+    let config: Config = toml::from_str(include_str!("../Yukumo.toml.example")).unwrap();
+    let text = toml::to_string_pretty(&config).unwrap();
+    assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+}
+
+#[test]
+fn test_retention() {
+    let config: Config = toml::from_str(
+        r#"
+        [database]
+        host = "postgres://localhost/yukumo"
+
+        [notion]
+        token-v2 = ""
+        file-token = ""
+        page-id = ""
+
+        [versions]
+        keep = 10
+
+        [[retention]]
+        prefix = "ci/"
+        keep-versions = 3
+        delete-after = "30d"
+
+        [[retention]]
+        prefix = "ci/nightly/"
+        delete-after = "7d"
+        "#,
+    )
+    .unwrap();
+    let nightly = config.retention("ci/nightly/a.zip").unwrap();
+    assert_eq!(
+        nightly.delete_after().unwrap(),
+        Some(Duration::from_secs(7 * 24 * 60 * 60))
+    );
+    assert_eq!(config.keep_versions("ci/nightly/a.zip"), Some(10));
+    assert_eq!(config.keep_versions("ci/a.zip"), Some(3));
+    assert!(config.retention("docs/a.pdf").is_none());
+    assert_eq!(config.keep_versions("docs/a.pdf"), Some(10));
+}
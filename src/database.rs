@@ -1,11 +1,164 @@
-use anyhow::{Context as _, Result};
-use chrono::NaiveDateTime;
+use std::{cmp::Ordering, collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use async_stream::try_stream;
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
+use futures::{Stream, TryStreamExt as _};
+#[cfg(feature = "sqlite")]
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
 use sqlx::{
-    postgres::{PgPool, PgPoolOptions},
+    database::HasArguments,
+    migrate::Migrator,
+    pool::PoolOptions,
+    postgres::{PgConnectOptions, PgPool, PgSslMode},
     prelude::*,
+    Database, QueryBuilder,
 };
 
-#[derive(FromRow, Debug)]
+use crate::{
+    config::{DatabaseConfig, SslMode, DEFAULT_BUCKET},
+    source::FileSource,
+    tag::Tag,
+};
+
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+#[cfg(feature = "sqlite")]
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// カタログを置くデータベースへの接続と、操作するバケット
+/// FileRow の読み書きはすべてこのバケットの中で行う
+#[derive(Clone, Debug)]
+pub struct Pool {
+    backend: Backend,
+    bucket: String,
+}
+
+/// `database.host` のスキームで選ぶ (`sqlite://` なら SQLite、それ以外は PostgreSQL)
+#[derive(Clone, Debug)]
+enum Backend {
+    Postgres(PgPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqlitePool),
+}
+
+/// バックエンドごとに同じ処理を展開する
+/// SQL はどちらでも動くように書く
+macro_rules! on_pool {
+    ($pool:expr, |$conn:ident| $body:expr) => {
+        match &$pool.backend {
+            Backend::Postgres($conn) => $body,
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite($conn) => $body,
+        }
+    };
+}
+
+impl Pool {
+    fn new(backend: Backend) -> Pool {
+        Pool {
+            backend,
+            bucket: DEFAULT_BUCKET.to_string(),
+        }
+    }
+
+    /// 同じ接続で別のバケットを操作する
+    pub fn with_bucket(mut self, bucket: &str) -> Pool {
+        self.bucket = bucket.to_string();
+        self
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// カタログに行があるバケットの一覧
+    pub async fn buckets(&self) -> Result<Vec<String>> {
+        let buckets = on_pool!(self, |pool| sqlx::query_scalar(
+            "SELECT DISTINCT bucket FROM files ORDER BY bucket"
+        )
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select buckets")?;
+        Ok(buckets)
+    }
+
+    pub async fn close(&self) {
+        on_pool!(self, |pool| pool.close().await)
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        match self.backend {
+            Backend::Postgres(_) => &POSTGRES_MIGRATOR,
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(_) => &SQLITE_MIGRATOR,
+        }
+    }
+
+    /// 名前をバイト順で並べる照合順序
+    /// PostgreSQL の既定はロケールに従うので、Notion のカタログや sync の突き合わせと順番が変わることがある
+    fn name_collation(&self) -> &'static str {
+        match self.backend {
+            Backend::Postgres(_) => r#" COLLATE "C""#,
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(_) => "",
+        }
+    }
+
+    /// 正規表現でマッチする演算子
+    fn regex_operator(&self) -> &'static str {
+        match self.backend {
+            Backend::Postgres(_) => "~",
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(_) => "REGEXP",
+        }
+    }
+}
+
+/// やり直しても通らない書き込み (名前を変えるか --overwrite を付けるまで同じ結果になる)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Rejected {
+    /// 名前が既に使われている
+    Exists(String),
+    /// 名前がゴミ箱にある
+    Trashed(String),
+    /// 名前として使えない
+    InvalidName(String),
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejected::Exists(name) => write!(f, "file_name ({name}) is already exists."),
+            Rejected::Trashed(name) => {
+                write!(
+                    f,
+                    "file_name ({name}) is in the trash. Restore it or use --overwrite."
+                )
+            }
+            Rejected::InvalidName(name) => write!(f, "file_name ({name:?}) is not a valid name."),
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+impl Rejected {
+    /// e の原因のどこかが Rejected ならそれを返す
+    pub fn find(e: &anyhow::Error) -> Option<&Rejected> {
+        e.chain().find_map(|cause| cause.downcast_ref())
+    }
+
+    /// 空の名前と NUL を含む名前は登録できない
+    pub fn check_name(name: &str) -> Result<(), Rejected> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(Rejected::InvalidName(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(FromRow, Clone, Debug)]
 pub struct FileRow {
     /// ファイル名
     pub file_name: String,
@@ -19,42 +172,677 @@ pub struct FileRow {
     pub origin_file_path: String,
     /// 作成日時
     pub created_at: NaiveDateTime,
+    /// ファイルサイズ (バイト)
+    pub size: Option<i64>,
+    /// MIME タイプ
+    pub mime: Option<String>,
+    /// SHA-256 のハッシュ値
+    pub hash: Option<String>,
+    /// バージョン (1 から始まる)
+    pub version: i32,
+    /// put したときの元ファイルの状態
+    #[sqlx(flatten)]
+    pub source: FileSource,
+    /// put したときにかけた圧縮と暗号化 (codec.rs、かけていなければ None)
+    pub encoding: Option<String>,
+    /// ゴミ箱に入れた日時 (file_versions にはない)
+    #[sqlx(default)]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// この日時を過ぎたら gc --expired で消す (file_versions にはない)
+    #[sqlx(default)]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// 全文検索で見つかったファイル
+#[derive(FromRow, Debug)]
+pub struct SearchRow {
+    #[sqlx(flatten)]
+    pub file: FileRow,
+    /// 関連度 (大きいほど関連が強い、バックエンドごとに尺度が違う)
+    pub rank: f64,
+}
+
+/// files と file_versions で共通のカラム
+const FILE_COLUMNS: &str = "file_name, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash, version, \
+     source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink, encoding";
+
+/// 名前のパターン
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Pattern {
+    /// `*` は `/` をまたがず、`**` はまたぐ
+    Glob(String),
+    /// PostgreSQL の POSIX 正規表現
+    Regex(String),
+}
+
+impl Pattern {
+    fn to_regex(&self) -> String {
+        match self {
+            Pattern::Glob(glob) => glob_to_regex(glob),
+            Pattern::Regex(regex) => regex.clone(),
+        }
+    }
+
+    fn compile(&self) -> Result<regex::Regex> {
+        let regex = self.to_regex();
+        regex::Regex::new(&regex).with_context(|| format!("Invalid pattern: {regex}"))
+    }
+}
+
+/// 並び替えのキー
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Date,
+    Size,
+}
+
+impl SortKey {
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::Name => "file_name",
+            SortKey::Date => "created_at",
+            SortKey::Size => "size",
+        }
+    }
+}
+
+/// ファイルの検索条件
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct FileQuery {
+    /// 名前の前方一致
+    pub prefix: String,
+    /// 名前のパターン
+    pub name: Option<Pattern>,
+    /// 元ファイルのパスのパターン
+    pub origin: Option<Pattern>,
+    /// この日時以降に作成されたもの
+    pub since: Option<NaiveDateTime>,
+    /// この日時より前に作成されたもの
+    pub until: Option<NaiveDateTime>,
+    /// 最小サイズ (バイト)
+    pub min_size: Option<i64>,
+    /// 最大サイズ (バイト)
+    pub max_size: Option<i64>,
+    pub sort: SortKey,
+    pub reverse: bool,
+    /// この名前の行より後ろ (並び順で) だけ
+    /// 名前順以外では、並び替えの値を取るためにこの名前の行がカタログにないといけない
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+    /// すべて付いているもの
+    pub tags: Vec<Tag>,
+}
+
+impl FileQuery {
+    /// cursor は名前順以外で after の行 (並び替えの値をここから取る)
+    fn build<DB>(
+        &self,
+        bucket: &str,
+        regex: &str,
+        collate: &str,
+        cursor: Option<&FileRow>,
+    ) -> QueryBuilder<'static, DB>
+    where
+        DB: Database,
+        <DB as HasArguments<'static>>::Arguments: Default,
+        String: Encode<'static, DB> + Type<DB>,
+        i64: Encode<'static, DB> + Type<DB>,
+        NaiveDateTime: Encode<'static, DB> + Type<DB>,
+    {
+        let mut builder = QueryBuilder::new("SELECT * FROM files WHERE bucket = ");
+        builder
+            .push_bind(bucket.to_string())
+            .push(" AND deleted_at IS NULL AND file_name LIKE ")
+            .push_bind(format!("{}%", escape_like(&self.prefix)))
+            .push(r" ESCAPE '\'");
+        if let Some(name) = &self.name {
+            builder
+                .push(format_args!(" AND file_name {regex} "))
+                .push_bind(name.to_regex());
+        }
+        if let Some(origin) = &self.origin {
+            builder
+                .push(format_args!(" AND origin_file_path {regex} "))
+                .push_bind(origin.to_regex());
+        }
+        if let Some(since) = self.since {
+            builder.push(" AND created_at >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            builder.push(" AND created_at < ").push_bind(until);
+        }
+        if let Some(min_size) = self.min_size {
+            builder.push(" AND size >= ").push_bind(min_size);
+        }
+        if let Some(max_size) = self.max_size {
+            builder.push(" AND size <= ").push_bind(max_size);
+        }
+        for tag in &self.tags {
+            match tag {
+                Tag::Label(label) => {
+                    builder
+                        .push(" AND EXISTS (SELECT 1 FROM file_tags t WHERE t.bucket = files.bucket AND t.file_name = files.file_name AND t.tag = ")
+                        .push_bind(label.clone())
+                        .push(")");
+                }
+                Tag::Meta(key, value) => {
+                    builder
+                        .push(" AND EXISTS (SELECT 1 FROM file_meta m WHERE m.bucket = files.bucket AND m.file_name = files.file_name AND m.key = ")
+                        .push_bind(key.clone())
+                        .push(" AND m.value = ")
+                        .push_bind(value.clone())
+                        .push(")");
+                }
+            }
+        }
+        let order = if self.reverse { "DESC" } else { "ASC" };
+        if let Some(after) = &self.after {
+            // ORDER BY と同じ並びで after より後ろ (NULL は向きによらず最後)
+            let op = if self.reverse { "<" } else { ">" };
+            let name = |builder: &mut QueryBuilder<'static, DB>| {
+                builder
+                    .push(format_args!("file_name{collate} {op} "))
+                    .push_bind(after.clone());
+            };
+            match (self.sort, cursor) {
+                (SortKey::Name, _) | (_, None) => {
+                    builder.push(" AND ");
+                    name(&mut builder);
+                }
+                (SortKey::Date, Some(cursor)) => {
+                    builder
+                        .push(format_args!(" AND (created_at {op} "))
+                        .push_bind(cursor.created_at)
+                        .push(" OR (created_at = ")
+                        .push_bind(cursor.created_at)
+                        .push(" AND ");
+                    name(&mut builder);
+                    builder.push("))");
+                }
+                (SortKey::Size, Some(FileRow { size: None, .. })) => {
+                    builder.push(" AND size IS NULL AND ");
+                    name(&mut builder);
+                }
+                (
+                    SortKey::Size,
+                    Some(FileRow {
+                        size: Some(size), ..
+                    }),
+                ) => {
+                    builder
+                        .push(format_args!(" AND (size {op} "))
+                        .push_bind(*size)
+                        .push(" OR (size = ")
+                        .push_bind(*size)
+                        .push(" AND ");
+                    name(&mut builder);
+                    builder.push(") OR size IS NULL)");
+                }
+            }
+        }
+        match self.sort {
+            SortKey::Name => builder.push(format_args!(" ORDER BY file_name{collate} {order}")),
+            sort => builder.push(format_args!(
+                " ORDER BY {} {order} NULLS LAST, file_name{collate} {order}",
+                sort.column()
+            )),
+        };
+        if let Some(limit) = self.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        builder
+    }
+}
+
+impl FileQuery {
+    /// SQL を使えないカタログのために、取得した行をしぼりこんで並び替える
+    /// タグではしぼりこめない
+    pub fn apply(&self, rows: Vec<FileRow>) -> Result<Vec<FileRow>> {
+        ensure!(
+            self.tags.is_empty(),
+            "Filtering by tags is not supported by this catalog."
+        );
+        let name = self.name.as_ref().map(Pattern::compile).transpose()?;
+        let origin = self.origin.as_ref().map(Pattern::compile).transpose()?;
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .filter(|row| {
+                row.deleted_at.is_none()
+                    && row.file_name.starts_with(&self.prefix)
+                    && name.as_ref().is_none_or(|r| r.is_match(&row.file_name))
+                    && origin
+                        .as_ref()
+                        .is_none_or(|r| r.is_match(&row.origin_file_path))
+                    && self.since.is_none_or(|since| row.created_at >= since)
+                    && self.until.is_none_or(|until| row.created_at < until)
+                    && self.min_size.is_none_or(|min| row.size >= Some(min))
+                    && self
+                        .max_size
+                        .is_none_or(|max| row.size.is_some_and(|size| size <= max))
+            })
+            .collect();
+
+        let order = |ordering: Ordering| {
+            if self.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+        rows.sort_by(|a, b| {
+            let key = match self.sort {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Date => order(a.created_at.cmp(&b.created_at)),
+                // SQL の NULLS LAST と同じく、サイズがないものは常に後ろ
+                SortKey::Size => match (a.size, b.size) {
+                    (Some(x), Some(y)) => order(x.cmp(&y)),
+                    (x, y) => x.is_none().cmp(&y.is_none()),
+                },
+            };
+            key.then_with(|| order(a.file_name.cmp(&b.file_name)))
+        });
+        if let Some(after) = &self.after {
+            match rows.iter().position(|row| &row.file_name == after) {
+                Some(i) => {
+                    rows.drain(..=i);
+                }
+                None if self.sort == SortKey::Name => {
+                    rows.retain(|row| (row.file_name > *after) != self.reverse);
+                }
+                None => bail!("file_name ({after}) given to --after is not found."),
+            }
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit.max(0) as usize);
+        }
+        Ok(rows)
+    }
+}
+
+/// トランザクションの中でタグを付ける
+macro_rules! insert_tags {
+    ($tx:ident, $bucket:expr, $file_name:expr, $tags:expr) => {
+        for tag in $tags {
+            let query = match tag {
+                Tag::Label(label) => sqlx::query(
+                    r#"INSERT INTO file_tags (bucket, file_name, tag) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"#,
+                )
+                .bind($bucket)
+                .bind($file_name)
+                .bind(label),
+                Tag::Meta(key, value) => sqlx::query(
+                    r#"
+                INSERT INTO file_meta (bucket, file_name, key, value) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (bucket, file_name, key) DO UPDATE SET value = EXCLUDED.value
+                "#,
+                )
+                .bind($bucket)
+                .bind($file_name)
+                .bind(key)
+                .bind(value),
+            };
+            query
+                .execute(&mut *$tx)
+                .await
+                .context("Failed to insert tag")?;
+        }
+    };
 }
 
 impl FileRow {
-    pub async fn query(pool: &PgPool, prefix: &str) -> Result<Vec<FileRow>> {
-        let files = sqlx::query_as(r#"SELECT * FROM files WHERE starts_with(file_name, $1)"#)
-            .bind(prefix)
-            .fetch_all(pool)
-            .await
-            .context("Failed to select files")?;
-        Ok(files)
+    pub async fn query(pool: &Pool, query: &FileQuery) -> Result<Vec<FileRow>> {
+        FileRow::stream(pool, query).try_collect().await
     }
 
-    pub async fn find_one(pool: &PgPool, file_name: &str) -> Result<FileRow> {
-        let row = sqlx::query_as(r#"SELECT * FROM files WHERE file_name = $1"#)
-            .bind(file_name)
-            .fetch_one(pool)
-            .await
-            .context("Failed to get file")?;
-        Ok(row)
+    /// 条件に合う行を 1 行ずつ返す (すべての行をメモリに載せない)
+    pub fn stream<'a>(
+        pool: &'a Pool,
+        query: &'a FileQuery,
+    ) -> impl Stream<Item = Result<FileRow>> + 'a {
+        try_stream! {
+            // after の行の値と比べる (行ごとに after の行を引き直さない)
+            let mut cursor = None;
+            if let Some(after) = query.after.as_deref().filter(|_| query.sort != SortKey::Name) {
+                match FileRow::find_any(pool, after).await? {
+                    Some(row) => cursor = Some(row),
+                    None => Err(anyhow!("file_name ({after}) given to --after is not found."))?,
+                }
+            }
+            let regex = pool.regex_operator();
+            let collate = pool.name_collation();
+            let bucket = pool.bucket.clone();
+            match &pool.backend {
+                Backend::Postgres(pool) => {
+                    let mut builder = query.build(&bucket, regex, collate, cursor.as_ref());
+                    let mut rows = builder.build_query_as().fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select files")? {
+                        yield row;
+                    }
+                }
+                #[cfg(feature = "sqlite")]
+                Backend::Sqlite(pool) => {
+                    let mut builder = query.build(&bucket, regex, collate, cursor.as_ref());
+                    let mut rows = builder.build_query_as().fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select files")? {
+                        yield row;
+                    }
+                }
+            }
+        }
     }
 
-    pub async fn is_exists(pool: &PgPool, file_name: &str) -> Result<bool> {
-        let (exists,): (bool,) =
-            sqlx::query_as(r#"SELECT EXISTS (SELECT * FROM files WHERE file_name = $1)"#)
-                .bind(file_name)
-                .fetch_one(pool)
+    /// 名前、元ファイルのパス、タグとメタデータを全文検索する (関連度の高い順)
+    /// lexemes は小文字の英数字で、すべてを語の前方一致で含むものを返す
+    pub async fn search(pool: &Pool, lexemes: &[String], limit: i64) -> Result<Vec<SearchRow>> {
+        let bucket = &pool.bucket;
+        let rows = match &pool.backend {
+            Backend::Postgres(pool) => {
+                sqlx::query_as(
+                    r#"
+            SELECT files.*, ts_rank(search, query)::DOUBLE PRECISION AS rank
+            FROM files, to_tsquery('simple', $1) query
+            WHERE bucket = $3 AND deleted_at IS NULL AND search @@ query
+            ORDER BY rank DESC, file_name
+            LIMIT $2
+            "#,
+                )
+                .bind(
+                    lexemes
+                        .iter()
+                        .map(|lexeme| format!("'{lexeme}':*"))
+                        .collect::<Vec<_>>()
+                        .join(" & "),
+                )
+                .bind(limit)
+                .bind(bucket)
+                .fetch_all(pool)
                 .await
-                .context("Failed to count files")?;
+            }
+            // bm25 は小さいほど関連が強いので符号を反転する (重みは名前 3、パス 1、タグ 2)
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(pool) => {
+                sqlx::query_as(
+                    r#"
+            SELECT files.*, -bm25(files_fts, 3.0, 1.0, 2.0) AS rank
+            FROM files_fts JOIN files ON files.id = files_fts.rowid
+            WHERE files_fts MATCH $1 AND bucket = $3 AND deleted_at IS NULL
+            ORDER BY rank DESC, file_name
+            LIMIT $2
+            "#,
+                )
+                .bind(
+                    lexemes
+                        .iter()
+                        .map(|lexeme| format!("\"{lexeme}\"*"))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                )
+                .bind(limit)
+                .bind(bucket)
+                .fetch_all(pool)
+                .await
+            }
+        }
+        .context("Failed to search files")?;
+        Ok(rows)
+    }
+
+    /// ゴミ箱に入っていないファイル
+    pub async fn find_one(pool: &Pool, file_name: &str) -> Result<FileRow> {
+        let bucket = pool.bucket();
+        let row = on_pool!(pool, |pool| sqlx::query_as(
+            r#"SELECT * FROM files WHERE bucket = $2 AND file_name = $1 AND deleted_at IS NULL"#
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_one(pool)
+        .await)
+        .context("Failed to get file")?;
+        Ok(row)
+    }
+
+    /// ゴミ箱に入っていないファイル (なければ None)
+    pub async fn find(pool: &Pool, file_name: &str) -> Result<Option<FileRow>> {
+        let bucket = pool.bucket();
+        let row = on_pool!(pool, |pool| sqlx::query_as(
+            r#"SELECT * FROM files WHERE bucket = $2 AND file_name = $1 AND deleted_at IS NULL"#
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_optional(pool)
+        .await)
+        .context("Failed to get file")?;
+        Ok(row)
+    }
+
+    /// ゴミ箱に入っているものも含めて名前で引く
+    async fn find_any(pool: &Pool, file_name: &str) -> Result<Option<FileRow>> {
+        let bucket = pool.bucket();
+        let row = on_pool!(pool, |pool| sqlx::query_as(
+            r#"SELECT * FROM files WHERE bucket = $2 AND file_name = $1"#
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_optional(pool)
+        .await)
+        .context("Failed to get file")?;
+        Ok(row)
+    }
+
+    /// ゴミ箱に入っているものも含めて名前が使われているか
+    pub async fn is_exists(pool: &Pool, file_name: &str) -> Result<bool> {
+        let bucket = pool.bucket();
+        let (exists,): (bool,) = on_pool!(pool, |pool| sqlx::query_as(
+            r#"SELECT EXISTS (SELECT * FROM files WHERE bucket = $2 AND file_name = $1)"#
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_one(pool)
+        .await)
+        .context("Failed to count files")?;
         Ok(exists)
     }
 
-    pub async fn insert(&self, pool: &PgPool) -> Result<()> {
-        let _ = sqlx::query(
+    pub async fn insert(&self, pool: &Pool) -> Result<()> {
+        let bucket = pool.bucket();
+        let _ = on_pool!(pool, |pool| sqlx::query(
+            r#"
+        INSERT INTO files (file_name, file_url, space_id, block_id, origin_file_path, created_at, size, mime, hash, source_host, source_mtime, source_mode, source_uid, source_gid, source_symlink, expires_at, bucket, encoding)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+        "#,
+        )
+        .bind(&self.file_name)
+        .bind(&self.file_url)
+        .bind(&self.space_id)
+        .bind(&self.block_id)
+        .bind(&self.origin_file_path)
+        .bind(self.created_at)
+        .bind(self.size)
+        .bind(&self.mime)
+        .bind(&self.hash)
+        .bind(&self.source.host)
+        .bind(self.source.mtime)
+        .bind(self.source.mode)
+        .bind(self.source.uid)
+        .bind(self.source.gid)
+        .bind(&self.source.symlink)
+        .bind(self.expires_at)
+        .bind(bucket)
+        .bind(&self.encoding)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected()))
+        .map_err(|e| match &e {
+            // is_exists の確認の後に同じ名前が登録された
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                anyhow!(Rejected::Exists(self.file_name.clone()))
+            }
+            _ => anyhow!(e).context("Failed to insert row"),
+        })?;
+        Ok(())
+    }
+
+    /// ゴミ箱に入っているものも含めて名前順にすべて返す (1 行ずつ)
+    pub fn all<'a>(pool: &'a Pool, prefix: &str) -> impl Stream<Item = Result<FileRow>> + 'a {
+        const SQL: &str = r#"SELECT * FROM files WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' ORDER BY file_name"#;
+        let pattern = format!("{}%", escape_like(prefix));
+        let bucket = pool.bucket.clone();
+        try_stream! {
+            match &pool.backend {
+                Backend::Postgres(pool) => {
+                    let mut rows = sqlx::query_as(SQL).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select files")? {
+                        yield row;
+                    }
+                }
+                #[cfg(feature = "sqlite")]
+                Backend::Sqlite(pool) => {
+                    let mut rows = sqlx::query_as(SQL).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select files")? {
+                        yield row;
+                    }
+                }
+            }
+        }
+    }
+
+    /// prefix で始まるファイルの、Notion に置いてあるすべてのバージョン (ゴミ箱のものも含む)
+    pub fn stored<'a>(pool: &'a Pool, prefix: &str) -> impl Stream<Item = Result<FileRow>> + 'a {
+        let sql = format!(
+            r#"
+        SELECT {FILE_COLUMNS} FROM files WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\'
+        UNION ALL
+        SELECT {FILE_COLUMNS} FROM file_versions WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\'
+        ORDER BY file_name, version
+        "#
+        );
+        let pattern = format!("{}%", escape_like(prefix));
+        let bucket = pool.bucket.clone();
+        try_stream! {
+            match &pool.backend {
+                Backend::Postgres(pool) => {
+                    let mut rows = sqlx::query_as(&sql).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select versions")? {
+                        yield row;
+                    }
+                }
+                #[cfg(feature = "sqlite")]
+                Backend::Sqlite(pool) => {
+                    let mut rows = sqlx::query_as(&sql).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select versions")? {
+                        yield row;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 古いバージョンとタグも含めて行をそのまま書き込む
+    /// 同じ名前が既にあれば、古いバージョンやタグごと消してから書き込む
+    /// 途中で失敗したら何も書き込まない
+    pub async fn replace(pool: &Pool, files: &[(FileRow, Vec<FileRow>, Vec<Tag>)]) -> Result<()> {
+        let bucket = pool.bucket();
+        on_pool!(pool, |pool| {
+            let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+            for (row, old_versions, tags) in files {
+                sqlx::query(r#"DELETE FROM files WHERE bucket = $2 AND file_name = $1"#)
+                    .bind(&row.file_name)
+                    .bind(bucket)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to delete row")?;
+                sqlx::query(&format!(
+                    r#"
+                INSERT INTO files ({FILE_COLUMNS}, deleted_at, expires_at, bucket)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
+                "#
+                ))
+                .bind(&row.file_name)
+                .bind(&row.file_url)
+                .bind(&row.space_id)
+                .bind(&row.block_id)
+                .bind(&row.origin_file_path)
+                .bind(row.created_at)
+                .bind(row.size)
+                .bind(&row.mime)
+                .bind(&row.hash)
+                .bind(row.version)
+                .bind(&row.source.host)
+                .bind(row.source.mtime)
+                .bind(row.source.mode)
+                .bind(row.source.uid)
+                .bind(row.source.gid)
+                .bind(&row.source.symlink)
+                .bind(&row.encoding)
+                .bind(row.deleted_at)
+                .bind(row.expires_at)
+                .bind(bucket)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to insert row")?;
+                for old in old_versions {
+                    sqlx::query(&format!(
+                        r#"
+                    INSERT INTO file_versions ({FILE_COLUMNS}, bucket)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+                    "#
+                    ))
+                    .bind(&row.file_name)
+                    .bind(&old.file_url)
+                    .bind(&old.space_id)
+                    .bind(&old.block_id)
+                    .bind(&old.origin_file_path)
+                    .bind(old.created_at)
+                    .bind(old.size)
+                    .bind(&old.mime)
+                    .bind(&old.hash)
+                    .bind(old.version)
+                    .bind(&old.source.host)
+                    .bind(old.source.mtime)
+                    .bind(old.source.mode)
+                    .bind(old.source.uid)
+                    .bind(old.source.gid)
+                    .bind(&old.source.symlink)
+                    .bind(&old.encoding)
+                    .bind(bucket)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to insert old version")?;
+                }
+                insert_tags!(tx, bucket, &row.file_name, tags);
+            }
+            tx.commit().await.context("Failed to commit transaction")?;
+        });
+        Ok(())
+    }
+
+    /// 今の行を file_versions に退避して、新しいバージョンとして置き換える
+    /// 置き換えたあとのバージョンを返す
+    pub async fn overwrite(&self, pool: &Pool) -> Result<i32> {
+        let bucket = pool.bucket();
+        let version = on_pool!(pool, |pool| {
+            let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+            let _ = sqlx::query(&format!(
+                r#"
+        INSERT INTO file_versions ({FILE_COLUMNS}, bucket)
+        SELECT {FILE_COLUMNS}, bucket FROM files WHERE bucket = $2 AND file_name = $1
+        "#
+            ))
+            .bind(&self.file_name)
+            .bind(bucket)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to save old version")?;
+            let (version,): (i32,) = sqlx::query_as(
             r#"
-        INSERT INTO files (file_name, file_url, space_id, block_id, origin_file_path, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        UPDATE files
+        SET file_url = $2, space_id = $3, block_id = $4, origin_file_path = $5, created_at = $6, size = $7, mime = $8, hash = $9,
+            source_host = $10, source_mtime = $11, source_mode = $12, source_uid = $13, source_gid = $14, source_symlink = $15,
+            expires_at = $17, encoding = $18, version = version + 1
+        WHERE bucket = $16 AND file_name = $1
+        RETURNING version
         "#,
         )
         .bind(&self.file_name)
@@ -63,22 +851,1103 @@ impl FileRow {
         .bind(&self.block_id)
         .bind(&self.origin_file_path)
         .bind(self.created_at)
+        .bind(self.size)
+        .bind(&self.mime)
+        .bind(&self.hash)
+        .bind(&self.source.host)
+        .bind(self.source.mtime)
+        .bind(self.source.mode)
+        .bind(self.source.uid)
+        .bind(self.source.gid)
+        .bind(&self.source.symlink)
+        .bind(bucket)
+        .bind(self.expires_at)
+        .bind(&self.encoding)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to update row")?;
+            tx.commit().await.context("Failed to commit transaction")?;
+            version
+        });
+        Ok(version)
+    }
+
+    /// 今のバージョンも含めて新しい順に返す
+    pub async fn versions(pool: &Pool, file_name: &str) -> Result<Vec<FileRow>> {
+        let bucket = pool.bucket();
+        let rows = on_pool!(pool, |pool| sqlx::query_as(&format!(
+            r#"
+        SELECT {FILE_COLUMNS} FROM files WHERE bucket = $2 AND file_name = $1
+        UNION ALL
+        SELECT {FILE_COLUMNS} FROM file_versions WHERE bucket = $2 AND file_name = $1
+        ORDER BY version DESC
+        "#
+        ))
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select versions")?;
+        Ok(rows)
+    }
+
+    /// names の古いバージョンをまとめて引く (ファイルごとに新しい順、古いバージョンのないファイルは入らない)
+    pub async fn old_versions_of(
+        pool: &Pool,
+        names: &[String],
+    ) -> Result<HashMap<String, Vec<FileRow>>> {
+        let mut versions: HashMap<String, Vec<FileRow>> = HashMap::new();
+        if names.is_empty() {
+            return Ok(versions);
+        }
+        let bucket = pool.bucket();
+        let rows: Vec<FileRow> = on_pool!(pool, |pool| {
+            let mut builder = QueryBuilder::new(format!(
+                "SELECT {FILE_COLUMNS} FROM file_versions WHERE bucket = "
+            ));
+            builder.push_bind(bucket).push(" AND file_name IN (");
+            let mut separated = builder.separated(", ");
+            for name in names {
+                separated.push_bind(name);
+            }
+            builder.push(") ORDER BY file_name, version DESC");
+            builder.build_query_as().fetch_all(pool).await
+        })
+        .context("Failed to select versions")?;
+        for row in rows {
+            versions.entry(row.file_name.clone()).or_default().push(row);
+        }
+        Ok(versions)
+    }
+
+    pub async fn find_version(pool: &Pool, file_name: &str, version: i32) -> Result<FileRow> {
+        let bucket = pool.bucket();
+        let row = on_pool!(pool, |pool| sqlx::query_as(&format!(
+            r#"
+        SELECT {FILE_COLUMNS} FROM files WHERE bucket = $3 AND file_name = $1 AND version = $2
+        UNION ALL
+        SELECT {FILE_COLUMNS} FROM file_versions WHERE bucket = $3 AND file_name = $1 AND version = $2
+        "#
+        ))
+        .bind(file_name)
+        .bind(version)
+        .bind(bucket)
+        .fetch_one(pool)
+        .await)
+        .with_context(|| format!("Failed to get version {version} of {file_name}"))?;
+        Ok(row)
+    }
+
+    /// 新しいほうから keep 個を除いた古いバージョン
+    pub async fn stale_versions(pool: &Pool, file_name: &str, keep: i64) -> Result<Vec<FileRow>> {
+        let bucket = pool.bucket();
+        let rows = on_pool!(pool, |pool| sqlx::query_as(
+            r#"
+        SELECT * FROM file_versions
+        WHERE bucket = $3 AND file_name = $1 AND version NOT IN (
+            SELECT version FROM file_versions WHERE bucket = $3 AND file_name = $1 ORDER BY version DESC LIMIT $2
+        )
+        ORDER BY version DESC
+        "#,
+        )
+        .bind(file_name)
+        .bind(keep)
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select old versions")?;
+        Ok(rows)
+    }
+
+    /// 古いバージョンがあるファイル名
+    pub async fn versioned_names(pool: &Pool, prefix: &str) -> Result<Vec<String>> {
+        let bucket = pool.bucket();
+        let names = on_pool!(pool, |pool| sqlx::query_scalar(
+            r#"SELECT DISTINCT file_name FROM file_versions WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' ORDER BY file_name"#,
+        )
+        .bind(format!("{}%", escape_like(prefix)))
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select versioned files")?;
+        Ok(names)
+    }
+
+    pub async fn delete_version(pool: &Pool, file_name: &str, version: i32) -> Result<()> {
+        let bucket = pool.bucket();
+        let _ = on_pool!(pool, |pool| sqlx::query(
+            r#"DELETE FROM file_versions WHERE bucket = $3 AND file_name = $1 AND version = $2"#
+        )
+        .bind(file_name)
+        .bind(version)
+        .bind(bucket)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected()))
+        .context("Failed to delete version")?;
+        Ok(())
+    }
+
+    /// 名前を変える (古いバージョンも外部キーでついてくる)
+    pub async fn rename(pool: &Pool, from: &str, to: &str) -> Result<()> {
+        let bucket = pool.bucket();
+        let _ = on_pool!(pool, |pool| sqlx::query(
+            r#"UPDATE files SET file_name = $2 WHERE bucket = $3 AND file_name = $1"#
+        )
+        .bind(from)
+        .bind(to)
+        .bind(bucket)
         .execute(pool)
         .await
-        .context("Failed to insert row")?;
+        .map(|result| result.rows_affected()))
+        .context("Failed to rename row")?;
+        Ok(())
+    }
+
+    /// size、mime、hash のどれかがない行 (古いバージョンとゴミ箱のものも含む)
+    pub fn incomplete<'a>(
+        pool: &'a Pool,
+        prefix: &str,
+    ) -> impl Stream<Item = Result<FileRow>> + 'a {
+        let sql = format!(
+            r#"
+        SELECT {FILE_COLUMNS} FROM files
+        WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' AND (size IS NULL OR mime IS NULL OR hash IS NULL)
+        UNION ALL
+        SELECT {FILE_COLUMNS} FROM file_versions
+        WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' AND (size IS NULL OR mime IS NULL OR hash IS NULL)
+        ORDER BY file_name, version
+        "#
+        );
+        let pattern = format!("{}%", escape_like(prefix));
+        let bucket = pool.bucket.clone();
+        try_stream! {
+            match &pool.backend {
+                Backend::Postgres(pool) => {
+                    let mut rows = sqlx::query_as(&sql).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select incomplete files")? {
+                        yield row;
+                    }
+                }
+                #[cfg(feature = "sqlite")]
+                Backend::Sqlite(pool) => {
+                    let mut rows = sqlx::query_as(&sql).bind(pattern).bind(bucket).fetch(pool);
+                    while let Some(row) = rows.try_next().await.context("Failed to select incomplete files")? {
+                        yield row;
+                    }
+                }
+            }
+        }
+    }
+
+    /// incomplete で返る行の数
+    pub async fn count_incomplete(pool: &Pool, prefix: &str) -> Result<i64> {
+        let bucket = pool.bucket();
+        let (count,): (i64,) = on_pool!(pool, |pool| sqlx::query_as(
+            r#"
+        SELECT
+            (SELECT COUNT(*) FROM files
+             WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' AND (size IS NULL OR mime IS NULL OR hash IS NULL))
+          + (SELECT COUNT(*) FROM file_versions
+             WHERE bucket = $2 AND file_name LIKE $1 ESCAPE '\' AND (size IS NULL OR mime IS NULL OR hash IS NULL))
+        "#
+        )
+        .bind(format!("{}%", escape_like(prefix)))
+        .bind(bucket)
+        .fetch_one(pool)
+        .await)
+        .context("Failed to count incomplete files")?;
+        Ok(count)
+    }
+
+    /// 空いている size、mime、hash だけを埋める
+    pub async fn fill_metadata(&self, pool: &Pool) -> Result<()> {
+        let bucket = pool.bucket();
+        for table in ["files", "file_versions"] {
+            on_pool!(pool, |pool| sqlx::query(&format!(
+                r#"
+            UPDATE {table}
+            SET size = COALESCE(size, $3), mime = COALESCE(mime, $4), hash = COALESCE(hash, $5)
+            WHERE bucket = $6 AND file_name = $1 AND version = $2
+            "#
+            ))
+            .bind(&self.file_name)
+            .bind(self.version)
+            .bind(self.size)
+            .bind(&self.mime)
+            .bind(&self.hash)
+            .bind(bucket)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to update {table}"))?;
+        }
+        Ok(())
+    }
+
+    pub async fn is_trashed(pool: &Pool, file_name: &str) -> Result<bool> {
+        let bucket = pool.bucket();
+        let (trashed,): (bool,) = on_pool!(pool, |pool| sqlx::query_as(
+            r#"SELECT EXISTS (SELECT * FROM files WHERE bucket = $2 AND file_name = $1 AND deleted_at IS NOT NULL)"#,
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_one(pool)
+        .await)
+        .context("Failed to count files")?;
+        Ok(trashed)
+    }
+
+    /// ゴミ箱に入れる (ゴミ箱の外になければ None)
+    pub async fn trash(
+        pool: &Pool,
+        file_name: &str,
+        now: NaiveDateTime,
+    ) -> Result<Option<FileRow>> {
+        let bucket = pool.bucket();
+        let row = on_pool!(pool, |pool| sqlx::query_as(
+            r#"UPDATE files SET deleted_at = $2 WHERE bucket = $3 AND file_name = $1 AND deleted_at IS NULL RETURNING *"#,
+        )
+        .bind(file_name)
+        .bind(now)
+        .bind(bucket)
+        .fetch_optional(pool)
+        .await)
+        .context("Failed to trash file")?;
+        Ok(row)
+    }
+
+    /// ゴミ箱から戻す (ゴミ箱になければ false)
+    pub async fn restore(pool: &Pool, file_name: &str) -> Result<bool> {
+        let bucket = pool.bucket();
+        let result = on_pool!(pool, |pool| {
+            sqlx::query(
+            r#"UPDATE files SET deleted_at = NULL WHERE bucket = $2 AND file_name = $1 AND deleted_at IS NOT NULL"#,
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected())
+        })
+        .context("Failed to restore file")?;
+        Ok(result > 0)
+    }
+
+    /// ゴミ箱の中身 (before より前に入れたものだけ、古い順)
+    pub async fn trashed(
+        pool: &Pool,
+        prefix: &str,
+        before: Option<NaiveDateTime>,
+    ) -> Result<Vec<FileRow>> {
+        let bucket = pool.bucket();
+        let rows = on_pool!(pool, |pool| sqlx::query_as(
+            r#"
+        SELECT * FROM files
+        WHERE bucket = $3 AND deleted_at IS NOT NULL AND file_name LIKE $1 ESCAPE '\' AND ($2 IS NULL OR deleted_at < $2)
+        ORDER BY deleted_at, file_name
+        "#,
+        )
+        .bind(format!("{}%", escape_like(prefix)))
+        .bind(before)
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select trash")?;
+        Ok(rows)
+    }
+
+    /// now までに期限が過ぎたファイル (ゴミ箱のものも含む、期限の古い順)
+    pub async fn expired(pool: &Pool, prefix: &str, now: NaiveDateTime) -> Result<Vec<FileRow>> {
+        let bucket = pool.bucket();
+        let rows = on_pool!(pool, |pool| sqlx::query_as(
+            r#"
+        SELECT * FROM files
+        WHERE bucket = $3 AND expires_at <= $2 AND file_name LIKE $1 ESCAPE '\'
+        ORDER BY expires_at, file_name
+        "#,
+        )
+        .bind(format!("{}%", escape_like(prefix)))
+        .bind(now)
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select expired files")?;
+        Ok(rows)
+    }
+
+    /// タグとメタデータ (タグが先、それぞれ名前順)
+    pub async fn tags(pool: &Pool, file_name: &str) -> Result<Vec<Tag>> {
+        let bucket = pool.bucket();
+        let rows: Vec<(String, Option<String>)> = on_pool!(pool, |pool| sqlx::query_as(
+            r#"
+        SELECT tag, NULL FROM file_tags WHERE bucket = $2 AND file_name = $1
+        UNION ALL
+        SELECT key, value FROM file_meta WHERE bucket = $2 AND file_name = $1
+        ORDER BY 2 NULLS FIRST, 1
+        "#,
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select tags")?;
+        Ok(rows
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => Tag::Meta(key, value),
+                None => Tag::Label(key),
+            })
+            .collect())
+    }
+
+    /// names のタグとメタデータをまとめて引く (ファイルごとの並びは tags と同じ、タグのないファイルは入らない)
+    pub async fn tags_of(pool: &Pool, names: &[String]) -> Result<HashMap<String, Vec<Tag>>> {
+        let mut tags: HashMap<String, Vec<Tag>> = HashMap::new();
+        if names.is_empty() {
+            return Ok(tags);
+        }
+        let bucket = pool.bucket();
+        let rows: Vec<(String, String, Option<String>)> = on_pool!(pool, |pool| {
+            let mut builder = QueryBuilder::new("");
+            for (i, (columns, table)) in [
+                ("file_name, tag, NULL", "file_tags"),
+                ("file_name, key, value", "file_meta"),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                if i > 0 {
+                    builder.push(" UNION ALL ");
+                }
+                builder
+                    .push(format_args!(
+                        "SELECT {columns} FROM {table} WHERE bucket = "
+                    ))
+                    .push_bind(bucket)
+                    .push(" AND file_name IN (");
+                let mut separated = builder.separated(", ");
+                for name in names {
+                    separated.push_bind(name);
+                }
+                builder.push(")");
+            }
+            builder.push(" ORDER BY 3 NULLS FIRST, 2");
+            builder.build_query_as().fetch_all(pool).await
+        })
+        .context("Failed to select tags")?;
+        for (file_name, key, value) in rows {
+            tags.entry(file_name).or_default().push(match value {
+                Some(value) => Tag::Meta(key, value),
+                None => Tag::Label(key),
+            });
+        }
+        Ok(tags)
+    }
+
+    /// タグを付ける (同じキーのメタデータは値を置き換える)
+    pub async fn add_tags(pool: &Pool, file_name: &str, tags: &[Tag]) -> Result<()> {
+        let bucket = pool.bucket();
+        on_pool!(pool, |pool| {
+            let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+            insert_tags!(tx, bucket, file_name, tags);
+            tx.commit().await.context("Failed to commit transaction")?;
+        });
+        Ok(())
+    }
+
+    /// タグ名かメタデータのキーが keys にあるものを外す
+    pub async fn remove_tags(pool: &Pool, file_name: &str, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let bucket = pool.bucket();
+        on_pool!(pool, |pool| {
+            let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+            for (table, column) in [("file_tags", "tag"), ("file_meta", "key")] {
+                let mut builder = QueryBuilder::new(format!("DELETE FROM {table} WHERE bucket = "));
+                builder
+                    .push_bind(bucket)
+                    .push(" AND file_name = ")
+                    .push_bind(file_name)
+                    .push(format_args!(" AND {column} IN ("));
+                let mut separated = builder.separated(", ");
+                for key in keys {
+                    separated.push_bind(key);
+                }
+                builder.push(")");
+                builder
+                    .build()
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("Failed to delete from {table}"))?;
+            }
+            tx.commit().await.context("Failed to commit transaction")?;
+        });
+        Ok(())
+    }
+
+    pub async fn delete(pool: &Pool, file_name: &str) -> Result<()> {
+        let bucket = pool.bucket();
+        let _ = on_pool!(pool, |pool| sqlx::query(
+            r#"DELETE FROM files WHERE bucket = $2 AND file_name = $1"#
+        )
+        .bind(file_name)
+        .bind(bucket)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected()))
+        .context("Failed to delete row")?;
         Ok(())
     }
 }
 
-pub async fn create_pool(host: &str) -> Result<PgPool> {
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(host)
+/// audit_log に記録する操作
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AuditAction {
+    Put,
+    Overwrite,
+    Get,
+    /// ゴミ箱に入れる
+    Rm,
+    Restore,
+    /// ゴミ箱から消して Notion 上でアーカイブする
+    Purge,
+    Mv,
+    /// 古いバージョンを消す
+    Gc,
+    Tag,
+    Import,
+    /// 足りないメタデータを埋める
+    Backfill,
+    /// 期限が過ぎたので Notion 上でアーカイブして消す
+    Expire,
+}
+
+impl AuditAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Put => "put",
+            AuditAction::Overwrite => "overwrite",
+            AuditAction::Get => "get",
+            AuditAction::Rm => "rm",
+            AuditAction::Restore => "restore",
+            AuditAction::Purge => "purge",
+            AuditAction::Mv => "mv",
+            AuditAction::Gc => "gc",
+            AuditAction::Tag => "tag",
+            AuditAction::Import => "import",
+            AuditAction::Backfill => "backfill",
+            AuditAction::Expire => "expire",
+        }
+    }
+}
+
+/// 追記のみの操作の記録
+#[derive(FromRow, Debug)]
+pub struct AuditRow {
+    pub id: i64,
+    pub created_at: NaiveDateTime,
+    /// 操作した OS のユーザー
+    pub os_user: String,
+    pub hostname: String,
+    pub yukumo_version: String,
+    pub action: String,
+    pub file_name: String,
+    pub file_version: Option<i32>,
+    pub block_id: Option<String>,
+    pub succeeded: bool,
+    /// 失敗した理由や移動先の名前
+    pub detail: Option<String>,
+}
+
+impl AuditRow {
+    /// 今のユーザーとホストで成功した記録を作る
+    pub fn new(action: AuditAction, file_name: &str) -> AuditRow {
+        AuditRow {
+            id: 0,
+            created_at: Utc::now().naive_utc(),
+            os_user: whoami::username(),
+            hostname: whoami::hostname(),
+            yukumo_version: crate::meta::PKG_VERSION.to_string(),
+            action: action.as_str().to_string(),
+            file_name: file_name.to_string(),
+            file_version: None,
+            block_id: None,
+            succeeded: true,
+            detail: None,
+        }
+    }
+
+    pub fn file(mut self, row: &FileRow) -> AuditRow {
+        self.file_version = Some(row.version);
+        self.block_id = Some(row.block_id.clone());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> AuditRow {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// 失敗していればその理由を記録する
+    pub fn outcome<T>(mut self, result: &Result<T>) -> AuditRow {
+        if let Err(e) = result {
+            self.succeeded = false;
+            self.detail = Some(format!("{e:#}"));
+        }
+        self
+    }
+
+    pub async fn insert(&self, pool: &Pool) -> Result<()> {
+        let bucket = pool.bucket();
+        let _ = on_pool!(pool, |pool| sqlx::query(
+            r#"
+        INSERT INTO audit_log (created_at, os_user, hostname, yukumo_version, action, file_name, file_version, block_id, succeeded, detail, bucket)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        )
+        .bind(self.created_at)
+        .bind(&self.os_user)
+        .bind(&self.hostname)
+        .bind(&self.yukumo_version)
+        .bind(&self.action)
+        .bind(&self.file_name)
+        .bind(self.file_version)
+        .bind(&self.block_id)
+        .bind(self.succeeded)
+        .bind(&self.detail)
+        .bind(bucket)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected()))
+        .context("Failed to insert audit log")?;
+        Ok(())
+    }
+
+    /// 新しい順に返す
+    pub async fn query(
+        pool: &Pool,
+        file_name: Option<&str>,
+        since: Option<NaiveDateTime>,
+        limit: Option<i64>,
+    ) -> Result<Vec<AuditRow>> {
+        let bucket = pool.bucket();
+        let rows = on_pool!(pool, |pool| {
+            let mut builder = QueryBuilder::new("SELECT * FROM audit_log WHERE bucket = ");
+            builder.push_bind(bucket);
+            if let Some(file_name) = file_name {
+                builder.push(" AND file_name = ").push_bind(file_name);
+            }
+            if let Some(since) = since {
+                builder.push(" AND created_at >= ").push_bind(since);
+            }
+            builder.push(" ORDER BY id DESC");
+            if let Some(limit) = limit {
+                builder.push(" LIMIT ").push_bind(limit);
+            }
+            builder.build_query_as().fetch_all(pool).await
+        })
+        .context("Failed to select audit log")?;
+        Ok(rows)
+    }
+}
+
+/// 接続して、run-migrations ならマイグレーションする
+pub async fn create_pool(config: &DatabaseConfig) -> Result<Pool> {
+    let pool = connect(config).await?;
+    if config.run_migrations {
+        migrate(&pool).await?;
+    } else {
+        let pending = pending_migrations(&pool).await?;
+        if !pending.is_empty() {
+            log::warn!(
+                "{} migrations are pending. Run `yukumo migrate` with a role that owns the schema.",
+                pending.len()
+            );
+        }
+    }
+    Ok(pool)
+}
+
+/// まだ適用されていないマイグレーションを適用して、その説明を返す
+pub async fn migrate(pool: &Pool) -> Result<Vec<String>> {
+    let pending = pending_migrations(pool).await?;
+    on_pool!(pool, |conn| pool.migrator().run(conn).await).context("Failed to run migration")?;
+    Ok(pending)
+}
+
+/// マイグレーションせずに接続する
+pub async fn connect(config: &DatabaseConfig) -> Result<Pool> {
+    let host = &config.host;
+    if let Some(path) = host.strip_prefix("sqlite://") {
+        return connect_sqlite(config, path).await;
+    }
+    let mut options = PgConnectOptions::from_str(host)
+        .with_context(|| format!("Invalid database.host {host}"))?;
+    if let Some(sslmode) = config.sslmode {
+        options = options.ssl_mode(match sslmode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Allow => PgSslMode::Allow,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require => PgSslMode::Require,
+            SslMode::VerifyCa => PgSslMode::VerifyCa,
+            SslMode::VerifyFull => PgSslMode::VerifyFull,
+        });
+    }
+    if let Some(path) = &config.ssl_root_cert {
+        options = options.ssl_root_cert(path);
+    }
+    if let Some(path) = &config.ssl_client_cert {
+        options = options.ssl_client_cert(path);
+    }
+    if let Some(path) = &config.ssl_client_key {
+        options = options.ssl_client_key(path);
+    }
+    let pool = pool_options(config)
+        .connect_with(options)
         .await
         .with_context(|| format!("Failed to connect {host}"))?;
-    sqlx::migrate!()
-        .run(&pool)
+    Ok(Pool::new(Backend::Postgres(pool)))
+}
+
+/// 接続の数とタイムアウト
+fn pool_options<DB: Database>(config: &DatabaseConfig) -> PoolOptions<DB> {
+    let mut options = PoolOptions::new().max_connections(config.max_connections.unwrap_or(5));
+    if let Some(secs) = config.acquire_timeout {
+        options = options.acquire_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = config.idle_timeout {
+        options = options.idle_timeout(Duration::from_secs(secs));
+    }
+    options
+}
+
+/// ファイルがなければ作る (`~/` はホームディレクトリにする)
+/// `sqlite://:memory:` なら、プロセスが終わると消えるカタログを 1 つの接続で持つ
+#[cfg(feature = "sqlite")]
+async fn connect_sqlite(config: &DatabaseConfig, path: &str) -> Result<Pool> {
+    if path == ":memory:" {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?
+            .pragma("case_sensitive_like", "ON")
+            .with_regexp();
+        // 接続を閉じると中身が消えるので、1 つを使い続ける
+        let pool = PoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .context("Failed to open in-memory database")?;
+        return Ok(Pool::new(Backend::Sqlite(pool)));
+    }
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => home::home_dir()
+            .context("Failed to get homedir")?
+            .join(rest),
+        None => std::path::PathBuf::from(path),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true)
+        // PostgreSQL と同じく LIKE で大文字と小文字を区別する
+        .pragma("case_sensitive_like", "ON")
+        .with_regexp();
+    let pool = pool_options(config)
+        .connect_with(options)
         .await
-        .context("Failed to run migration")?;
-    Ok(pool)
+        .with_context(|| format!("Failed to open {path:?}"))?;
+    Ok(Pool::new(Backend::Sqlite(pool)))
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn connect_sqlite(_config: &DatabaseConfig, _path: &str) -> Result<Pool> {
+    anyhow::bail!("SQLite is not supported in this build. Rebuild with `--features sqlite`.")
+}
+
+/// まだ適用されていないマイグレーションの説明
+pub async fn pending_migrations(pool: &Pool) -> Result<Vec<String>> {
+    let exists_query = match pool.backend {
+        Backend::Postgres(_) => r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL"#,
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite(_) => {
+            r#"SELECT EXISTS (SELECT * FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')"#
+        }
+    };
+    let (exists,): (bool,) = on_pool!(pool, |pool| sqlx::query_as(exists_query)
+        .fetch_one(pool)
+        .await)
+    .context("Failed to check migrations table")?;
+    let applied: Vec<i64> = if exists {
+        on_pool!(pool, |pool| sqlx::query_scalar(
+            r#"SELECT version FROM _sqlx_migrations WHERE success"#
+        )
+        .fetch_all(pool)
+        .await)
+        .context("Failed to select applied migrations")?
+    } else {
+        Vec::new()
+    };
+    Ok(pool
+        .migrator()
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect())
+}
+
+/// LIKE の特殊文字をエスケープする
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// glob を前後にアンカーした正規表現にする
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c if "\\.^$|()[]{}+".contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[test]
+fn test_glob_to_regex() {
+    assert_eq!(glob_to_regex("photos/*.jpg"), r"^photos/[^/]*\.jpg$");
+    assert_eq!(glob_to_regex("**/report-?.pdf"), r"^.*/report-[^/]\.pdf$");
+    assert_eq!(glob_to_regex("[!a-c]x(1)"), r"^[^a-c]x\(1\)$");
+}
+
+#[test]
+fn test_escape_like() {
+    assert_eq!(escape_like(r"100%_a\b"), r"100\%\_a\\b");
+}
+
+#[test]
+fn test_file_query_apply() {
+    let row = |name: &str, size: Option<i64>| FileRow {
+        file_name: name.to_string(),
+        file_url: String::new(),
+        space_id: String::new(),
+        block_id: String::new(),
+        origin_file_path: format!("/tmp/{name}"),
+        created_at: NaiveDateTime::default(),
+        size,
+        mime: None,
+        hash: None,
+        version: 1,
+        source: FileSource::default(),
+        encoding: None,
+        deleted_at: None,
+        expires_at: None,
+    };
+    let rows = || {
+        vec![
+            row("a/c.txt", Some(3)),
+            row("a/b.txt", None),
+            row("a/x/d.pdf", Some(10)),
+            row("b/e.txt", Some(1)),
+        ]
+    };
+    let names =
+        |rows: Vec<FileRow>| -> Vec<String> { rows.into_iter().map(|row| row.file_name).collect() };
+
+    let query = FileQuery {
+        prefix: "a/".to_string(),
+        name: Some(Pattern::Glob("a/*.txt".to_string())),
+        ..Default::default()
+    };
+    assert_eq!(names(query.apply(rows()).unwrap()), ["a/b.txt", "a/c.txt"]);
+
+    let query = FileQuery {
+        sort: SortKey::Size,
+        reverse: true,
+        limit: Some(3),
+        ..Default::default()
+    };
+    assert_eq!(
+        names(query.apply(rows()).unwrap()),
+        ["a/x/d.pdf", "a/c.txt", "b/e.txt"]
+    );
+
+    let query = FileQuery {
+        sort: SortKey::Size,
+        reverse: true,
+        after: Some("a/c.txt".to_string()),
+        limit: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(names(query.apply(rows()).unwrap()), ["b/e.txt", "a/b.txt"]);
+
+    // 名前順なら、なくなった名前からでも続けられる
+    let query = FileQuery {
+        after: Some("a/c.txz".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        names(query.apply(rows()).unwrap()),
+        ["a/x/d.pdf", "b/e.txt"]
+    );
+
+    let query = FileQuery {
+        sort: SortKey::Date,
+        after: Some("a/c.txz".to_string()),
+        ..Default::default()
+    };
+    assert!(query.apply(rows()).is_err());
+
+    let query = FileQuery {
+        tags: vec![Tag::Label("raw".to_string())],
+        ..Default::default()
+    };
+    assert!(query.apply(rows()).is_err());
+}
+
+#[test]
+fn test_rejected() {
+    let e = anyhow!(Rejected::Exists("a.txt".to_string())).context("Failed to put");
+    assert_eq!(
+        Rejected::find(&e),
+        Some(&Rejected::Exists("a.txt".to_string()))
+    );
+    assert_eq!(Rejected::find(&anyhow!("timed out")), None);
+    assert!(Rejected::check_name("").is_err());
+    assert!(Rejected::check_name("a\0b").is_err());
+    assert!(Rejected::check_name("a/b.txt").is_ok());
+}
+
+/// テスト用のメモリ上のカタログ
+#[cfg(all(test, feature = "sqlite"))]
+async fn memory_pool() -> Result<Pool> {
+    create_pool(&DatabaseConfig::new("sqlite://:memory:".to_string())).await
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+fn test_row(name: &str, size: Option<i64>, created_at: i64) -> FileRow {
+    FileRow {
+        file_name: name.to_string(),
+        file_url: String::new(),
+        space_id: String::new(),
+        block_id: format!("block-{name}"),
+        origin_file_path: String::new(),
+        created_at: chrono::DateTime::from_timestamp(created_at, 0)
+            .unwrap()
+            .naive_utc(),
+        size,
+        mime: None,
+        hash: None,
+        version: 1,
+        source: FileSource::default(),
+        encoding: None,
+        deleted_at: None,
+        expires_at: None,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_query_pagination() -> Result<()> {
+    let pool = memory_pool().await?;
+    for (name, size, created_at) in [
+        ("b", Some(20), 30),
+        ("a", Some(20), 10),
+        ("C", None, 20),
+        ("d", Some(5), 40),
+        ("e", None, 50),
+    ] {
+        test_row(name, size, created_at).insert(&pool).await?;
+    }
+    let names = |query: FileQuery| {
+        let pool = pool.clone();
+        async move {
+            let rows = FileRow::query(&pool, &query).await?;
+            anyhow::Ok(
+                rows.into_iter()
+                    .map(|row| row.file_name)
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+    let query = |sort, reverse, after: Option<&str>| FileQuery {
+        sort,
+        reverse,
+        after: after.map(ToString::to_string),
+        limit: Some(2),
+        ..Default::default()
+    };
+
+    // 名前はバイト順 (大文字が先)
+    assert_eq!(names(query(SortKey::Name, false, None)).await?, ["C", "a"]);
+    assert_eq!(
+        names(query(SortKey::Name, false, Some("a"))).await?,
+        ["b", "d"]
+    );
+    // after はカタログになくてもよい
+    assert_eq!(
+        names(query(SortKey::Name, false, Some("c"))).await?,
+        ["d", "e"]
+    );
+    assert_eq!(
+        names(query(SortKey::Name, true, Some("b"))).await?,
+        ["a", "C"]
+    );
+
+    // 同じサイズは名前順、サイズのないものは向きによらず最後
+    assert_eq!(names(query(SortKey::Size, false, None)).await?, ["d", "a"]);
+    assert_eq!(
+        names(query(SortKey::Size, false, Some("a"))).await?,
+        ["b", "C"]
+    );
+    assert_eq!(names(query(SortKey::Size, false, Some("C"))).await?, ["e"]);
+    assert_eq!(
+        names(query(SortKey::Size, true, Some("b"))).await?,
+        ["a", "d"]
+    );
+    assert_eq!(
+        names(query(SortKey::Size, true, Some("d"))).await?,
+        ["e", "C"]
+    );
+
+    assert_eq!(
+        names(query(SortKey::Date, false, Some("C"))).await?,
+        ["b", "d"]
+    );
+    assert_eq!(
+        names(query(SortKey::Date, true, Some("d"))).await?,
+        ["b", "C"]
+    );
+    assert!(names(query(SortKey::Date, false, Some("x"))).await.is_err());
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_trash_and_restore() -> Result<()> {
+    let pool = memory_pool().await?;
+    test_row("a.txt", Some(1), 10).insert(&pool).await?;
+    let now = chrono::DateTime::from_timestamp(100, 0)
+        .unwrap()
+        .naive_utc();
+
+    let trashed = FileRow::trash(&pool, "a.txt", now).await?;
+    assert_eq!(trashed.map(|row| row.deleted_at), Some(Some(now)));
+    assert!(FileRow::trash(&pool, "a.txt", now).await?.is_none());
+    assert!(FileRow::is_trashed(&pool, "a.txt").await?);
+    // ゴミ箱のものは一覧に出ないが、名前は使われたまま
+    assert!(FileRow::query(&pool, &FileQuery::default())
+        .await?
+        .is_empty());
+    assert!(FileRow::is_exists(&pool, "a.txt").await?);
+    assert_eq!(FileRow::trashed(&pool, "", None).await?.len(), 1);
+    assert!(FileRow::trashed(&pool, "", Some(now)).await?.is_empty());
+
+    assert!(FileRow::restore(&pool, "a.txt").await?);
+    assert!(!FileRow::restore(&pool, "a.txt").await?);
+    assert!(!FileRow::is_trashed(&pool, "a.txt").await?);
+    assert_eq!(FileRow::query(&pool, &FileQuery::default()).await?.len(), 1);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_version_pruning() -> Result<()> {
+    let pool = memory_pool().await?;
+    test_row("a.txt", Some(1), 10).insert(&pool).await?;
+    for i in 2..=4 {
+        let mut row = test_row("a.txt", Some(i), i * 10);
+        row.block_id = format!("block-{i}");
+        assert_eq!(row.overwrite(&pool).await?, i as i32);
+    }
+    let versions = FileRow::versions(&pool, "a.txt").await?;
+    let numbers: Vec<_> = versions.iter().map(|row| row.version).collect();
+    assert_eq!(numbers, [4, 3, 2, 1]);
+    assert_eq!(versions[0].block_id, "block-4");
+
+    // 今のバージョンを除いて新しいほうから 2 つ残す
+    let stale = FileRow::stale_versions(&pool, "a.txt", 2).await?;
+    let numbers: Vec<_> = stale.iter().map(|row| row.version).collect();
+    assert_eq!(numbers, [1]);
+    FileRow::delete_version(&pool, "a.txt", 1).await?;
+    assert!(FileRow::stale_versions(&pool, "a.txt", 2).await?.is_empty());
+    assert_eq!(
+        FileRow::find_version(&pool, "a.txt", 2).await?.size,
+        Some(2)
+    );
+    assert!(FileRow::find_version(&pool, "a.txt", 1).await.is_err());
+
+    // 消すと古いバージョンも消える
+    FileRow::delete(&pool, "a.txt").await?;
+    assert!(FileRow::versioned_names(&pool, "").await?.is_empty());
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_bucket_isolation() -> Result<()> {
+    let pool = memory_pool().await?;
+    let photos = pool.clone().with_bucket("photos");
+    test_row("a.txt", Some(1), 10).insert(&pool).await?;
+    test_row("a.txt", Some(2), 20).insert(&photos).await?;
+    test_row("b.txt", Some(3), 30).insert(&photos).await?;
+    FileRow::add_tags(&photos, "a.txt", &[Tag::Label("raw".to_string())]).await?;
+
+    assert_eq!(FileRow::find_one(&pool, "a.txt").await?.size, Some(1));
+    assert_eq!(FileRow::find_one(&photos, "a.txt").await?.size, Some(2));
+    assert!(!FileRow::is_exists(&pool, "b.txt").await?);
+    assert!(FileRow::tags(&pool, "a.txt").await?.is_empty());
+    assert_eq!(FileRow::tags(&photos, "a.txt").await?.len(), 1);
+
+    FileRow::trash(&photos, "a.txt", Utc::now().naive_utc()).await?;
+    assert!(!FileRow::is_trashed(&pool, "a.txt").await?);
+    assert_eq!(FileRow::query(&pool, &FileQuery::default()).await?.len(), 1);
+    assert_eq!(
+        FileRow::query(&photos, &FileQuery::default()).await?.len(),
+        1
+    );
+
+    FileRow::delete(&pool, "a.txt").await?;
+    assert!(FileRow::is_exists(&photos, "a.txt").await?);
+    assert_eq!(pool.buckets().await?, ["photos"]);
+    Ok(())
 }
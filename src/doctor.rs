@@ -0,0 +1,318 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Result};
+use notionfs::{
+    archive_block, create_new_block, get_file_by_signed_url, get_signed_file_urls,
+    notion::client::Notion, to_page_id,
+};
+use serde::Serialize;
+
+use crate::{
+    collection::Collection,
+    config::Config,
+    database::{connect, pending_migrations, FileQuery, FileRow, Pool},
+    output::{Printer, Record},
+};
+
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+/// 1 つの確認項目の結果
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// 失敗したときの直し方
+    pub hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, error: anyhow::Error, hint: &'static str) -> Check {
+        Check {
+            name,
+            status: Status::Fail,
+            detail: format!("{error:#}"),
+            hint: Some(hint),
+        }
+    }
+
+    fn skip(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Skip,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+}
+
+impl Record for Check {
+    fn to_text(&self) -> String {
+        let status = match self.status {
+            Status::Pass => "PASS",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        };
+        let mut text = format!("{status} {}: {}", self.name, self.detail);
+        if let Some(hint) = self.hint {
+            text.push_str(&format!("\n     hint: {hint}"));
+        }
+        text
+    }
+}
+
+struct Report<'a, W: Write> {
+    printer: &'a mut Printer<W>,
+    failed: usize,
+}
+
+impl<W: Write> Report<'_, W> {
+    fn add(&mut self, check: Check) -> Result<()> {
+        if check.status == Status::Fail {
+            self.failed += 1;
+        }
+        self.printer.print(&check)
+    }
+}
+
+/// 設定、データベース、Notion のトークンとページを順に確かめる
+pub async fn doctor(path: &Path, printer: &mut Printer<impl Write>) -> Result<()> {
+    let mut report = Report { printer, failed: 0 };
+
+    let config = match Config::open(path) {
+        Ok(config) => {
+            report.add(Check::pass("config", path.display().to_string()))?;
+            config
+        }
+        Err(e) => {
+            report.add(Check::fail(
+                "config",
+                e,
+                "Run `yukumo init` or pass the right file with --config.",
+            ))?;
+            bail!("1 check failed.");
+        }
+    };
+
+    if let Some(page) = config.database.host.strip_prefix("notion://") {
+        check_collection(&mut report, &config, page).await?;
+        check_notion(&mut report, &config).await?;
+        report.add(Check::skip(
+            "file_token",
+            "not checked with a Notion database catalog",
+        ))?;
+    } else {
+        let pool = check_database(&mut report, &config).await?;
+        check_notion(&mut report, &config).await?;
+        check_file_token(&mut report, &config, pool.as_ref()).await?;
+    }
+
+    if report.failed > 0 {
+        bail!("{} checks failed.", report.failed);
+    }
+    Ok(())
+}
+
+async fn check_database(
+    report: &mut Report<'_, impl Write>,
+    config: &Config,
+) -> Result<Option<Pool>> {
+    let pool = match connect(&config.database).await {
+        Ok(pool) => {
+            report.add(Check::pass("database", &config.database.host))?;
+            pool
+        }
+        Err(e) => {
+            report.add(Check::fail(
+                "database",
+                e,
+                database_hint(&config.database.host),
+            ))?;
+            report.add(Check::skip("migrations", "database is unreachable"))?;
+            return Ok(None);
+        }
+    };
+
+    match pending_migrations(&pool).await {
+        Ok(pending) if pending.is_empty() => {
+            report.add(Check::pass("migrations", "up to date"))?;
+        }
+        Ok(pending) => {
+            report.add(Check::fail(
+                "migrations",
+                anyhow::anyhow!("{} pending: {}", pending.len(), pending.join(", ")),
+                "Run `yukumo migrate` with a role that owns the schema.",
+            ))?;
+        }
+        Err(e) => {
+            report.add(Check::fail(
+                "migrations",
+                e,
+                "The database user needs to be able to read _sqlx_migrations.",
+            ))?;
+        }
+    }
+
+    Ok(Some(pool))
+}
+
+/// カタログに接続できないときの直し方 (database.host のスキームで変える)
+fn database_hint(host: &str) -> &'static str {
+    if host.starts_with("notion://") {
+        "Share the Notion database with your account and give it Origin (text), Size (number), Hash (text), Created (date) and File (file) properties."
+    } else if host.starts_with("sqlite://") && cfg!(feature = "sqlite") {
+        "Check that the directory of the SQLite file in database.host is writable."
+    } else if host.starts_with("sqlite://") {
+        "Rebuild yukumo with `--features sqlite`, or point database.host at PostgreSQL."
+    } else {
+        "Check database.host and that Postgres is running (`docker compose up -d database`)."
+    }
+}
+
+/// Notion のデータベースがカタログのときは、データベースとプロパティを確かめる
+async fn check_collection(
+    report: &mut Report<'_, impl Write>,
+    config: &Config,
+    page: &str,
+) -> Result<()> {
+    match Collection::open(config, page, true).await {
+        Ok(_) => report.add(Check::pass("database", &config.database.host))?,
+        Err(e) => report.add(Check::fail(
+            "database",
+            e,
+            database_hint(&config.database.host),
+        ))?,
+    }
+    report.add(Check::skip(
+        "migrations",
+        "the catalog is a Notion database",
+    ))
+}
+
+async fn check_notion(report: &mut Report<'_, impl Write>, config: &Config) -> Result<()> {
+    let client = Notion::new(
+        config.notion.token_v2.clone(),
+        config.notion.user_agent.clone(),
+    );
+
+    let page_id = match to_page_id(&config.notion.page_id) {
+        Ok(page_id) => page_id,
+        Err(e) => {
+            report.add(Check::skip("token_v2", "page-id is invalid"))?;
+            report.add(Check::fail(
+                "page",
+                e,
+                "notion.page-id must be a page ID or a notion.so page URL.",
+            ))?;
+            return Ok(());
+        }
+    };
+
+    let page = match client.get_page_data(page_id).await {
+        Ok(page) => {
+            report.add(Check::pass(
+                "token_v2",
+                format!("space \"{}\" ({})", page.space_name, page.space_domain),
+            ))?;
+            page
+        }
+        Err(e) => {
+            report.add(Check::fail(
+                "token_v2",
+                e,
+                "token_v2 may have expired. Copy the token_v2 cookie of www.notion.so and run `yukumo init`.",
+            ))?;
+            report.add(Check::skip("page", "token_v2 is not accepted"))?;
+            return Ok(());
+        }
+    };
+
+    // 使い捨てのブロックを作ってすぐアーカイブする
+    let writable = async {
+        let block_id = create_new_block(&client, &page.space_id, &page.page_id).await?;
+        archive_block(&client, &block_id, &page.space_id, &page.page_id).await
+    };
+    match writable.await {
+        Ok(()) => report.add(Check::pass("page", format!("{} is writable", page.page_id)))?,
+        Err(e) => report.add(Check::fail(
+            "page",
+            e,
+            "The owner of token_v2 needs edit access to the page.",
+        ))?,
+    }
+
+    Ok(())
+}
+
+async fn check_file_token(
+    report: &mut Report<'_, impl Write>,
+    config: &Config,
+    pool: Option<&Pool>,
+) -> Result<()> {
+    let Some(pool) = pool else {
+        return report.add(Check::skip("file_token", "database is unreachable"));
+    };
+    let query = FileQuery {
+        limit: Some(1),
+        ..Default::default()
+    };
+    let row = match FileRow::query(pool, &query).await {
+        Ok(rows) => rows.into_iter().next(),
+        Err(e) => {
+            return report.add(Check::fail(
+                "file_token",
+                e,
+                "Failed to read the catalog; check the migrations above.",
+            ))
+        }
+    };
+    let Some(row) = row else {
+        return report.add(Check::skip("file_token", "the catalog is empty"));
+    };
+
+    let client = Notion::new(
+        config.notion.token_v2.clone(),
+        config.notion.user_agent.clone(),
+    );
+    let fetched = async {
+        let urls =
+            get_signed_file_urls(&client, &[(&row.file_url, &row.block_id, &row.space_id)]).await?;
+        for url in urls {
+            get_file_by_signed_url(&url, &config.notion.file_token).await?;
+        }
+        anyhow::Ok(())
+    };
+    match fetched.await {
+        Ok(()) => report.add(Check::pass(
+            "file_token",
+            format!("downloaded {}", row.file_name),
+        )),
+        Err(e) => report.add(Check::fail(
+            "file_token",
+            e,
+            "file_token may have expired. Copy the file_token cookie of file.notion.so and run `yukumo init`.",
+        )),
+    }
+}
+
+#[test]
+fn test_database_hint() {
+    assert!(database_hint("postgres://localhost/yukumo").contains("Postgres"));
+    assert!(!database_hint("sqlite://~/catalog.db").contains("Postgres"));
+    assert!(database_hint("notion://abc").contains("Notion database"));
+}
@@ -0,0 +1,105 @@
+use std::{io, path::Path};
+
+use anyhow::{Context as _, Result};
+use futures::TryStreamExt as _;
+use notionfs::Response;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+};
+
+/// ファイルの SHA-256 を16進文字列で返す
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {path:?}"))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await.context("Failed to read file")?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// ダウンロードしながらサイズと SHA-256 を求める
+pub async fn hash_response(mut res: Response) -> Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = res.chunk().await.context("Failed to read response")? {
+        size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// ダウンロードしながら output に書き込み、サイズと SHA-256 を返す
+/// 中身をメモリに溜めず、途中で失敗したら output は作らない (一時ファイルに書いてから名前を変える)
+pub async fn save_response(res: Response, output: &Path) -> Result<(u64, String)> {
+    let mut part = output.as_os_str().to_owned();
+    part.push(".part");
+    let part = Path::new(&part);
+    let result = async {
+        let file = File::create(part)
+            .await
+            .with_context(|| format!("Failed to create {part:?}"))?;
+        let mut writer = BufWriter::new(file);
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut chunks = res.bytes_stream();
+        while let Some(chunk) = chunks.try_next().await.context("Failed to read response")? {
+            size += chunk.len() as u64;
+            hasher.update(&chunk);
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        tokio::fs::rename(part, output)
+            .await
+            .with_context(|| format!("Failed to save {output:?}"))?;
+        Ok((size, hex::encode(hasher.finalize())))
+    }
+    .await;
+    if result.is_err() {
+        tokio::fs::remove_file(part).await.ok();
+    }
+    result
+}
+
+/// 書き込みながらサイズと SHA-256 を求める
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: io::Write> HashWriter<W> {
+    pub fn new(inner: W) -> HashWriter<W> {
+        HashWriter {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// 書き込んだサイズと SHA-256
+    pub fn finish(self) -> (W, u64, String) {
+        (self.inner, self.size, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<W: io::Write> io::Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Write},
+    pin::pin,
+};
+
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use futures::TryStreamExt as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{AuditAction, AuditRow, FileRow},
+    output::{Printer, Record},
+    plan::{Action, Change},
+    source::FileSource,
+    storage::Storage,
+    tag::{parse_tag, Tag},
+};
+
+/// 同じ名前が既にあったときの扱い
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Conflict {
+    /// 既にあるほうを残す
+    Skip,
+    /// put --overwrite と同じく新しいバージョンとして置き換える
+    /// 既にあったバージョンは古いバージョンとして残し、retention や versions.keep で消す
+    Overwrite,
+    /// 何も書き込まずに止める
+    #[default]
+    Fail,
+}
+
+/// カタログの 1 行 (JSONL の 1 行)
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct IndexEntry {
+    #[serde(flatten)]
+    pub file: IndexFile,
+    /// `raw` や `project=x`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 古いバージョン (新しい順)
+    #[serde(default)]
+    pub versions: Vec<IndexFile>,
+}
+
+/// FileRow のすべてのカラム
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct IndexFile {
+    pub file_name: String,
+    pub file_url: String,
+    pub space_id: String,
+    pub block_id: String,
+    pub origin_file_path: String,
+    pub created_at: DateTime<Utc>,
+    pub size: Option<i64>,
+    pub mime: Option<String>,
+    pub hash: Option<String>,
+    pub version: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mode: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_uid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_gid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_symlink: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<FileRow> for IndexFile {
+    fn from(row: FileRow) -> IndexFile {
+        IndexFile {
+            file_name: row.file_name,
+            file_url: row.file_url,
+            space_id: row.space_id,
+            block_id: row.block_id,
+            origin_file_path: row.origin_file_path,
+            created_at: row.created_at.and_utc(),
+            size: row.size,
+            mime: row.mime,
+            hash: row.hash,
+            version: row.version,
+            source_host: row.source.host,
+            source_mtime: row.source.mtime.map(|mtime| mtime.and_utc()),
+            source_mode: row.source.mode,
+            source_uid: row.source.uid,
+            source_gid: row.source.gid,
+            source_symlink: row.source.symlink,
+            encoding: row.encoding,
+            deleted_at: row.deleted_at.map(|deleted_at| deleted_at.and_utc()),
+            expires_at: row.expires_at.map(|expires_at| expires_at.and_utc()),
+        }
+    }
+}
+
+impl From<IndexFile> for FileRow {
+    fn from(file: IndexFile) -> FileRow {
+        FileRow {
+            file_name: file.file_name,
+            file_url: file.file_url,
+            space_id: file.space_id,
+            block_id: file.block_id,
+            origin_file_path: file.origin_file_path,
+            created_at: file.created_at.naive_utc(),
+            size: file.size,
+            mime: file.mime,
+            hash: file.hash,
+            version: file.version,
+            source: FileSource {
+                host: file.source_host,
+                mtime: file.source_mtime.map(|mtime| mtime.naive_utc()),
+                mode: file.source_mode,
+                uid: file.source_uid,
+                gid: file.source_gid,
+                symlink: file.source_symlink,
+            },
+            encoding: file.encoding,
+            deleted_at: file.deleted_at.map(|deleted_at| deleted_at.naive_utc()),
+            expires_at: file.expires_at.map(|expires_at| expires_at.naive_utc()),
+        }
+    }
+}
+
+impl Record for IndexEntry {
+    fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// export でタグと古いバージョンをまとめて引く行数
+const EXPORT_BATCH: usize = 500;
+
+/// prefix で始まるファイルを古いバージョンやゴミ箱のものも含めて書き出す
+pub async fn export(
+    storage: &Storage,
+    prefix: &str,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    // タグと古いバージョンは EXPORT_BATCH 行ごとにまとめて引く
+    let mut rows = pin!(FileRow::all(&storage.pool, prefix).try_chunks(EXPORT_BATCH));
+    while let Some(rows) = rows.try_next().await.map_err(|e| e.1)? {
+        let names: Vec<String> = rows.iter().map(|row| row.file_name.clone()).collect();
+        let mut tags = FileRow::tags_of(&storage.pool, &names).await?;
+        let mut versions = FileRow::old_versions_of(&storage.pool, &names).await?;
+        for row in rows {
+            let tags = tags.remove(&row.file_name).unwrap_or_default();
+            let versions = versions.remove(&row.file_name).unwrap_or_default();
+            let entry = IndexEntry {
+                file: IndexFile::from(row),
+                tags: tags.iter().map(ToString::to_string).collect(),
+                versions: versions.into_iter().map(IndexFile::from).collect(),
+            };
+            printer.print(&entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// JSONL を読んでカタログに書き込む
+/// 途中で失敗したら何も書き込まない
+pub async fn import(
+    storage: &Storage,
+    reader: impl BufRead,
+    conflict: Conflict,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let (mut created, mut replaced, mut skipped) = (0, 0, 0);
+    let mut files: Vec<(FileRow, Vec<FileRow>, Vec<Tag>)> = Vec::new();
+    let mut reasons = Vec::new();
+    // 名前ごとに最初に出てきた行と、files に入れた位置
+    let mut seen: HashMap<String, (usize, Option<usize>)> = HashMap::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: IndexEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid entry at line {}", i + 1))?;
+        let tags = entry
+            .tags
+            .iter()
+            .map(|tag| parse_tag(tag))
+            .collect::<Result<Vec<Tag>>>()
+            .with_context(|| format!("Invalid tag at line {}", i + 1))?;
+        let name = entry.file.file_name.clone();
+        let size = entry.file.size;
+
+        // 同じ JSONL に同じ名前が 2 回あれば、カタログにあるときと同じく --on-conflict に従う
+        if let Some(&(first, index)) = seen.get(&name) {
+            let reason = format!("duplicate of line {}", first + 1);
+            let change = match conflict {
+                Conflict::Fail => bail!(
+                    "file_name ({name}) appears twice at lines {} and {}. Nothing was imported.",
+                    first + 1,
+                    i + 1
+                ),
+                Conflict::Skip => Change::new(Action::Skip, &name, reason),
+                Conflict::Overwrite => match index {
+                    // 前の行も取り込まなかったなら、この行も取り込まない
+                    None => Change::new(Action::Skip, &name, reason),
+                    // 前の行のバージョンを古いバージョンとして残して、後の行で置き換える
+                    Some(index) => {
+                        let mut row = FileRow::from(entry.file);
+                        let mut old_versions: Vec<_> =
+                            entry.versions.into_iter().map(FileRow::from).collect();
+                        let (earlier, earlier_versions, _) = &files[index];
+                        let existing = earlier_versions.iter().chain([earlier]).cloned().collect();
+                        merge_versions(&mut row, &mut old_versions, existing);
+                        let change = Change::new(Action::Overwrite, &name, reason).size(size);
+                        printer.print(&change)?;
+                        replaced += 1;
+                        files[index] = (row, old_versions, tags);
+                        reasons[index] = change.reason;
+                        continue;
+                    }
+                },
+            };
+            printer.print(&change.size(size))?;
+            skipped += 1;
+            continue;
+        }
+
+        let exists = FileRow::is_exists(&storage.pool, &name).await?;
+        let mut row = FileRow::from(entry.file);
+        let mut old_versions: Vec<_> = entry.versions.into_iter().map(FileRow::from).collect();
+        let change = match (exists, conflict) {
+            (false, _) => Change::new(Action::Create, &name, "new file"),
+            (true, Conflict::Overwrite) => {
+                let existing = FileRow::versions(&storage.pool, &name).await?;
+                let kept = merge_versions(&mut row, &mut old_versions, existing);
+                let reason = if kept > 0 {
+                    format!("already exists, {kept} versions kept as old versions")
+                } else {
+                    "already exists".to_string()
+                };
+                Change::new(Action::Overwrite, &name, reason)
+            }
+            (true, Conflict::Skip) => Change::new(Action::Skip, &name, "already exists"),
+            (true, Conflict::Fail) => {
+                bail!(
+                    "file_name ({name}) is already exists at line {}. Nothing was imported.",
+                    i + 1
+                )
+            }
+        };
+        let change = change.size(size);
+        printer.print(&change)?;
+        match change.action {
+            Action::Create => created += 1,
+            Action::Overwrite => replaced += 1,
+            _ => {
+                skipped += 1;
+                seen.insert(name, (i, None));
+                continue;
+            }
+        }
+
+        seen.insert(name, (i, Some(files.len())));
+        files.push((row, old_versions, tags));
+        reasons.push(change.reason);
+    }
+
+    if storage.dry_run {
+        log::info!("(dry run) {created} created, {replaced} overwritten, {skipped} skipped");
+        return Ok(());
+    }
+    FileRow::replace(&storage.pool, &files).await?;
+    for ((row, _, _), reason) in files.iter().zip(reasons) {
+        let entry = AuditRow::new(AuditAction::Import, &row.file_name)
+            .file(row)
+            .detail(reason);
+        storage.audit(entry).await;
+    }
+    // put --overwrite と同じく、増えた古いバージョンを保持の決まりで消す
+    for (row, old_versions, _) in &files {
+        if old_versions.is_empty() {
+            continue;
+        }
+        if let Some(keep) = storage.config.keep_versions(&row.file_name) {
+            storage.prune(&row.file_name, keep).await?;
+        }
+    }
+    log::info!("{created} created, {replaced} overwritten, {skipped} skipped");
+    Ok(())
+}
+
+/// 置き換える名前に既にあったバージョン (existing) のうち、取り込むものにないブロックを古いバージョンに足す
+/// カタログから消すと Notion のブロックが gc や usage から見えなくなるため
+/// 足したときは古いバージョンを作成日時の順に番号を付け直し、取り込む行をその次のバージョンにする
+/// 足したバージョンの数を返す
+fn merge_versions(
+    row: &mut FileRow,
+    old_versions: &mut Vec<FileRow>,
+    existing: Vec<FileRow>,
+) -> usize {
+    let imported: HashSet<_> = old_versions
+        .iter()
+        .chain([&*row])
+        .map(|row| row.block_id.clone())
+        .collect();
+    let displaced: Vec<_> = existing
+        .into_iter()
+        .filter(|old| !imported.contains(&old.block_id))
+        .collect();
+    let kept = displaced.len();
+    if kept == 0 {
+        return 0;
+    }
+    old_versions.extend(displaced);
+    old_versions.sort_by_key(|old| (old.created_at, old.version));
+    for (i, old) in old_versions.iter_mut().enumerate() {
+        old.version = i as i32 + 1;
+    }
+    row.version = old_versions.len() as i32 + 1;
+    kept
+}
+
+#[test]
+fn test_merge_versions() {
+    let row = |block_id: &str, version: i32, created_at: i64| FileRow {
+        file_name: "a.txt".to_string(),
+        file_url: String::new(),
+        space_id: String::new(),
+        block_id: block_id.to_string(),
+        origin_file_path: String::new(),
+        created_at: DateTime::from_timestamp(created_at, 0).unwrap().naive_utc(),
+        size: None,
+        mime: None,
+        hash: None,
+        version,
+        source: FileSource::default(),
+        encoding: None,
+        deleted_at: None,
+        expires_at: None,
+    };
+
+    // 同じカタログを取り込み直すだけなら何も足さない
+    let mut current = row("b2", 2, 20);
+    let mut old = vec![row("b1", 1, 10)];
+    let existing = vec![row("b2", 2, 20), row("b1", 1, 10)];
+    assert_eq!(merge_versions(&mut current, &mut old, existing), 0);
+    assert_eq!(current.version, 2);
+
+    // 既にあった b3 は古いバージョンとして残る
+    let mut current = row("b2", 2, 20);
+    let mut old = vec![row("b1", 1, 10)];
+    let existing = vec![row("b3", 1, 15)];
+    assert_eq!(merge_versions(&mut current, &mut old, existing), 1);
+    let blocks: Vec<_> = old
+        .iter()
+        .map(|r| (r.block_id.as_str(), r.version))
+        .collect();
+    assert_eq!(blocks, [("b1", 1), ("b3", 2)]);
+    assert_eq!(current.version, 3);
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_import_conflict() -> Result<()> {
+    use crate::{config::Config, output::OutputFormat};
+
+    let line = |name: &str, block_id: &str, created_at: i64| {
+        serde_json::json!({
+            "file_name": name,
+            "file_url": "",
+            "space_id": "",
+            "block_id": block_id,
+            "origin_file_path": "",
+            "created_at": DateTime::from_timestamp(created_at, 0).unwrap(),
+            "size": null,
+            "mime": null,
+            "hash": null,
+            "version": 1,
+        })
+        .to_string()
+    };
+    let jsonl = [
+        line("a.txt", "b1", 10),
+        line("b.txt", "b2", 10),
+        line("a.txt", "b3", 20),
+    ]
+    .join("\n");
+    let open = || async {
+        let config: Config = toml::from_str(
+            r#"
+            [database]
+            host = "sqlite://:memory:"
+
+            [notion]
+            token-v2 = ""
+            file-token = ""
+            page-id = ""
+            "#,
+        )?;
+        Storage::open(config, "default", false).await
+    };
+    let blocks = |storage: &Storage| {
+        let pool = storage.pool.clone();
+        async move {
+            let versions = FileRow::versions(&pool, "a.txt").await?;
+            anyhow::Ok(
+                versions
+                    .into_iter()
+                    .map(|row| (row.block_id, row.version))
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+    let mut printer = Printer::new(OutputFormat::Jsonl, Vec::new());
+
+    // 同じ名前が 2 回あれば何も取り込まない
+    let storage = open().await?;
+    assert!(
+        import(&storage, jsonl.as_bytes(), Conflict::Fail, &mut printer)
+            .await
+            .is_err()
+    );
+    assert!(FileRow::find(&storage.pool, "b.txt").await?.is_none());
+
+    // 後の行は取り込まない
+    let storage = open().await?;
+    import(&storage, jsonl.as_bytes(), Conflict::Skip, &mut printer).await?;
+    assert_eq!(blocks(&storage).await?, [("b1".to_string(), 1)]);
+
+    // 後の行が新しいバージョンになり、前の行は古いバージョンとして残る
+    let storage = open().await?;
+    import(
+        &storage,
+        jsonl.as_bytes(),
+        Conflict::Overwrite,
+        &mut printer,
+    )
+    .await?;
+    assert_eq!(
+        blocks(&storage).await?,
+        [("b3".to_string(), 2), ("b1".to_string(), 1)]
+    );
+
+    // カタログに既にある名前
+    let again = line("b.txt", "b4", 30);
+    assert!(
+        import(&storage, again.as_bytes(), Conflict::Fail, &mut printer)
+            .await
+            .is_err()
+    );
+    import(&storage, again.as_bytes(), Conflict::Skip, &mut printer).await?;
+    let row = FileRow::find_one(&storage.pool, "b.txt").await?;
+    assert_eq!(row.block_id, "b2");
+    import(
+        &storage,
+        again.as_bytes(),
+        Conflict::Overwrite,
+        &mut printer,
+    )
+    .await?;
+    let row = FileRow::find_one(&storage.pool, "b.txt").await?;
+    assert_eq!((row.block_id.as_str(), row.version), ("b4", 2));
+    Ok(())
+}
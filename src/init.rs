@@ -0,0 +1,199 @@
+use std::path::Path;
+
+use anyhow::{bail, Context as _, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password};
+use notionfs::{
+    get_block, get_block_property,
+    notion::{client::Notion, types::PageDataResponse},
+    to_page_id,
+};
+
+use crate::{
+    config::{Config, DatabaseConfig, NotionConfig},
+    database::create_pool,
+};
+
+const DEFAULT_DATABASE_HOST: &str = "postgres://localhost/yukumo";
+
+/// 対話的に設定ファイルを作る
+pub async fn init(path: &Path) -> Result<()> {
+    let theme = ColorfulTheme::default();
+    let existing = Config::open(path).ok();
+    if path.exists() {
+        let overwrite = Confirm::with_theme(&theme)
+            .with_prompt(format!("{} already exists. Overwrite?", path.display()))
+            .default(false)
+            .interact()?;
+        if !overwrite {
+            bail!("Aborted.");
+        }
+    }
+
+    let notion = prompt_notion(&theme, existing.as_ref().map(|c| &c.notion)).await?;
+    let database = prompt_database(&theme, existing.as_ref().map(|c| &c.database)).await?;
+
+    let (versions, tags, buckets, retention) = existing
+        .map(|c| (c.versions, c.tags, c.buckets, c.retention))
+        .unwrap_or_default();
+    let config = Config {
+        database,
+        notion,
+        versions,
+        tags,
+        buckets,
+        retention,
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create directory")?;
+    }
+    config
+        .save(path)
+        .with_context(|| format!("Failed to save config = {path:?}"))?;
+    log::info!("Saved {}", path.display());
+
+    Ok(())
+}
+
+async fn prompt_notion(
+    theme: &ColorfulTheme,
+    existing: Option<&NotionConfig>,
+) -> Result<NotionConfig> {
+    loop {
+        let token_v2 = prompt_secret(
+            theme,
+            "token_v2 (cookie of www.notion.so)",
+            existing.map(|c| c.token_v2.as_str()),
+        )?;
+        let file_token = prompt_secret(
+            theme,
+            "file_token (cookie of file.notion.so)",
+            existing.map(|c| c.file_token.as_str()),
+        )?;
+        let page = Input::<String>::with_theme(theme)
+            .with_prompt("Page ID or URL to store files")
+            .with_initial_text(existing.map(|c| c.page_id.as_str()).unwrap_or_default())
+            .interact_text()?;
+        let user_agent = Input::<String>::with_theme(theme)
+            .with_prompt("User-Agent (empty for default)")
+            .with_initial_text(
+                existing
+                    .and_then(|c| c.user_agent.as_deref())
+                    .unwrap_or_default(),
+            )
+            .allow_empty(true)
+            .interact_text()?;
+        let user_agent = Some(user_agent).filter(|ua| !ua.is_empty());
+
+        let page_id = match to_page_id(&page) {
+            Ok(page_id) => page_id,
+            Err(e) => {
+                log::error!("{e:#}");
+                continue;
+            }
+        };
+
+        let client = Notion::new(token_v2.clone(), user_agent.clone());
+        match check_page(&client, &page_id).await {
+            Ok((page, title)) => {
+                log::info!(
+                    "Found page \"{}\" in space \"{}\" ({})",
+                    title.as_deref().unwrap_or("Untitled"),
+                    page.space_name,
+                    page.space_domain
+                );
+                return Ok(NotionConfig {
+                    token_v2,
+                    file_token,
+                    page_id,
+                    user_agent,
+                });
+            }
+            Err(e) => {
+                log::error!("{e:#}");
+                if !retry(theme)? {
+                    bail!("Aborted.");
+                }
+            }
+        }
+    }
+}
+
+async fn prompt_database(
+    theme: &ColorfulTheme,
+    existing: Option<&DatabaseConfig>,
+) -> Result<DatabaseConfig> {
+    loop {
+        let host = Input::<String>::with_theme(theme)
+            .with_prompt("Database URL")
+            .with_initial_text(
+                existing
+                    .map(|c| c.host.as_str())
+                    .unwrap_or(DEFAULT_DATABASE_HOST),
+            )
+            .interact_text()?;
+        // 接続の設定は既にあるものを引き継ぐ
+        let config = match existing {
+            Some(existing) => DatabaseConfig {
+                host: host.clone(),
+                ..existing.clone()
+            },
+            None => DatabaseConfig::new(host.clone()),
+        };
+        // Notion のデータベースは doctor で確かめる
+        if host.starts_with("notion://") {
+            return Ok(config);
+        }
+        // 接続できればマイグレーションも走る (run-migrations = false なら走らない)
+        match create_pool(&config).await {
+            Ok(pool) => {
+                pool.close().await;
+                log::info!("Connected to {host}");
+                return Ok(config);
+            }
+            Err(e) => {
+                log::error!("{e:#}");
+                if !retry(theme)? {
+                    bail!("Aborted.");
+                }
+            }
+        }
+    }
+}
+
+/// ページにアクセスできるか確かめて、ページのタイトルも返す
+pub async fn check_page(
+    client: &Notion,
+    page_id: &str,
+) -> Result<(PageDataResponse, Option<String>)> {
+    let page = client
+        .get_page_data(page_id.to_string())
+        .await
+        .with_context(|| format!("Failed to get notion page {page_id}"))?;
+    let title = get_block(client, &page.page_id, &page.space_id)
+        .await?
+        .and_then(|block| get_block_property(&block, "title"));
+    Ok((page, title))
+}
+
+/// 空のまま入力されたら既存の値を使う
+fn prompt_secret(theme: &ColorfulTheme, prompt: &str, existing: Option<&str>) -> Result<String> {
+    let prompt = match existing {
+        Some(_) => format!("{prompt} [keep current]"),
+        None => prompt.to_string(),
+    };
+    let value = Password::with_theme(theme)
+        .with_prompt(prompt)
+        .allow_empty_password(existing.is_some())
+        .interact()?;
+    Ok(match existing {
+        Some(existing) if value.is_empty() => existing.to_string(),
+        _ => value,
+    })
+}
+
+fn retry(theme: &ColorfulTheme) -> Result<bool> {
+    Ok(Confirm::with_theme(theme)
+        .with_prompt("Try again?")
+        .default(true)
+        .interact()?)
+}
@@ -1,29 +1,70 @@
+mod backfill;
+mod codec;
+mod collection;
 mod config;
 mod database;
+mod doctor;
+mod hash;
+mod index;
+mod init;
+#[cfg(target_os = "linux")]
+mod mount;
+mod output;
+mod parse;
+mod plan;
+mod search;
+mod serve;
+mod source;
+mod storage;
+mod sync;
+mod tag;
+mod usage;
+mod verify;
+mod watch;
 
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::Duration,
+};
 
-use anyhow::{bail, Context, Result};
-use chrono::{Local, Utc};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{NaiveDateTime, Utc};
 use clap::Parser;
-use futures::{Stream, StreamExt};
+use futures::TryStreamExt as _;
 use home::home_dir;
-use indicatif::ProgressBar;
-use notionfs::{
-    attach_file_to_block, create_new_block, get_file_by_signed_url, get_file_stem,
-    get_signed_file_urls, get_signed_put_file,
-    notion::{client::Notion, types::PageDataResponse},
-    put_to_signed_url, to_dashed_id, Body,
-};
+use notionfs::get_file_stem;
 use shadow_rs::shadow;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use walkdir::WalkDir;
 
 use crate::{
-    config::Config,
-    database::{create_pool, FileRow},
+    backfill::backfill,
+    collection::Collection,
+    config::{Config, DEFAULT_BUCKET},
+    database::{
+        connect, migrate, pending_migrations, AuditRow, FileQuery, FileRow, Pattern, SortKey,
+    },
+    doctor::doctor,
+    index::{export, import, Conflict},
+    init::init,
+    output::{AuditEntry, FileEntry, OutputFormat, Printer, TagEntry, VersionEntry},
+    parse::{parse_datetime, parse_duration, parse_size},
+    plan::{Action, Change},
+    search::search,
+    serve::serve,
+    source::FileSource,
+    storage::Storage,
+    sync::{sync, SyncOptions},
+    tag::{parse_tag, Tag},
+    usage::{usage, UsageBy},
+    verify::verify,
+    watch::{watch, AfterUpload, WatchOptions},
 };
 
+#[cfg(target_os = "linux")]
+use crate::mount::{mount, MountOptions};
+
 shadow!(meta);
 
 #[derive(Parser)]
@@ -35,12 +76,32 @@ struct Cli {
     #[clap(short, long, global = true)]
     skip_on_failure: bool,
 
+    /// 操作するバケット (default 以外は設定ファイルの [buckets] に書く)
+    /// notion:// のカタログは行にバケットを持てないので default だけ
+    #[clap(long, global = true, env = "YUKUMO_BUCKET", default_value = DEFAULT_BUCKET)]
+    bucket: String,
+
+    /// 結果の出力フォーマット
+    // --output は get の保存先として前からあるので使わない
+    #[clap(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Notion にもカタログにも書き込まず、行う操作だけを表示する
+    #[clap(long, global = true)]
+    dry_run: bool,
+
     #[clap(subcommand)]
     subcommand: Subcommand,
 }
 
 #[derive(Parser)]
 enum Subcommand {
+    /// 対話的に設定ファイルを作る
+    Init,
+    /// 設定、データベース、Notion との接続を確かめる
+    Doctor,
+    /// まだ適用されていないマイグレーションを適用する (run-migrations = false でも走る)
+    Migrate,
     Put {
         source: PathBuf,
 
@@ -49,15 +110,332 @@ enum Subcommand {
 
         #[clap(short = 'n', long = "name")]
         file_name: Option<String>,
+
+        /// 同じ名前があれば新しいバージョンとして置き換える
+        #[clap(long)]
+        overwrite: bool,
+
+        /// ディレクトリの中をたどって、相対パスを名前にする
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// タグ (`raw`) かメタデータ (`project=x`)
+        #[clap(short, long = "tag", value_parser = parse_tag)]
+        tags: Vec<Tag>,
+
+        /// この期間 (`30d` など) が過ぎたら gc --expired で消す (指定しなければ retention の delete-after)
+        #[clap(long, value_parser = parse_duration)]
+        expires_in: Option<Duration>,
     },
-    Query {
-        prefix: String,
+    /// タグとメタデータを付け外しする
+    #[clap(subcommand)]
+    Tag(TagCommand),
+    /// ゴミ箱に入れる
+    Rm {
+        #[clap(required = true)]
+        file_names: Vec<String>,
     },
-    Get {
+    /// ゴミ箱から戻す
+    Restore {
         file_name: String,
+    },
+    /// ゴミ箱の一覧と削除
+    #[clap(subcommand)]
+    Trash(TrashCommand),
+    /// 誰がいつ何をしたかの記録 (新しい順)
+    Log {
+        /// このファイルについての記録
+        #[clap(long = "name")]
+        file_name: Option<String>,
+
+        /// この日時以降の記録
+        #[clap(long, value_parser = parse_datetime)]
+        since: Option<NaiveDateTime>,
 
         #[clap(short, long)]
-        output: PathBuf,
+        limit: Option<i64>,
+    },
+    /// カタログを JSONL で書き出す、読み込む
+    #[clap(subcommand)]
+    Index(IndexCommand),
+    /// 名前を変える
+    Mv {
+        from: String,
+        to: String,
+    },
+    Query(QueryArgs),
+    /// 名前、元ファイルのパス、タグとメタデータを全文検索する (関連度の高い順)
+    Search {
+        /// すべてを含むものを探す (語の前方一致)
+        #[clap(required = true)]
+        terms: Vec<String>,
+
+        #[clap(short, long, default_value_t = 20)]
+        limit: i64,
+    },
+    Get {
+        #[clap(required_unless_present = "prefix")]
+        file_name: Option<String>,
+
+        /// 保存先 (--prefix のときはディレクトリ)
+        #[clap(short = 'o', long = "output", visible_alias = "dest")]
+        dest: PathBuf,
+
+        /// 名前がこれで始まるファイルをまとめて、保存先の下にこれより後ろの名前で保存する
+        #[clap(long, conflicts_with_all = ["file_name", "version"])]
+        prefix: Option<String>,
+
+        /// 取得するバージョン (指定しなければ最新)
+        #[clap(long)]
+        version: Option<i32>,
+
+        /// put したときの元ファイルの更新日時とパーミッションに戻す
+        #[clap(long)]
+        preserve: bool,
+    },
+    /// ファイルのバージョンの一覧
+    Versions {
+        file_name: String,
+    },
+    /// カタログと Notion 上のブロックの情報を表示する
+    Stat {
+        file_name: String,
+
+        /// 表示するバージョン (指定しなければ最新)
+        #[clap(long)]
+        version: Option<i32>,
+    },
+    /// 古いバージョンを Notion 上でアーカイブしてカタログから消す
+    Gc {
+        #[clap(default_value = "")]
+        prefix: String,
+
+        /// 残す古いバージョンの数 (指定しなければ設定ファイルの retention か versions.keep)
+        #[clap(long)]
+        keep_versions: Option<u32>,
+
+        /// 古いバージョンの代わりに、期限が過ぎたファイルをすべてのバージョンごと消す (ゴミ箱のものも含む)
+        #[clap(long, conflicts_with = "keep_versions")]
+        expired: bool,
+    },
+    /// 古い行に足りないサイズ、MIME、ハッシュを埋める (止めても続きから再開できる)
+    Backfill {
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+    /// Notion 上の中身をダウンロードして、カタログのサイズとハッシュに合っているか確かめる
+    Verify {
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+    /// カタログを読み取り専用のディレクトリとしてマウントする (Linux のみ、Ctrl-C でアンマウント)
+    Mount {
+        mountpoint: PathBuf,
+
+        #[clap(short, long, default_value = "")]
+        prefix: String,
+
+        /// 読んだブロックを置くディレクトリ (指定しなければ ~/.cache/yukumo/blocks)
+        #[clap(long)]
+        cache_dir: Option<PathBuf>,
+
+        /// ブロックのキャッシュの上限
+        #[clap(long, default_value = "1GB", value_parser = parse_size)]
+        cache_size: u64,
+
+        /// カタログを読み直す間隔 (0 ならマウントしたときのまま)
+        #[clap(long, default_value = "5m", value_parser = parse_duration)]
+        refresh: Duration,
+    },
+    /// Notion に置いてあるファイルの数とバイト数をまとめる (古いバージョンとゴミ箱も含む)
+    Usage {
+        #[clap(default_value = "")]
+        prefix: String,
+
+        #[clap(long, value_enum, default_value_t)]
+        by: UsageBy,
+
+        /// --by prefix-depth でまとめる `/` 区切りの深さ
+        #[clap(long, default_value_t = 1)]
+        depth: usize,
+    },
+    /// カタログを読み書きする HTTP の API を立てる (認証はしない、Ctrl-C で止める)
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
+    /// ローカルのディレクトリをカタログに一方向で同期する
+    Sync {
+        dir: PathBuf,
+
+        #[clap(short, long, default_value = "")]
+        prefix: String,
+
+        /// サイズと更新日時が同じでもハッシュを比べる
+        #[clap(long)]
+        checksum: bool,
+
+        /// ローカルで消されたファイルをゴミ箱に入れる
+        #[clap(long)]
+        delete: bool,
+    },
+    /// ディレクトリに置かれたファイルを自動でアップロードする
+    Watch {
+        dir: PathBuf,
+
+        #[clap(short, long, default_value = "")]
+        prefix: String,
+
+        /// 名前のテンプレート ({path} {name} {stem} {ext} {date} {time})
+        #[clap(short = 'n', long = "name", default_value = "{path}")]
+        name_template: String,
+
+        /// サイズがこの時間変わらなければ書き込み完了とみなす
+        #[clap(long, default_value = "5s", value_parser = parse_duration)]
+        stable: Duration,
+
+        /// 起動時に既にあるファイルもアップロードする
+        #[clap(long)]
+        initial_scan: bool,
+
+        /// 同じ名前があれば新しいバージョンとして置き換える
+        #[clap(long)]
+        overwrite: bool,
+
+        /// アップロードしたファイルを削除する
+        #[clap(long, conflicts_with = "move_to")]
+        remove: bool,
+
+        /// アップロードしたファイルをこのディレクトリに移動する
+        #[clap(long)]
+        move_to: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Args)]
+struct QueryArgs {
+    /// 名前の前方一致
+    #[clap(default_value = "")]
+    prefix: String,
+
+    /// 名前を glob でしぼりこむ (`*` は `/` をまたがない)
+    #[clap(long, conflicts_with = "regex")]
+    glob: Option<String>,
+
+    /// 名前を正規表現でしぼりこむ
+    #[clap(long)]
+    regex: Option<String>,
+
+    /// 元ファイルのパスを glob でしぼりこむ
+    #[clap(long)]
+    origin: Option<String>,
+
+    /// この日時以降に作成されたもの
+    #[clap(long, value_parser = parse_datetime)]
+    since: Option<NaiveDateTime>,
+
+    /// この日時より前に作成されたもの
+    #[clap(long, value_parser = parse_datetime)]
+    until: Option<NaiveDateTime>,
+
+    /// 最小サイズ (`10MB` など)
+    #[clap(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// 最大サイズ (`10MB` など)
+    #[clap(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    #[clap(long, value_enum, default_value_t)]
+    sort: SortKey,
+
+    #[clap(short, long)]
+    reverse: bool,
+
+    /// この名前より後ろから (前のページの最後の名前を渡す)
+    #[clap(long)]
+    after: Option<String>,
+
+    #[clap(short, long)]
+    limit: Option<i64>,
+
+    /// このタグ (`raw`) かメタデータ (`project=x`) が付いているもの
+    #[clap(short, long = "tag", value_parser = parse_tag)]
+    tags: Vec<Tag>,
+}
+
+impl From<QueryArgs> for FileQuery {
+    fn from(args: QueryArgs) -> FileQuery {
+        FileQuery {
+            prefix: args.prefix,
+            name: args
+                .glob
+                .map(Pattern::Glob)
+                .or(args.regex.map(Pattern::Regex)),
+            origin: args.origin.map(Pattern::Glob),
+            since: args.since,
+            until: args.until,
+            min_size: args.min_size.map(|size| size as i64),
+            max_size: args.max_size.map(|size| size as i64),
+            sort: args.sort,
+            reverse: args.reverse,
+            after: args.after,
+            limit: args.limit,
+            tags: args.tags,
+        }
+    }
+}
+
+#[derive(Parser)]
+enum TagCommand {
+    /// タグを付ける (同じキーのメタデータは値を置き換える)
+    Add {
+        file_name: String,
+
+        #[clap(required = true, value_parser = parse_tag)]
+        tags: Vec<Tag>,
+    },
+    /// タグ名かメタデータのキーを指定して外す
+    Rm {
+        file_name: String,
+
+        #[clap(required = true)]
+        keys: Vec<String>,
+    },
+    /// 付いているタグの一覧
+    Ls { file_name: String },
+}
+
+#[derive(Parser)]
+enum TrashCommand {
+    /// ゴミ箱に入っているファイル (古い順)
+    List {
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+    /// Notion 上でアーカイブしてカタログから消す
+    Empty {
+        /// ゴミ箱に入れてからこれより経ったものだけ (`30d` など)
+        #[clap(long, value_parser = parse_duration)]
+        older_than: Option<Duration>,
+    },
+}
+
+#[derive(Parser)]
+enum IndexCommand {
+    /// 古いバージョン、タグ、ゴミ箱のものも含めて標準出力に書き出す
+    Export {
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+    /// export したファイルを読み込む
+    Import {
+        /// `-` なら標準入力
+        file: PathBuf,
+
+        /// 同じ名前が既にあったときの扱い
+        #[clap(long, value_enum, default_value_t)]
+        on_conflict: Conflict,
     },
 }
 
@@ -71,9 +449,6 @@ async fn main() -> Result<()> {
             .join("Yukumo.toml")
     });
 
-    let config =
-        Config::open(&path).with_context(|| format!("Failed to open config = {path:?}"))?;
-
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "yukumo=info");
     }
@@ -81,202 +456,624 @@ async fn main() -> Result<()> {
 
     log::debug!("Config path = {path:?}");
 
+    // 設定ファイルを作るので読み込む前に処理する
+    if let Subcommand::Init = cli.subcommand {
+        return init(&path).await;
+    }
+    // 設定ファイルが壊れていても診断したいので読み込む前に処理する
+    if let Subcommand::Doctor = cli.subcommand {
+        let mut printer = Printer::stdout(cli.format);
+        let result = doctor(&path, &mut printer).await;
+        printer.finish()?;
+        return result;
+    }
+
+    let config =
+        Config::open(&path).with_context(|| format!("Failed to open config = {path:?}"))?;
+
+    // notion:// なら Notion のデータベースをカタログにする
+    // データベースの行にはバケットの列がないので、default 以外のバケットは使えない
+    if let Some(page) = config.database.host.strip_prefix("notion://") {
+        ensure!(
+            cli.bucket == DEFAULT_BUCKET,
+            "Buckets other than default are not supported with a Notion database catalog."
+        );
+        // データベースの行には encoding の列もないので、戻し方を記録できない
+        let bucket = config.bucket(DEFAULT_BUCKET)?;
+        ensure!(
+            bucket.compression.is_none() && bucket.encryption_key_file.is_none(),
+            "Compression and encryption are not supported with a Notion database catalog."
+        );
+        let collection = Collection::open(&config, page, cli.dry_run).await?;
+        let mut printer = Printer::stdout(cli.format);
+        run_collection(
+            &collection,
+            cli.subcommand,
+            cli.skip_on_failure,
+            &mut printer,
+        )
+        .await?;
+        return printer.finish();
+    }
+
+    // スキーマを変えられないユーザーの設定でも Storage::open より前に走らせる
+    if let Subcommand::Migrate = cli.subcommand {
+        let pool = connect(&config.database).await?;
+        let result = if cli.dry_run {
+            pending_migrations(&pool).await
+        } else {
+            migrate(&pool).await
+        };
+        pool.close().await;
+        let migrations = result?;
+        if migrations.is_empty() {
+            log::info!("No pending migrations.");
+        }
+        let verb = if cli.dry_run { "Pending" } else { "Applied" };
+        for migration in migrations {
+            log::info!("{verb} {migration}");
+        }
+        return Ok(());
+    }
+
+    let storage = Storage::open(config, &cli.bucket, cli.dry_run).await?;
+    // export は import で読めるように常に JSONL にする
+    let format = match cli.subcommand {
+        Subcommand::Index(IndexCommand::Export { .. }) => OutputFormat::Jsonl,
+        _ => cli.format,
+    };
+    let mut printer = Printer::stdout(format);
+
     match cli.subcommand {
+        Subcommand::Init | Subcommand::Doctor | Subcommand::Migrate => unreachable!(),
         Subcommand::Put {
             source,
             file_name,
             prefix,
+            overwrite,
+            recursive,
+            tags,
+            expires_in,
         } => {
-            if source.is_file() {
-                put(config, source, file_name, prefix).await
-            } else if source.is_dir() {
-                let dir = source.read_dir().context("Failed to read directory.")?;
-                for entry in dir {
-                    if let Ok(entry) = entry {
-                        if let Err(e) =
-                            put(config.clone(), entry.path(), None, prefix.clone()).await
-                        {
-                            log::error!("Failed to put {}", entry.path().to_string_lossy());
-                            log::error!("{e:#?}");
-                            if !cli.skip_on_failure {
-                                bail!("Aborted by error.");
-                            }
+            let prefix = prefix.or_else(|| storage.bucket.prefix.clone());
+            for (path, name) in put_sources(source, file_name, prefix, recursive)? {
+                let result = if cli.dry_run {
+                    storage
+                        .plan_put(&path, &name, overwrite)
+                        .await
+                        .and_then(|plan| plan.iter().try_for_each(|c| printer.print(c)))
+                } else {
+                    storage
+                        .put(&path, &name, overwrite, &tags, expires_in, None)
+                        .await
+                        .and_then(|row| printer.print(&FileEntry::from(row)))
+                };
+                if let Err(e) = result {
+                    log::error!("Failed to put {}", path.to_string_lossy());
+                    log::error!("{e:#?}");
+                    if !cli.skip_on_failure {
+                        bail!("Aborted by error.");
+                    }
+                }
+            }
+        }
+        Subcommand::Rm { file_names } => {
+            for name in file_names {
+                if cli.dry_run {
+                    printer.print(&storage.plan_trash(&name).await?)?;
+                    continue;
+                }
+                match storage.trash(&name).await {
+                    Ok(row) => printer.print(&FileEntry::from(row))?,
+                    Err(e) => {
+                        log::error!("Failed to remove {name}");
+                        log::error!("{e:#?}");
+                        if !cli.skip_on_failure {
+                            bail!("Aborted by error.");
                         }
                     }
                 }
-                Ok(())
+            }
+        }
+        Subcommand::Restore { file_name } => {
+            printer.print(&FileEntry::from(storage.restore(&file_name).await?))?;
+        }
+        Subcommand::Trash(TrashCommand::List { prefix }) => {
+            for row in FileRow::trashed(&storage.pool, &prefix, None).await? {
+                printer.print(&FileEntry::from(row))?;
+            }
+        }
+        Subcommand::Trash(TrashCommand::Empty { older_than }) => {
+            let before = older_than
+                .map(chrono::Duration::from_std)
+                .transpose()?
+                .map(|older_than| Utc::now().naive_utc() - older_than);
+            for row in FileRow::trashed(&storage.pool, "", before).await? {
+                if cli.dry_run {
+                    for change in storage.plan_remove(&row.file_name).await? {
+                        printer.print(&change)?;
+                    }
+                    continue;
+                }
+                match storage.remove(&row.file_name).await {
+                    Ok(()) => printer.print(&FileEntry::from(row))?,
+                    Err(e) => {
+                        log::error!("Failed to remove {}", row.file_name);
+                        log::error!("{e:#?}");
+                        if !cli.skip_on_failure {
+                            bail!("Aborted by error.");
+                        }
+                    }
+                }
+            }
+        }
+        Subcommand::Log {
+            file_name,
+            since,
+            limit,
+        } => {
+            for row in AuditRow::query(&storage.pool, file_name.as_deref(), since, limit).await? {
+                printer.print(&AuditEntry::from(row))?;
+            }
+        }
+        Subcommand::Index(IndexCommand::Export { prefix }) => {
+            export(&storage, &prefix, &mut printer).await?
+        }
+        Subcommand::Index(IndexCommand::Import { file, on_conflict }) => {
+            if file == Path::new("-") {
+                import(&storage, std::io::stdin().lock(), on_conflict, &mut printer).await?
+            } else {
+                let reader = std::fs::File::open(&file)
+                    .with_context(|| format!("Failed to open {file:?}"))?;
+                let reader = std::io::BufReader::new(reader);
+                import(&storage, reader, on_conflict, &mut printer).await?
+            }
+        }
+        Subcommand::Mv { from, to } => {
+            if cli.dry_run {
+                printer.print(&storage.plan_rename(&from, &to).await?)?;
             } else {
-                bail!("Invalid path: {source:?}");
+                storage.rename(&from, &to).await?;
+                printer.print(&FileEntry::from(
+                    FileRow::find_one(&storage.pool, &to).await?,
+                ))?;
             }
         }
-        Subcommand::Query { prefix } => query(config, prefix).await,
-        Subcommand::Get { file_name, output } => get(config, file_name, output).await,
+        Subcommand::Tag(command) => {
+            let (file_name, tags) = match command {
+                TagCommand::Add { file_name, tags } => {
+                    let tags = storage.tag(&file_name, &tags, &[]).await?;
+                    (file_name, tags)
+                }
+                TagCommand::Rm { file_name, keys } => {
+                    let tags = storage.tag(&file_name, &[], &keys).await?;
+                    (file_name, tags)
+                }
+                TagCommand::Ls { file_name } => {
+                    FileRow::find_one(&storage.pool, &file_name).await?;
+                    let tags = FileRow::tags(&storage.pool, &file_name).await?;
+                    (file_name, tags)
+                }
+            };
+            for tag in tags {
+                printer.print(&TagEntry::new(&file_name, &tag))?;
+            }
+        }
+        Subcommand::Query(args) => self::query(&storage, args.into(), &mut printer).await?,
+        Subcommand::Search { terms, limit } => {
+            search(&storage, &terms, limit, &mut printer).await?
+        }
+        Subcommand::Get {
+            file_name: Some(file_name),
+            dest,
+            version,
+            preserve,
+            ..
+        } => {
+            let row = get(&storage, &file_name, &dest, version).await?;
+            if preserve {
+                preserve_source(&row, &dest)?;
+            }
+            printer.print(&FileEntry::from(row))?;
+        }
+        Subcommand::Get {
+            file_name: None,
+            dest,
+            prefix,
+            preserve,
+            ..
+        } => {
+            let query = FileQuery {
+                prefix: prefix.unwrap_or_default(),
+                ..Default::default()
+            };
+            let mut rows = pin!(FileRow::stream(&storage.pool, &query));
+            while let Some(row) = rows.try_next().await? {
+                let result = async {
+                    let output = bulk_dest(&dest, &query.prefix, &row.file_name)?;
+                    storage.download(&row, &output).await?;
+                    if preserve {
+                        preserve_source(&row, &output)?;
+                    }
+                    anyhow::Ok(())
+                }
+                .await;
+                match result {
+                    Ok(()) => printer.print(&FileEntry::from(row))?,
+                    Err(e) => {
+                        log::error!("Failed to get {}", row.file_name);
+                        log::error!("{e:#?}");
+                        if !cli.skip_on_failure {
+                            bail!("Aborted by error.");
+                        }
+                    }
+                }
+            }
+        }
+        Subcommand::Versions { file_name } => {
+            let versions = FileRow::versions(&storage.pool, &file_name).await?;
+            if versions.is_empty() {
+                bail!("file_name ({file_name}) is not found.");
+            }
+            for (i, row) in versions.into_iter().enumerate() {
+                printer.print(&VersionEntry::new(row, i == 0))?;
+            }
+        }
+        Subcommand::Stat { file_name, version } => {
+            let row = match version {
+                Some(version) => FileRow::find_version(&storage.pool, &file_name, version).await?,
+                None => FileRow::find_one(&storage.pool, &file_name).await?,
+            };
+            printer.print(&storage.stat(row).await?)?;
+        }
+        Subcommand::Gc {
+            prefix,
+            expired: true,
+            ..
+        } => {
+            for row in FileRow::expired(&storage.pool, &prefix, Utc::now().naive_utc()).await? {
+                if cli.dry_run {
+                    for change in storage.plan_remove(&row.file_name).await? {
+                        printer.print(&change)?;
+                    }
+                    continue;
+                }
+                match storage.expire(&row.file_name).await {
+                    Ok(()) => printer.print(&FileEntry::from(row))?,
+                    Err(e) => {
+                        log::error!("Failed to expire {}", row.file_name);
+                        log::error!("{e:#?}");
+                        if !cli.skip_on_failure {
+                            bail!("Aborted by error.");
+                        }
+                    }
+                }
+            }
+        }
+        Subcommand::Gc {
+            prefix,
+            keep_versions,
+            expired: false,
+        } => {
+            let config = &storage.config;
+            if keep_versions.is_none()
+                && config.versions.keep.is_none()
+                && config
+                    .retention
+                    .iter()
+                    .all(|rule| rule.keep_versions.is_none())
+            {
+                bail!("Specify --keep-versions, or versions.keep or retention keep-versions in config.");
+            }
+            for name in FileRow::versioned_names(&storage.pool, &prefix).await? {
+                let Some(keep) = keep_versions.or_else(|| config.keep_versions(&name)) else {
+                    continue;
+                };
+                if cli.dry_run {
+                    let stale = FileRow::stale_versions(&storage.pool, &name, keep as i64).await?;
+                    for row in stale {
+                        let reason = format!("version {}, exceeds {keep} versions", row.version);
+                        printer
+                            .print(&Change::new(Action::Delete, &name, reason).size(row.size))?;
+                    }
+                    continue;
+                }
+                for row in storage.prune(&name, keep).await? {
+                    printer.print(&VersionEntry::new(row, false))?;
+                }
+            }
+        }
+        Subcommand::Backfill { prefix } => {
+            backfill(&storage, &prefix, cli.skip_on_failure, &mut printer).await?
+        }
+        Subcommand::Verify { prefix } => {
+            verify(&storage, &prefix, cli.skip_on_failure, &mut printer).await?
+        }
+        #[cfg(target_os = "linux")]
+        Subcommand::Mount {
+            mountpoint,
+            prefix,
+            cache_dir,
+            cache_size,
+            refresh,
+        } => {
+            let cache_dir = cache_dir.unwrap_or_else(|| {
+                home_dir()
+                    .expect("Failed to get homedir")
+                    .join(".cache/yukumo/blocks")
+            });
+            let options = MountOptions {
+                prefix,
+                cache_dir,
+                cache_size,
+                refresh,
+            };
+            mount(&storage, &mountpoint, options).await?
+        }
+        #[cfg(not(target_os = "linux"))]
+        Subcommand::Mount { .. } => bail!("mount is only supported on Linux."),
+        Subcommand::Usage { prefix, by, depth } => {
+            usage(&storage, by, &prefix, depth, &mut printer).await?
+        }
+        Subcommand::Serve { listen } => serve(storage, listen).await?,
+        Subcommand::Sync {
+            dir,
+            prefix,
+            checksum,
+            delete,
+        } => {
+            let options = SyncOptions {
+                dir,
+                prefix,
+                checksum,
+                delete,
+                skip_on_failure: cli.skip_on_failure,
+            };
+            sync(&storage, &options, &mut printer).await?
+        }
+        Subcommand::Watch {
+            dir,
+            prefix,
+            name_template,
+            stable,
+            initial_scan,
+            overwrite,
+            remove,
+            move_to,
+        } => {
+            let after = match (remove, move_to) {
+                (true, _) => AfterUpload::Remove,
+                (false, Some(dest)) => AfterUpload::MoveTo(dest),
+                (false, None) => AfterUpload::Keep,
+            };
+            let options = WatchOptions {
+                dir,
+                prefix,
+                name_template,
+                stable,
+                initial_scan,
+                overwrite,
+                after,
+            };
+            watch(&storage, &options, &mut printer).await?
+        }
     }
-}
-
-async fn get(config: Config, file_name: String, output: PathBuf) -> Result<()> {
-    let pool = create_pool(&config.database.host).await?;
 
-    let FileRow {
-        file_url,
-        space_id,
-        block_id,
-        ..
-    } = FileRow::find_one(&pool, &file_name).await?;
-
-    let client = Notion::new(config.notion.token_v2, config.notion.user_agent);
-    log::debug!("UserAgent = {}", client.user_agent());
-
-    let signed_urls = get_signed_file_urls(&client, &[(&file_url, &block_id, &space_id)]).await?;
+    printer.finish()
+}
 
-    if let Some(parent) = output.parent() {
-        tokio::fs::create_dir_all(&parent).await?;
+/// Notion のデータベースがカタログのときに使えるサブコマンド
+async fn run_collection(
+    collection: &Collection,
+    subcommand: Subcommand,
+    skip_on_failure: bool,
+    printer: &mut Printer<impl std::io::Write>,
+) -> Result<()> {
+    match subcommand {
+        Subcommand::Put {
+            source,
+            file_name,
+            prefix,
+            overwrite,
+            recursive,
+            tags,
+            expires_in,
+        } => {
+            if !tags.is_empty() {
+                bail!("Tags are not supported with a Notion database catalog.");
+            }
+            if expires_in.is_some() {
+                bail!("Expiry is not supported with a Notion database catalog.");
+            }
+            for (path, name) in put_sources(source, file_name, prefix, recursive)? {
+                let result = if collection.dry_run {
+                    collection
+                        .plan_put(&path, &name, overwrite)
+                        .await
+                        .and_then(|plan| plan.iter().try_for_each(|c| printer.print(c)))
+                } else {
+                    collection
+                        .put(&path, &name, overwrite)
+                        .await
+                        .and_then(|row| printer.print(&FileEntry::from(row)))
+                };
+                if let Err(e) = result {
+                    log::error!("Failed to put {}", path.to_string_lossy());
+                    log::error!("{e:#?}");
+                    if !skip_on_failure {
+                        bail!("Aborted by error.");
+                    }
+                }
+            }
+        }
+        Subcommand::Query(args) => {
+            let query = FileQuery::from(args);
+            for row in query.apply(collection.rows().await?)? {
+                printer.print(&FileEntry::from(row))?;
+            }
+        }
+        Subcommand::Get {
+            file_name: Some(file_name),
+            dest,
+            version,
+            preserve,
+            ..
+        } => {
+            if version.is_some() {
+                bail!("Versions are not supported with a Notion database catalog.");
+            }
+            let row = collection.find_one(&file_name).await?;
+            collection.download(&row, &dest).await?;
+            if preserve {
+                preserve_source(&row, &dest)?;
+            }
+            printer.print(&FileEntry::from(row))?;
+        }
+        Subcommand::Get {
+            file_name: None,
+            dest,
+            prefix,
+            preserve,
+            ..
+        } => {
+            let query = FileQuery {
+                prefix: prefix.unwrap_or_default(),
+                ..Default::default()
+            };
+            for row in query.apply(collection.rows().await?)? {
+                let result = async {
+                    let output = bulk_dest(&dest, &query.prefix, &row.file_name)?;
+                    collection.download(&row, &output).await?;
+                    if preserve {
+                        preserve_source(&row, &output)?;
+                    }
+                    anyhow::Ok(())
+                }
+                .await;
+                match result {
+                    Ok(()) => printer.print(&FileEntry::from(row))?,
+                    Err(e) => {
+                        log::error!("Failed to get {}", row.file_name);
+                        log::error!("{e:#?}");
+                        if !skip_on_failure {
+                            bail!("Aborted by error.");
+                        }
+                    }
+                }
+            }
+        }
+        _ => bail!("This command is not supported with a Notion database catalog."),
     }
+    Ok(())
+}
 
-    for url in signed_urls {
-        let res = get_file_by_signed_url(&url, &config.notion.file_token).await?;
-        let bytes = res.bytes().await?;
-        tokio::fs::write(&output, bytes).await?;
-        log::info!("Saved {output:?}");
+async fn get(
+    storage: &Storage,
+    file_name: &str,
+    output: &Path,
+    version: Option<i32>,
+) -> Result<FileRow> {
+    let row = match version {
+        Some(version) => FileRow::find_version(&storage.pool, file_name, version).await?,
+        None => FileRow::find_one(&storage.pool, file_name).await?,
+    };
+    storage.download(&row, output).await?;
+    Ok(row)
+}
 
-        log::debug!("- {url}");
+/// get --prefix で name を保存するパス (name から prefix を除いた部分を dest の下に置く)
+/// `..` を含む名前は dest の外に出てしまうので保存しない
+fn bulk_dest(dest: &Path, prefix: &str, name: &str) -> Result<PathBuf> {
+    let relative = name.strip_prefix(prefix).unwrap_or(name);
+    let mut path = dest.to_path_buf();
+    for part in relative.split('/').filter(|part| !part.is_empty()) {
+        ensure!(
+            part != "." && part != "..",
+            "Refusing to save {name} outside {dest:?}"
+        );
+        path.push(part);
     }
+    ensure!(path != dest, "Refusing to save {name} as {dest:?}");
+    Ok(path)
+}
 
-    Ok(())
+/// 元ファイルの状態を記録していない行 (古い行や Notion のデータベース) は警告だけ出す
+fn preserve_source(row: &FileRow, output: &Path) -> Result<()> {
+    if row.source == FileSource::default() {
+        log::warn!(
+            "{} has no recorded source metadata; mtime and mode are not restored.",
+            row.file_name
+        );
+        return Ok(());
+    }
+    row.source.restore(output)
 }
 
-async fn put(
-    config: Config,
+/// put するファイルと、付ける名前の一覧
+fn put_sources(
     source: PathBuf,
-    name: Option<String>,
+    file_name: Option<String>,
     prefix: Option<String>,
-) -> Result<()> {
-    let pool = create_pool(&config.database.host).await?;
-
-    let client = Notion::new(config.notion.token_v2, config.notion.user_agent);
-    let page_id = to_dashed_id(&config.notion.page_id).context("Failed to convert dashed id")?;
-    let PageDataResponse {
-        owner_user_id,
-        page_id,
-        space_id,
-        ..
-    } = client
-        .get_page_data(page_id)
-        .await
-        .with_context(|| format!("Failed to get notion page {}", config.notion.page_id))?;
-
-    log::debug!("page_id = {page_id}");
-    log::debug!("space_id = {space_id}");
-    log::debug!("owner_user_id = {}", owner_user_id.as_deref().unwrap_or(""));
-
-    // 最初にブロックを作っとかないといけないっぽい
-    let new_block_id = create_new_block(&client, &space_id, &page_id).await?;
-
-    let name = if let Some(name) = name {
-        name
+    recursive: bool,
+) -> Result<Vec<(PathBuf, String)>> {
+    let prefix = prefix.unwrap_or_default();
+    if source.is_file() {
+        let name = file_name.map_or_else(|| get_file_stem(&source), Ok)?;
+        Ok(vec![(source, format!("{prefix}{name}"))])
+    } else if source.is_dir() {
+        list_dir(&source, &prefix, recursive)
     } else {
-        get_file_stem(&source)?
-    };
-    let name = prefix.map(|prefix| prefix + &name).unwrap_or(name);
-
-    if FileRow::is_exists(&pool, &name).await? {
-        bail!("file_name ({name}) is already exists.");
+        bail!("Invalid path: {source:?}");
     }
-
-    // 署名付きアップロードURLを取得して
-    let (url, signed_get_url, signed_put_url, mime, content_length) =
-        get_signed_put_file(&client, &source, &name, &new_block_id, &space_id).await?;
-
-    log::info!("block_id = {new_block_id}");
-    log::info!("space_id = {space_id}");
-    log::info!("url = {url}");
-    log::info!("signed_get_url = {signed_get_url}");
-    log::debug!("signed_put_url = {signed_put_url}");
-
-    let file = File::open(&source)
-        .await
-        .context("Failed to open input file")?;
-
-    let pb = ProgressBar::new(content_length);
-    let stream = create_upload_stream(file, pb);
-
-    put_to_signed_url(
-        &signed_put_url,
-        content_length,
-        &mime,
-        Body::wrap_stream(stream),
-    )
-    .await?;
-
-    // ブロックにファイルをくっつける
-    attach_file_to_block(
-        &client,
-        &new_block_id,
-        &space_id,
-        &url,
-        &name,
-        content_length,
-    )
-    .await?;
-
-    let row = FileRow {
-        file_url: url,
-        space_id,
-        block_id: new_block_id,
-        file_name: name,
-        origin_file_path: source
-            .canonicalize()
-            .unwrap_or(source)
-            .to_string_lossy()
-            .to_string(),
-        created_at: Utc::now().naive_utc(),
-    };
-
-    row.insert(&pool).await?;
-
-    log::info!(
-        "- {}: {} ({})",
-        row.file_name,
-        row.origin_file_path,
-        row.origin_file_path
-    );
-
-    Ok(())
 }
 
-async fn query(config: Config, prefix: String) -> Result<()> {
-    let pool = create_pool(&config.database.host).await?;
-    let files = FileRow::query(&pool, &prefix).await?;
-    for FileRow {
-        file_name,
-        origin_file_path,
-        created_at,
-        ..
-    } in files
+/// ディレクトリの中のファイルと、付ける名前の一覧
+fn list_dir(dir: &Path, prefix: &str, recursive: bool) -> Result<Vec<(PathBuf, String)>> {
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    let mut sources = Vec::new();
+    for entry in WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .sort_by_file_name()
     {
-        log::info!(
-            "- {file_name}: {origin_file_path} ({})",
-            created_at
-                .and_local_timezone(Local)
-                .single()
-                .unwrap()
-                .to_rfc3339()
-        );
+        let entry = entry.context("Failed to read directory.")?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir)?;
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        sources.push((entry.into_path(), format!("{prefix}{name}")));
     }
-    Ok(())
+    Ok(sources)
 }
 
-fn create_upload_stream(
-    file: File,
-    pb: ProgressBar,
-) -> impl Stream<Item = anyhow::Result<bytes::Bytes>> + 'static {
-    async_stream::try_stream! {
-        let mut stream = ReaderStream::new(file);
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.unwrap();
-            pb.inc(chunk.len() as u64);
-            yield chunk;
-        }
-        pb.finish();
+async fn query(
+    storage: &Storage,
+    query: FileQuery,
+    printer: &mut Printer<impl std::io::Write>,
+) -> Result<()> {
+    let mut rows = pin!(FileRow::stream(&storage.pool, &query));
+    while let Some(row) = rows.try_next().await? {
+        printer.print(&FileEntry::from(row))?;
     }
+    Ok(())
+}
+
+#[test]
+fn test_bulk_dest() {
+    let dest = Path::new("/tmp/out");
+    assert_eq!(
+        bulk_dest(dest, "photos/", "photos/2023/a.jpg").unwrap(),
+        Path::new("/tmp/out/2023/a.jpg")
+    );
+    assert_eq!(
+        bulk_dest(dest, "", "a//b.txt").unwrap(),
+        Path::new("/tmp/out/a/b.txt")
+    );
+    assert!(bulk_dest(dest, "photos/", "photos/../../etc/passwd").is_err());
+    assert!(bulk_dest(dest, "photos/", "photos/").is_err());
 }
@@ -0,0 +1,937 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context as _, Result};
+use chrono::NaiveDateTime;
+use fuser::{
+    AccessFlags, Errno, FileAttr, FileHandle, FileType, Filesystem, FopenFlags, Generation,
+    INodeNo, MountOption, Notifier, OpenAccMode, OpenFlags, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyXattr, Request,
+};
+use futures::{stream::FuturesUnordered, StreamExt as _, TryStreamExt as _};
+use notionfs::{get_file_range_by_signed_url, get_signed_file_urls, Client};
+use tokio::{io::AsyncReadExt as _, sync::mpsc};
+
+use crate::{
+    codec::Spool,
+    database::{FileQuery, FileRow, Pool},
+    storage::Storage,
+};
+
+/// Notion から取ってきてキャッシュする単位
+const BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// カーネルに名前と属性を覚えておいてもらう時間
+/// 読み直したカタログで消えた名前は、これだけ経つまで見えていることがある
+const TTL: Duration = Duration::from_secs(60);
+
+/// 署名付き URL を使い回す時間
+const SIGNED_URL_TTL: Duration = Duration::from_secs(10 * 60);
+
+const ROOT: u64 = INodeNo::ROOT.0;
+
+pub struct MountOptions {
+    /// この前置きで始まる名前だけを見せる
+    pub prefix: String,
+    /// 読んだブロックを置くディレクトリ
+    pub cache_dir: PathBuf,
+    /// キャッシュの上限 (バイト)
+    pub cache_size: u64,
+    /// カタログを読み直す間隔 (0 なら読み直さない)
+    pub refresh: Duration,
+}
+
+/// カタログの名前を `/` で区切ってディレクトリにし、読み取り専用でマウントする
+/// 中身は読まれたときに Notion から BLOCK_SIZE ずつ取ってくる
+/// Ctrl-C かアンマウントで終わる
+pub async fn mount(storage: &Storage, mountpoint: &Path, options: MountOptions) -> Result<()> {
+    let tree = Tree::load(&storage.pool, &options.prefix, Tree::new()).await?;
+    log::info!("{} files, {} bytes", tree.files, tree.bytes);
+    let tree = Arc::new(RwLock::new(tree));
+    let reader = Reader {
+        storage,
+        http: Client::new(),
+        cache: BlockCache::open(options.cache_dir, options.cache_size)?,
+        urls: Mutex::new(HashMap::new()),
+        decoding: tokio::sync::Mutex::new(()),
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let catalog = Catalog {
+        tree: tree.clone(),
+        reads: tx,
+    };
+    let mut config = fuser::Config::default();
+    config.mount_options = vec![
+        MountOption::RO,
+        MountOption::NoSuid,
+        MountOption::NoDev,
+        MountOption::FSName("yukumo".to_string()),
+        MountOption::Subtype("yukumo".to_string()),
+    ];
+    // マウントしてカーネルと INIT をやり取りするまで待つ
+    let path = mountpoint.to_path_buf();
+    let session = tokio::task::spawn_blocking(move || fuser::spawn_mount(catalog, path, &config))
+        .await?
+        .with_context(|| format!("Failed to mount {mountpoint:?}"))?;
+    log::info!("Mounted on {mountpoint:?}. Press Ctrl-C to unmount.");
+
+    let refresher = (!options.refresh.is_zero()).then(|| {
+        tokio::spawn(refresh(
+            storage.pool.clone(),
+            options.prefix,
+            tree.clone(),
+            session.notifier(),
+            options.refresh,
+        ))
+    });
+    let result = serve(&tree, &reader, rx).await;
+    if let Some(refresher) = refresher {
+        refresher.abort();
+    }
+    // Ctrl-C か serve の失敗で終わったときはアンマウントしてから、fuser のスレッドが終わるのを待つ
+    let unmounted = matches!(result, Ok(true));
+    let joined = tokio::task::spawn_blocking(move || match unmounted {
+        true => session.join(),
+        false => session.umount_and_join(),
+    })
+    .await?;
+    result?;
+    joined.context("Failed to unmount")
+}
+
+/// READ を受け取って Notion から読み、終わったものから返事をする
+/// アンマウントされて終わったときは true、Ctrl-C で終わったときは false
+async fn serve(
+    tree: &RwLock<Tree>,
+    reader: &Reader<'_>,
+    mut rx: mpsc::UnboundedReceiver<Read>,
+) -> Result<bool> {
+    let mut reads = FuturesUnordered::new();
+    let mut ctrl_c = pin!(tokio::signal::ctrl_c());
+    loop {
+        tokio::select! {
+            read = rx.recv() => {
+                // アンマウントされた
+                let Some(read) = read else {
+                    return Ok(true);
+                };
+                reads.push(reader.read(tree, read));
+            }
+            Some(()) = reads.next(), if !reads.is_empty() => {}
+            result = &mut ctrl_c => {
+                result?;
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// period ごとにカタログを読み直して tree を置き換える
+/// カーネルが覚えている名前と中身のうち、変わったものは忘れてもらう
+async fn refresh(
+    pool: Pool,
+    prefix: String,
+    tree: Arc<RwLock<Tree>>,
+    notifier: Notifier,
+    period: Duration,
+) {
+    loop {
+        tokio::time::sleep(period).await;
+        let next = tree.read().unwrap().next();
+        let next = match Tree::load(&pool, &prefix, next).await {
+            Ok(next) => next,
+            Err(e) => {
+                log::warn!("Failed to reload the catalog: {e:#}");
+                continue;
+            }
+        };
+        log::debug!("Reloaded {} files, {} bytes", next.files, next.bytes);
+        let (entries, inodes) = tree.read().unwrap().changes(&next);
+        *tree.write().unwrap() = next;
+        // カーネルが覚えていないものは失敗するので無視する
+        for (parent, name) in entries {
+            let _ = notifier.inval_entry(INodeNo(parent), OsStr::new(&name));
+        }
+        for ino in inodes {
+            let _ = notifier.inval_inode(INodeNo(ino), 0, 0);
+        }
+    }
+}
+
+/// Notion から読む READ の要求
+struct Read {
+    ino: u64,
+    offset: u64,
+    size: u32,
+    reply: ReplyData,
+}
+
+/// カーネルからの要求に fuser のスレッドで答える
+/// READ は serve に渡し、ほかはその場で tree から返事をする
+struct Catalog {
+    tree: Arc<RwLock<Tree>>,
+    reads: mpsc::UnboundedSender<Read>,
+}
+
+impl Filesystem for Catalog {
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let tree = self.tree.read().unwrap();
+        let ino = tree
+            .node(parent.0)
+            .ok_or(Errno::ENOENT)
+            .and_then(|parent| tree.lookup(parent, name));
+        match ino {
+            Ok(ino) => reply.entry(&TTL, &tree.attr(ino), Generation(0)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        let tree = self.tree.read().unwrap();
+        match tree.node(ino.0) {
+            Some(_) => reply.attr(&TTL, &tree.attr(ino.0)),
+            None => reply.error(Errno::ENOENT),
+        }
+    }
+
+    fn open(&self, _req: &Request, ino: INodeNo, flags: OpenFlags, reply: ReplyOpen) {
+        let tree = self.tree.read().unwrap();
+        match tree.node(ino.0).map(|node| &node.kind) {
+            None => reply.error(Errno::ENOENT),
+            Some(Kind::Dir(_)) => reply.error(Errno::EISDIR),
+            Some(Kind::File(_)) if flags.acc_mode() != OpenAccMode::O_RDONLY => {
+                reply.error(Errno::EROFS)
+            }
+            // 読み直して中身が変わったときはカーネルに忘れてもらうので、開き直してもページキャッシュを使う
+            // サイズが分からなければ、読めたところまでを返せるようにキャッシュしない
+            Some(Kind::File(row)) => match row.size {
+                Some(_) => reply.opened(FileHandle(0), FopenFlags::FOPEN_KEEP_CACHE),
+                None => reply.opened(FileHandle(0), FopenFlags::FOPEN_DIRECT_IO),
+            },
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<fuser::LockOwner>,
+        reply: ReplyData,
+    ) {
+        let read = Read {
+            ino: ino.0,
+            offset,
+            size,
+            reply,
+        };
+        // serve が終わっていれば読めない
+        if let Err(mpsc::error::SendError(read)) = self.reads.send(read) {
+            read.reply.error(Errno::EIO);
+        }
+    }
+
+    fn opendir(&self, _req: &Request, ino: INodeNo, _flags: OpenFlags, reply: ReplyOpen) {
+        let tree = self.tree.read().unwrap();
+        match tree.node(ino.0).map(|node| &node.kind) {
+            None => reply.error(Errno::ENOENT),
+            Some(Kind::Dir(_)) => reply.opened(FileHandle(0), FopenFlags::empty()),
+            Some(Kind::File(_)) => reply.error(Errno::ENOTDIR),
+        }
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let tree = self.tree.read().unwrap();
+        let Some(node) = tree.node(ino.0) else {
+            return reply.error(Errno::ENOENT);
+        };
+        let Kind::Dir(children) = &node.kind else {
+            return reply.error(Errno::ENOTDIR);
+        };
+        let entries = [(".", ino.0), ("..", node.parent)]
+            .into_iter()
+            .chain(children.iter().map(|(name, &ino)| (name.as_str(), ino)));
+        // offset は次のエントリの位置
+        for (i, (name, ino)) in entries.enumerate().skip(offset as usize) {
+            if reply.add(INodeNo(ino), i as u64 + 1, tree.attr(ino).kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn flush(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _lock_owner: fuser::LockOwner,
+        reply: ReplyEmpty,
+    ) {
+        reply.ok();
+    }
+
+    // 拡張属性はないので、カーネルに聞かれなくなるように未対応と返す
+    fn getxattr(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _name: &OsStr,
+        _size: u32,
+        reply: ReplyXattr,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    fn listxattr(&self, _req: &Request, _ino: INodeNo, _size: u32, reply: ReplyXattr) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    fn statfs(&self, _req: &Request, _ino: INodeNo, reply: ReplyStatfs) {
+        const BLOCK_SIZE: u32 = 4096;
+        let tree = self.tree.read().unwrap();
+        let blocks = tree.bytes.div_ceil(BLOCK_SIZE as u64);
+        reply.statfs(blocks, 0, 0, tree.files, 0, BLOCK_SIZE, 255, BLOCK_SIZE);
+    }
+
+    fn access(&self, _req: &Request, _ino: INodeNo, mask: AccessFlags, reply: ReplyEmpty) {
+        if mask.contains(AccessFlags::W_OK) {
+            reply.error(Errno::EROFS);
+        } else {
+            reply.ok();
+        }
+    }
+}
+
+/// カタログの名前をディレクトリに並べたもの
+/// 読み直しても、同じ名前のディレクトリとファイルは同じ inode にする
+struct Tree {
+    nodes: HashMap<u64, Node>,
+    /// パス (ディレクトリは `a/b/`、ファイルは `a/b.txt`) ごとの inode
+    inodes: HashMap<String, u64>,
+    /// 読み直す前の inodes
+    previous: HashMap<String, u64>,
+    /// まだ使っていない inode
+    next_ino: u64,
+    uid: u32,
+    gid: u32,
+    files: u64,
+    bytes: u64,
+}
+
+struct Node {
+    parent: u64,
+    /// ファイルは元ファイルの更新日時 (記録がなければ put した日時)、ディレクトリは中でいちばん新しいもの
+    mtime: NaiveDateTime,
+    kind: Kind,
+}
+
+enum Kind {
+    Dir(BTreeMap<String, u64>),
+    File(Arc<FileRow>),
+}
+
+impl Tree {
+    fn new() -> Tree {
+        let root = Node {
+            parent: ROOT,
+            mtime: NaiveDateTime::default(),
+            kind: Kind::Dir(BTreeMap::new()),
+        };
+        Tree {
+            nodes: HashMap::from([(ROOT, root)]),
+            inodes: HashMap::from([(String::new(), ROOT)]),
+            previous: HashMap::new(),
+            next_ino: ROOT + 1,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            files: 0,
+            bytes: 0,
+        }
+    }
+
+    /// 読み直すときに使う空の Tree (この Tree の inode を引き継ぐ)
+    fn next(&self) -> Tree {
+        Tree {
+            previous: self.inodes.clone(),
+            next_ino: self.next_ino,
+            ..Tree::new()
+        }
+    }
+
+    /// prefix で始まるファイルを tree に読み込む
+    /// prefix の最後の `/` までを取り除いた名前を並べる
+    async fn load(pool: &Pool, prefix: &str, mut tree: Tree) -> Result<Tree> {
+        let base = prefix.rfind('/').map_or("", |i| &prefix[..=i]);
+        let query = FileQuery {
+            prefix: prefix.to_string(),
+            ..Default::default()
+        };
+        let mut rows = pin!(FileRow::stream(pool, &query));
+        while let Some(row) = rows.try_next().await? {
+            let path = row.file_name[base.len()..].to_string();
+            if let Err(reason) = tree.insert(&path, row) {
+                log::warn!("Skipped {path}: {reason}");
+            }
+        }
+        tree.previous = HashMap::new();
+        Ok(tree)
+    }
+
+    /// path の途中のディレクトリを作ってファイルを置く
+    fn insert(&mut self, path: &str, row: FileRow) -> Result<(), &'static str> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let Some((file_name, dirs)) = segments.split_last() else {
+            return Err("empty name");
+        };
+        if segments.iter().any(|&s| s == "." || s == "..") {
+            return Err("`.` or `..` in name");
+        }
+        let mut parent = ROOT;
+        for (i, dir) in dirs.iter().enumerate() {
+            parent = match self.child(parent, dir) {
+                Some(ino) if matches!(self.nodes[&ino].kind, Kind::Dir(_)) => ino,
+                Some(_) => return Err("a parent directory is also a file"),
+                None => {
+                    let key = format!("{}/", segments[..=i].join("/"));
+                    self.push(parent, dir, key, Kind::Dir(BTreeMap::new()))
+                }
+            };
+        }
+        if self.child(parent, file_name).is_some() {
+            return Err("a directory with the same name exists");
+        }
+        let mtime = row.source.mtime.unwrap_or(row.created_at);
+        self.files += 1;
+        self.bytes += row.size.unwrap_or_default() as u64;
+        let key = segments.join("/");
+        let ino = self.push(parent, file_name, key, Kind::File(Arc::new(row)));
+        // ファイルとディレクトリの更新日時を中のいちばん新しいファイルに合わせる
+        let mut ino = ino;
+        loop {
+            let node = self.nodes.get_mut(&ino).unwrap();
+            node.mtime = node.mtime.max(mtime);
+            if ino == ROOT {
+                break;
+            }
+            ino = node.parent;
+        }
+        Ok(())
+    }
+
+    fn child(&self, parent: u64, name: &str) -> Option<u64> {
+        match &self.node(parent)?.kind {
+            Kind::Dir(children) => children.get(name).copied(),
+            Kind::File(_) => None,
+        }
+    }
+
+    /// 読み直した next と比べて、消えたか別のものになった (親の inode, 名前) と、
+    /// 中身か属性が変わった inode
+    fn changes(&self, next: &Tree) -> (Vec<(u64, String)>, Vec<u64>) {
+        let mut entries = Vec::new();
+        let mut inodes = Vec::new();
+        for (&ino, node) in &self.nodes {
+            if let Kind::Dir(children) = &node.kind {
+                for (name, &child) in children {
+                    if next.child(ino, name) != Some(child) {
+                        entries.push((ino, name.clone()));
+                    }
+                }
+            }
+            let Some(new) = next.node(ino) else {
+                continue;
+            };
+            let changed = match (&node.kind, &new.kind) {
+                (Kind::File(old), Kind::File(new)) => {
+                    old.block_id != new.block_id || old.size != new.size
+                }
+                _ => false,
+            };
+            if changed || node.mtime != new.mtime {
+                inodes.push(ino);
+            }
+        }
+        (entries, inodes)
+    }
+
+    /// key に前と同じ inode (なければ新しい inode) を割り当てて置く
+    fn push(&mut self, parent: u64, name: &str, key: String, kind: Kind) -> u64 {
+        let ino = match self.previous.get(&key) {
+            Some(&ino) => ino,
+            None => {
+                self.next_ino += 1;
+                self.next_ino - 1
+            }
+        };
+        self.inodes.insert(key, ino);
+        self.nodes.insert(
+            ino,
+            Node {
+                parent,
+                mtime: NaiveDateTime::default(),
+                kind,
+            },
+        );
+        if let Some(Node {
+            kind: Kind::Dir(children),
+            ..
+        }) = self.nodes.get_mut(&parent)
+        {
+            children.insert(name.to_string(), ino);
+        }
+        ino
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get(&ino)
+    }
+
+    fn lookup(&self, parent: &Node, name: &OsStr) -> Result<u64, Errno> {
+        let Kind::Dir(children) = &parent.kind else {
+            return Err(Errno::ENOTDIR);
+        };
+        name.to_str()
+            .and_then(|name| children.get(name))
+            .copied()
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn attr(&self, ino: u64) -> FileAttr {
+        let node = &self.nodes[&ino];
+        let (size, kind, perm, nlink) = match &node.kind {
+            Kind::Dir(_) => (0, FileType::Directory, 0o555, 2),
+            Kind::File(row) => (
+                row.size.unwrap_or_default() as u64,
+                FileType::RegularFile,
+                0o444,
+                1,
+            ),
+        };
+        let mtime = SystemTime::from(node.mtime.and_utc());
+        FileAttr {
+            ino: INodeNo(ino),
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+}
+
+/// ファイルの中身を Notion から読む
+struct Reader<'a> {
+    storage: &'a Storage,
+    http: Client,
+    cache: BlockCache,
+    /// block_id ごとの署名付き URL と取得した時刻
+    urls: Mutex<HashMap<String, (String, Instant)>>,
+    /// 圧縮や暗号化したファイルを戻している間だけ持つ
+    decoding: tokio::sync::Mutex<()>,
+}
+
+impl Reader<'_> {
+    /// 読んで返事をする
+    /// 読み直したカタログで消えたファイルは、開いたままでも読めなくなる
+    /// (新しいバージョンになったファイルは、開いたままでも新しいほうを読む)
+    async fn read(&self, tree: &RwLock<Tree>, read: Read) {
+        let row = match tree.read().unwrap().node(read.ino).map(|node| &node.kind) {
+            Some(Kind::File(row)) => row.clone(),
+            _ => return read.reply.error(Errno::ENOENT),
+        };
+        match self.read_range(&row, read.offset, read.size as u64).await {
+            Ok(data) => read.reply.data(&data),
+            Err(e) => {
+                log::error!("Failed to read {}: {e:#}", row.file_name);
+                read.reply.error(Errno::EIO);
+            }
+        }
+    }
+
+    /// offset から size バイト (ファイルの終わりまで)
+    async fn read_range(&self, row: &FileRow, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let end = match row.size {
+            Some(file_size) => (offset + size).min(file_size as u64),
+            None => offset + size,
+        };
+        let mut data = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut position = offset;
+        while position < end {
+            let index = position / BLOCK_SIZE;
+            let block = self.block(row, index).await?;
+            let start = (position - index * BLOCK_SIZE) as usize;
+            if start >= block.len() {
+                break;
+            }
+            let stop = ((end - index * BLOCK_SIZE) as usize).min(block.len());
+            data.extend_from_slice(&block[start..stop]);
+            // 足りないブロックはファイルの終わり
+            if (block.len() as u64) < BLOCK_SIZE {
+                break;
+            }
+            position = (index + 1) * BLOCK_SIZE;
+        }
+        Ok(data)
+    }
+
+    /// index 番目のブロック (キャッシュになければ取ってきて置く)
+    async fn block(&self, row: &FileRow, index: u64) -> Result<Vec<u8>> {
+        if let Some(block) = self.cache.get(&row.block_id, index).await {
+            return Ok(block);
+        }
+        if let Some(encoding) = &row.encoding {
+            return self.decoded_block(row, encoding, index).await;
+        }
+        let start = index * BLOCK_SIZE;
+        let end = match row.size {
+            Some(file_size) => (start + BLOCK_SIZE).min(file_size as u64),
+            None => start + BLOCK_SIZE,
+        };
+        let block = match self.fetch(row, start..end, false).await {
+            Ok(block) => block,
+            // 署名付き URL の期限が切れているかもしれないので取り直す
+            Err(e) => {
+                log::debug!("Retry {} with new signed url: {e:#}", row.file_name);
+                self.fetch(row, start..end, true).await?
+            }
+        };
+        if let Err(e) = self.cache.put(&row.block_id, index, block.clone()).await {
+            log::warn!("Failed to cache block: {e:#}");
+        }
+        Ok(block)
+    }
+
+    /// 圧縮や暗号化したファイルは途中から戻せないので、全体を戻してブロックごとにキャッシュに置く
+    async fn decoded_block(&self, row: &FileRow, encoding: &str, index: u64) -> Result<Vec<u8>> {
+        // 同じファイルを並んで何度も戻さないように一つずつ戻す
+        let _decoding = self.decoding.lock().await;
+        if let Some(block) = self.cache.get(&row.block_id, index).await {
+            return Ok(block);
+        }
+        let spool = Spool::new();
+        self.storage
+            .download_decoded(row, encoding, spool.path())
+            .await?;
+        let mut file = tokio::fs::File::open(spool.path()).await?;
+        let mut wanted = Vec::new();
+        for i in 0.. {
+            let mut block = Vec::with_capacity(BLOCK_SIZE as usize);
+            (&mut file).take(BLOCK_SIZE).read_to_end(&mut block).await?;
+            if block.is_empty() && i > 0 {
+                break;
+            }
+            let last = (block.len() as u64) < BLOCK_SIZE;
+            if i == index {
+                wanted = block.clone();
+            }
+            if let Err(e) = self.cache.put(&row.block_id, i, block).await {
+                log::warn!("Failed to cache block: {e:#}");
+            }
+            if last {
+                break;
+            }
+        }
+        Ok(wanted)
+    }
+
+    async fn fetch(
+        &self,
+        row: &FileRow,
+        range: std::ops::Range<u64>,
+        refresh: bool,
+    ) -> Result<Vec<u8>> {
+        let url = self.signed_url(row, refresh).await?;
+        let file_token = &self.storage.config.notion.file_token;
+        get_file_range_by_signed_url(&self.http, &url, file_token, range).await
+    }
+
+    async fn signed_url(&self, row: &FileRow, refresh: bool) -> Result<String> {
+        if !refresh {
+            let urls = self.urls.lock().unwrap();
+            if let Some((url, fetched_at)) = urls.get(&row.block_id) {
+                if fetched_at.elapsed() < SIGNED_URL_TTL {
+                    return Ok(url.clone());
+                }
+            }
+        }
+        let url = get_signed_file_urls(
+            &self.storage.client,
+            &[(&row.file_url, &row.block_id, &row.space_id)],
+        )
+        .await?
+        .into_iter()
+        .next()
+        .context("No signed url")?;
+        self.urls
+            .lock()
+            .unwrap()
+            .insert(row.block_id.clone(), (url.clone(), Instant::now()));
+        Ok(url)
+    }
+}
+
+/// ブロックを `{dir}/{block_id}/{index}` に置くキャッシュ
+/// 同じ block_id の中身は変わらないので、上限を超えたら使っていない順に消すだけにする
+/// ファイルの読み書きは要求を待つループを止めないように spawn_blocking で行う
+#[derive(Clone)]
+struct BlockCache {
+    dir: PathBuf,
+    limit: u64,
+    used: Arc<Mutex<u64>>,
+}
+
+impl BlockCache {
+    fn open(dir: PathBuf, limit: u64) -> Result<BlockCache> {
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {dir:?}"))?;
+        let cache = BlockCache {
+            dir,
+            limit,
+            used: Arc::new(Mutex::new(0)),
+        };
+        let used = cache.entries()?.iter().map(|(_, len, _)| len).sum();
+        *cache.used.lock().unwrap() = used;
+        Ok(cache)
+    }
+
+    async fn get(&self, block_id: &str, index: u64) -> Option<Vec<u8>> {
+        let cache = self.clone();
+        let path = self.dir.join(block_id).join(index.to_string());
+        tokio::task::spawn_blocking(move || cache.read(&path))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        let data = fs::read(path).ok()?;
+        // 使った順に消せるように更新日時を今にする
+        let _ = fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(data)
+    }
+
+    async fn put(&self, block_id: &str, index: u64, data: Vec<u8>) -> Result<()> {
+        let cache = self.clone();
+        let dir = self.dir.join(block_id);
+        tokio::task::spawn_blocking(move || cache.write(&dir, index, &data)).await?
+    }
+
+    fn write(&self, dir: &Path, index: u64, data: &[u8]) -> Result<()> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        fs::create_dir_all(dir)?;
+        // 書きかけを読まないように別名で書いてから置き換える
+        // 同じブロックを同時に取ってきても混ざらないように、書くたびに別の名前にする
+        let tmp = dir.join(format!(
+            "{index}.{}.tmp",
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, data)?;
+        if let Err(e) = fs::rename(&tmp, dir.join(index.to_string())) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        let mut used = self.used.lock().unwrap();
+        *used += data.len() as u64;
+        if *used > self.limit {
+            *used = self.evict(self.limit / 10 * 9)?;
+        }
+        Ok(())
+    }
+
+    /// 使っていない順に消して target 以下にする (消したあとの大きさを返す)
+    fn evict(&self, target: u64) -> Result<u64> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        let mut used: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in entries {
+            if used <= target {
+                break;
+            }
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => used -= len,
+            }
+            if let Some(parent) = path.parent() {
+                // 空になったディレクトリだけ消える
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        Ok(used)
+    }
+
+    /// 置いてあるブロックの (更新日時, 大きさ, パス)
+    fn entries(&self) -> Result<Vec<(SystemTime, u64, PathBuf)>> {
+        let mut entries = Vec::new();
+        for dir in fs::read_dir(&self.dir)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                // 書きかけのものは消さない (ほかで消されたものは飛ばす)
+                if file.file_name().to_string_lossy().ends_with(".tmp") {
+                    continue;
+                }
+                let Ok(metadata) = file.metadata() else {
+                    continue;
+                };
+                entries.push((metadata.modified()?, metadata.len(), file.path()));
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[test]
+fn test_tree_insert() {
+    use crate::source::FileSource;
+
+    let row = |name: &str, size: i64, mtime: i64| FileRow {
+        file_name: name.to_string(),
+        file_url: String::new(),
+        space_id: String::new(),
+        block_id: String::new(),
+        origin_file_path: String::new(),
+        created_at: chrono::DateTime::from_timestamp(mtime, 0)
+            .unwrap()
+            .naive_utc(),
+        size: Some(size),
+        mime: None,
+        hash: None,
+        version: 1,
+        source: FileSource::default(),
+        encoding: None,
+        deleted_at: None,
+        expires_at: None,
+    };
+    let mut tree = Tree::new();
+    assert!(tree.insert("a/b/c.txt", row("a/b/c.txt", 3, 10)).is_ok());
+    assert!(tree.insert("a/d.txt", row("a/d.txt", 4, 20)).is_ok());
+    assert!(tree.insert("e.txt", row("e.txt", 5, 5)).is_ok());
+    // ファイルの下にはファイルを置けないし、ディレクトリと同じ名前のファイルも置けない
+    assert!(tree
+        .insert("e.txt/f.txt", row("e.txt/f.txt", 1, 0))
+        .is_err());
+    assert!(tree.insert("a/b", row("a/b", 1, 0)).is_err());
+    assert!(tree.insert("a/../g.txt", row("a/../g.txt", 1, 0)).is_err());
+
+    assert_eq!((tree.files, tree.bytes), (3, 12));
+    let root = tree.node(ROOT).unwrap();
+    let a = tree.lookup(root, OsStr::new("a")).unwrap();
+    assert_eq!(tree.attr(a).kind, FileType::Directory);
+    assert_eq!(tree.attr(a).perm, 0o555);
+    assert_eq!(
+        tree.attr(a).mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(20)
+    );
+    let b = tree.lookup(tree.node(a).unwrap(), OsStr::new("b")).unwrap();
+    let c = tree
+        .lookup(tree.node(b).unwrap(), OsStr::new("c.txt"))
+        .unwrap();
+    assert_eq!(tree.attr(c).size, 3);
+    assert_eq!(
+        tree.lookup(tree.node(c).unwrap(), OsStr::new("x")),
+        Err(Errno::ENOTDIR)
+    );
+    assert_eq!(tree.lookup(root, OsStr::new("x")), Err(Errno::ENOENT));
+}
+
+#[tokio::test]
+async fn test_block_cache_concurrent_put() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("yukumo-test-cache-{}", std::process::id()));
+    let cache = BlockCache::open(dir.clone(), u64::MAX)?;
+    let puts = (0..8u8).map(|i| cache.put("block", 0, vec![i; 4096]));
+    for result in futures::future::join_all(puts).await {
+        result?;
+    }
+    let block = cache.get("block", 0).await.context("Block is not cached")?;
+    assert_eq!(block.len(), 4096);
+    assert!(block.iter().all(|&b| b == block[0]));
+    assert_eq!(fs::read_dir(dir.join("block"))?.count(), 1);
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_tree_next_keeps_inodes() {
+    use crate::source::FileSource;
+
+    let row = |name: &str, block_id: &str| FileRow {
+        file_name: name.to_string(),
+        file_url: String::new(),
+        space_id: String::new(),
+        block_id: block_id.to_string(),
+        origin_file_path: String::new(),
+        created_at: NaiveDateTime::default(),
+        size: Some(1),
+        mime: None,
+        hash: None,
+        version: 1,
+        source: FileSource::default(),
+        encoding: None,
+        deleted_at: None,
+        expires_at: None,
+    };
+    let path = |tree: &Tree, path: &str| {
+        path.split('/')
+            .try_fold(ROOT, |ino, name| tree.child(ino, name))
+    };
+    let mut tree = Tree::new();
+    tree.insert("a/b.txt", row("a/b.txt", "1")).unwrap();
+    tree.insert("c.txt", row("c.txt", "2")).unwrap();
+    tree.insert("d.txt", row("d.txt", "3")).unwrap();
+
+    // c.txt は新しいバージョンになり、d.txt は消えて e.txt が増えた
+    let mut next = tree.next();
+    next.insert("a/b.txt", row("a/b.txt", "1")).unwrap();
+    next.insert("c.txt", row("c.txt", "4")).unwrap();
+    next.insert("e.txt", row("e.txt", "5")).unwrap();
+    next.previous = HashMap::new();
+
+    for name in ["a", "a/b.txt", "c.txt"] {
+        assert_eq!(path(&next, name), path(&tree, name));
+    }
+    let d = path(&tree, "d.txt").unwrap();
+    assert_eq!(path(&next, "d.txt"), None);
+    // 消えたものの inode は使い回さない
+    assert_ne!(path(&next, "e.txt"), Some(d));
+    assert!(next.node(d).is_none());
+
+    let (entries, inodes) = tree.changes(&next);
+    assert_eq!(entries, vec![(ROOT, "d.txt".to_string())]);
+    assert_eq!(inodes, vec![path(&tree, "c.txt").unwrap()]);
+}
@@ -0,0 +1,565 @@
+use std::io::{self, Write};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use comfy_table::{presets::UTF8_FULL, Table};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    database::{AuditRow, FileRow},
+    tag::Tag,
+};
+
+/// 結果の出力フォーマット
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OutputFormat {
+    /// 人が読むためのテキスト
+    #[default]
+    Text,
+    /// JSON の配列
+    Json,
+    /// 1 行 1 レコードの JSON
+    Jsonl,
+    /// ヘッダ付きの CSV
+    Csv,
+    /// 罫線付きの表
+    Table,
+}
+
+/// 標準出力に書き出せるレコード
+pub trait Record: Serialize {
+    /// text フォーマットのときの 1 行
+    fn to_text(&self) -> String;
+}
+
+/// カタログに登録されているファイルの情報
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct FileEntry {
+    pub name: String,
+    pub origin_path: String,
+    pub size: Option<i64>,
+    pub mime: Option<String>,
+    pub hash: Option<String>,
+    pub block_id: String,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    /// ゴミ箱に入れた日時
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// gc --expired で消せるようになる日時
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<FileRow> for FileEntry {
+    fn from(row: FileRow) -> FileEntry {
+        FileEntry {
+            name: row.file_name,
+            origin_path: row.origin_file_path,
+            size: row.size,
+            mime: row.mime,
+            hash: row.hash,
+            block_id: row.block_id,
+            version: row.version,
+            created_at: row.created_at.and_utc(),
+            deleted_at: row.deleted_at.map(|deleted_at| deleted_at.and_utc()),
+            expires_at: row.expires_at.map(|expires_at| expires_at.and_utc()),
+        }
+    }
+}
+
+impl Record for FileEntry {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "- {}: {} ({})",
+            self.name,
+            self.origin_path,
+            self.created_at.with_timezone(&Local).to_rfc3339()
+        );
+        if let Some(deleted_at) = self.deleted_at {
+            text.push_str(&format!(
+                " deleted at {}",
+                deleted_at.with_timezone(&Local).to_rfc3339()
+            ));
+        }
+        if let Some(expires_at) = self.expires_at {
+            text.push_str(&format!(
+                " expires at {}",
+                expires_at.with_timezone(&Local).to_rfc3339()
+            ));
+        }
+        text
+    }
+}
+
+/// ファイルのバージョン
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct VersionEntry {
+    pub name: String,
+    pub version: i32,
+    pub current: bool,
+    pub size: Option<i64>,
+    pub hash: Option<String>,
+    pub block_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl VersionEntry {
+    pub fn new(row: FileRow, current: bool) -> VersionEntry {
+        VersionEntry {
+            name: row.file_name,
+            version: row.version,
+            current,
+            size: row.size,
+            hash: row.hash,
+            block_id: row.block_id,
+            created_at: row.created_at.and_utc(),
+        }
+    }
+}
+
+impl Record for VersionEntry {
+    fn to_text(&self) -> String {
+        format!(
+            "{} v{}: {} ({}){}",
+            if self.current { '*' } else { '-' },
+            self.version,
+            self.name,
+            self.created_at.with_timezone(&Local).to_rfc3339(),
+            self.size
+                .map(|size| format!(" {size} bytes"))
+                .unwrap_or_default()
+        )
+    }
+}
+
+/// ファイルについてカタログと Notion から分かること
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct StatEntry {
+    pub name: String,
+    pub version: i32,
+    pub origin_path: String,
+    pub created_at: DateTime<Utc>,
+    pub size: Option<i64>,
+    pub mime: Option<String>,
+    pub hash: Option<String>,
+    /// Notion に置く前にかけた圧縮や暗号化 (`zstd+xchacha20poly1305` など)
+    pub encoding: Option<String>,
+    pub block_id: String,
+    pub space_id: String,
+    pub file_url: String,
+    /// ブロックがアーカイブされていないか (取得できなければ null)
+    pub alive: Option<bool>,
+    /// ブロックの title プロパティ
+    pub title: Option<String>,
+    /// ブロックの size プロパティ
+    pub size_text: Option<String>,
+    /// notion.so でブロックを開く URL
+    pub block_url: Option<String>,
+    /// ダウンロード用の署名付き URL
+    pub signed_url: Option<String>,
+    /// タグとメタデータ (`raw` や `project=x`)
+    pub tags: Vec<String>,
+    /// gc --expired で消せるようになる日時
+    pub expires_at: Option<DateTime<Utc>>,
+    /// put したホスト名
+    pub source_host: Option<String>,
+    /// 元ファイルの更新日時
+    pub source_mtime: Option<DateTime<Utc>>,
+    /// 元ファイルの Unix のモード
+    pub source_mode: Option<i32>,
+    pub source_uid: Option<i64>,
+    pub source_gid: Option<i64>,
+    /// 元ファイルがシンボリックリンクだったときのリンク先
+    pub source_symlink: Option<String>,
+}
+
+impl Record for StatEntry {
+    fn to_text(&self) -> String {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        [
+            ("name", self.name.clone()),
+            ("version", self.version.to_string()),
+            ("origin_path", self.origin_path.clone()),
+            (
+                "created_at",
+                self.created_at.with_timezone(&Local).to_rfc3339(),
+            ),
+            ("size", or_dash(self.size.map(|size| size.to_string()))),
+            ("mime", or_dash(self.mime.clone())),
+            ("hash", or_dash(self.hash.clone())),
+            ("encoding", or_dash(self.encoding.clone())),
+            ("block_id", self.block_id.clone()),
+            ("space_id", self.space_id.clone()),
+            ("file_url", self.file_url.clone()),
+            ("alive", or_dash(self.alive.map(|alive| alive.to_string()))),
+            ("title", or_dash(self.title.clone())),
+            ("size_text", or_dash(self.size_text.clone())),
+            ("block_url", or_dash(self.block_url.clone())),
+            ("signed_url", or_dash(self.signed_url.clone())),
+            (
+                "tags",
+                or_dash(Some(self.tags.join(" ")).filter(|tags| !tags.is_empty())),
+            ),
+            (
+                "expires_at",
+                or_dash(
+                    self.expires_at
+                        .map(|expires_at| expires_at.with_timezone(&Local).to_rfc3339()),
+                ),
+            ),
+            ("source_host", or_dash(self.source_host.clone())),
+            (
+                "source_mtime",
+                or_dash(
+                    self.source_mtime
+                        .map(|mtime| mtime.with_timezone(&Local).to_rfc3339()),
+                ),
+            ),
+            (
+                "source_mode",
+                or_dash(self.source_mode.map(|mode| format!("{mode:o}"))),
+            ),
+            (
+                "source_uid",
+                or_dash(self.source_uid.map(|uid| uid.to_string())),
+            ),
+            (
+                "source_gid",
+                or_dash(self.source_gid.map(|gid| gid.to_string())),
+            ),
+            ("source_symlink", or_dash(self.source_symlink.clone())),
+        ]
+        .into_iter()
+        .map(|(key, value)| format!("{key:>14}: {value}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+/// 全文検索で見つかったファイル
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct SearchEntry {
+    /// 関連度 (大きいほど関連が強い)
+    pub rank: f64,
+    pub name: String,
+    pub version: i32,
+    pub origin_path: String,
+    pub size: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    /// タグとメタデータを空白でつないだもの
+    pub tags: String,
+    /// 一致した語を «» で囲んだ名前 (名前が一致しなければ null)
+    pub name_match: Option<String>,
+    /// 一致した語を «» で囲んだ元ファイルのパス
+    pub origin_match: Option<String>,
+    /// 一致した語を «» で囲んだタグ
+    pub tags_match: Option<String>,
+}
+
+impl Record for SearchEntry {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "- {}: {} ({:.3})",
+            self.name_match.as_ref().unwrap_or(&self.name),
+            self.origin_match.as_ref().unwrap_or(&self.origin_path),
+            self.rank
+        );
+        if let Some(tags) = &self.tags_match {
+            text.push_str(&format!(" [{tags}]"));
+        }
+        text
+    }
+}
+
+/// usage でまとめた 1 つのグループ
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct UsageEntry {
+    /// 名前の先頭、バケット名、space_id か `YYYY-MM`
+    pub key: String,
+    pub files: u64,
+    /// size がわかっているファイルの合計
+    pub bytes: i64,
+    /// カタログにも Notion にも size がないファイルの数
+    pub unknown: u64,
+    /// --by month のときの、その月までの累計
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<i64>,
+}
+
+impl Record for UsageEntry {
+    fn to_text(&self) -> String {
+        let key = if self.key.is_empty() { "-" } else { &self.key };
+        let mut text = format!("{key}: {} files, {} bytes", self.files, self.bytes);
+        if self.unknown > 0 {
+            text.push_str(&format!(" ({} unknown)", self.unknown));
+        }
+        if let Some(total_bytes) = self.total_bytes {
+            text.push_str(&format!(", {total_bytes} bytes in total"));
+        }
+        text
+    }
+}
+
+/// verify で Notion 上の中身をカタログと比べた結果
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct VerifyEntry {
+    pub name: String,
+    pub version: i32,
+    /// カタログの size と hash に合っている (hash のない行は size だけ比べる)
+    pub ok: bool,
+    pub size: Option<i64>,
+    pub actual_size: u64,
+    pub hash: Option<String>,
+    pub actual_hash: String,
+}
+
+impl VerifyEntry {
+    pub fn new(row: FileRow, actual_size: u64, actual_hash: String) -> VerifyEntry {
+        let ok = row.size.is_none_or(|size| size == actual_size as i64)
+            && row.hash.as_ref().is_none_or(|hash| *hash == actual_hash);
+        VerifyEntry {
+            name: row.file_name,
+            version: row.version,
+            ok,
+            size: row.size,
+            actual_size,
+            hash: row.hash,
+            actual_hash,
+        }
+    }
+}
+
+impl Record for VerifyEntry {
+    fn to_text(&self) -> String {
+        if self.ok {
+            let mut text = format!("ok {} ({} bytes)", self.name, self.actual_size);
+            if self.hash.is_none() {
+                text.push_str(", no hash to compare");
+            }
+            return text;
+        }
+        let mut reasons = Vec::new();
+        if let Some(size) = self.size.filter(|size| *size != self.actual_size as i64) {
+            reasons.push(format!("size {size} -> {}", self.actual_size));
+        }
+        if self
+            .hash
+            .as_ref()
+            .is_some_and(|hash| *hash != self.actual_hash)
+        {
+            reasons.push("hash differs".to_string());
+        }
+        format!("MISMATCH {}: {}", self.name, reasons.join(", "))
+    }
+}
+
+/// ファイルに付いているタグ 1 つ
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct TagEntry {
+    pub name: String,
+    pub key: String,
+    /// 値のないタグなら null
+    pub value: Option<String>,
+}
+
+impl TagEntry {
+    pub fn new(name: &str, tag: &Tag) -> TagEntry {
+        let (key, value) = match tag {
+            Tag::Label(label) => (label.clone(), None),
+            Tag::Meta(key, value) => (key.clone(), Some(value.clone())),
+        };
+        TagEntry {
+            name: name.to_string(),
+            key,
+            value,
+        }
+    }
+}
+
+impl Record for TagEntry {
+    fn to_text(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{}={value}", self.key),
+            None => self.key.clone(),
+        }
+    }
+}
+
+/// audit_log の 1 行
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub user: String,
+    pub hostname: String,
+    pub yukumo_version: String,
+    pub action: String,
+    pub name: String,
+    pub version: Option<i32>,
+    pub block_id: Option<String>,
+    pub succeeded: bool,
+    pub detail: Option<String>,
+}
+
+impl From<AuditRow> for AuditEntry {
+    fn from(row: AuditRow) -> AuditEntry {
+        AuditEntry {
+            id: row.id,
+            created_at: row.created_at.and_utc(),
+            user: row.os_user,
+            hostname: row.hostname,
+            yukumo_version: row.yukumo_version,
+            action: row.action,
+            name: row.file_name,
+            version: row.file_version,
+            block_id: row.block_id,
+            succeeded: row.succeeded,
+            detail: row.detail,
+        }
+    }
+}
+
+impl Record for AuditEntry {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} {}@{} {} {}",
+            self.created_at.with_timezone(&Local).to_rfc3339(),
+            self.user,
+            self.hostname,
+            self.action,
+            self.name
+        );
+        if let Some(version) = self.version {
+            text.push_str(&format!(" (version {version})"));
+        }
+        if !self.succeeded {
+            text.push_str(" FAILED");
+        }
+        if let Some(detail) = &self.detail {
+            text.push_str(&format!(": {detail}"));
+        }
+        text
+    }
+}
+
+/// レコードを指定のフォーマットで逐次書き出す
+pub struct Printer<W: Write> {
+    format: OutputFormat,
+    writer: W,
+    count: usize,
+    table: Table,
+}
+
+impl Printer<io::Stdout> {
+    pub fn stdout(format: OutputFormat) -> Printer<io::Stdout> {
+        Printer::new(format, io::stdout())
+    }
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(format: OutputFormat, writer: W) -> Printer<W> {
+        let mut table = Table::new();
+        table.load_preset(UTF8_FULL);
+        Printer {
+            format,
+            writer,
+            count: 0,
+            table,
+        }
+    }
+
+    pub fn print(&mut self, record: &impl Record) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => writeln!(self.writer, "{}", record.to_text())?,
+            OutputFormat::Json => {
+                let separator = if self.count == 0 { "[\n" } else { ",\n" };
+                self.writer.write_all(separator.as_bytes())?;
+                serde_json::to_writer(&mut self.writer, record)
+                    .context("Failed to serialize json")?;
+            }
+            OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut self.writer, record)
+                    .context("Failed to serialize json")?;
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Csv => {
+                // ヘッダは最初のレコードのときだけ書く
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(self.count == 0)
+                    .from_writer(&mut self.writer);
+                writer
+                    .serialize(record)
+                    .context("Failed to serialize csv")?;
+                writer.flush()?;
+            }
+            OutputFormat::Table => {
+                let Value::Object(map) =
+                    serde_json::to_value(record).context("Failed to serialize record")?
+                else {
+                    anyhow::bail!("Record must be an object");
+                };
+                if self.count == 0 {
+                    self.table.set_header(map.keys());
+                }
+                self.table.add_row(map.values().map(to_cell));
+            }
+        }
+        self.writer.flush()?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        match self.format {
+            OutputFormat::Json if self.count == 0 => writeln!(self.writer, "[]")?,
+            OutputFormat::Json => writeln!(self.writer, "\n]")?,
+            OutputFormat::Table if self.count > 0 => writeln!(self.writer, "{}", self.table)?,
+            _ => {}
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn to_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[test]
+fn test_printer_csv_writes_header_once() {
+    let entry = FileEntry {
+        name: "a.txt".to_string(),
+        origin_path: "/tmp/a.txt".to_string(),
+        size: Some(3),
+        mime: Some("text/plain".to_string()),
+        hash: None,
+        block_id: "block".to_string(),
+        version: 1,
+        created_at: DateTime::from_timestamp(0, 0).unwrap(),
+        deleted_at: None,
+        expires_at: None,
+    };
+    let mut buf = Vec::new();
+    let mut printer = Printer::new(OutputFormat::Csv, &mut buf);
+    printer.print(&entry).unwrap();
+    printer.print(&entry).unwrap();
+    printer.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "name,origin_path,size,mime,hash,block_id,version,created_at,deleted_at,expires_at\n\
+         a.txt,/tmp/a.txt,3,text/plain,,block,1,1970-01-01T00:00:00Z,,\n\
+         a.txt,/tmp/a.txt,3,text/plain,,block,1,1970-01-01T00:00:00Z,,\n"
+    );
+}
+
+#[test]
+fn test_printer_json_empty() {
+    let mut buf = Vec::new();
+    Printer::new(OutputFormat::Json, &mut buf).finish().unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "[]\n");
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+/// `10MB` や `1.5GiB` のようなサイズをバイト数にする
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let index = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(index);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size: {text}"))?;
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000u64.pow(2),
+        "g" | "gb" => 1000u64.pow(3),
+        "t" | "tb" => 1000u64.pow(4),
+        "kib" => 1024,
+        "mib" => 1024u64.pow(2),
+        "gib" => 1024u64.pow(3),
+        "tib" => 1024u64.pow(4),
+        unit => bail!("Unknown size unit: {unit}"),
+    };
+    Ok((number * scale as f64) as u64)
+}
+
+/// RFC3339 か `YYYY-MM-DD[ HH:MM[:SS]]` (ローカル時刻) を UTC の日時にする
+pub fn parse_datetime(text: &str) -> Result<NaiveDateTime> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Ok(datetime.naive_utc());
+    }
+    let local = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .with_context(|| format!("Invalid datetime: {text}"))?;
+    let Some(datetime) = Local.from_local_datetime(&local).earliest() else {
+        bail!("Invalid local datetime: {text}");
+    };
+    Ok(datetime.naive_utc())
+}
+
+/// `30s` `10m` `12h` `30d` `2w` のような期間を Duration にする (単位なしは秒)
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let index = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(index);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration: {text}"))?;
+    let scale = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => bail!("Unknown duration unit: {unit}"),
+    };
+    Ok(Duration::from_secs(number * scale))
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("123").ok(), Some(123));
+    assert_eq!(parse_size("10KB").ok(), Some(10_000));
+    assert_eq!(parse_size("1.5 MiB").ok(), Some(1_572_864));
+    assert!(parse_size("10XB").is_err());
+    assert!(parse_size("MB").is_err());
+}
+
+#[test]
+fn test_parse_datetime() {
+    assert_eq!(
+        parse_datetime("2023-09-23T10:54:44+09:00").ok(),
+        NaiveDate::from_ymd_opt(2023, 9, 23).and_then(|d| d.and_hms_opt(1, 54, 44))
+    );
+    assert!(parse_datetime("2023-09-23").is_ok());
+    assert!(parse_datetime("yesterday").is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("5").ok(), Some(Duration::from_secs(5)));
+    assert_eq!(parse_duration("10m").ok(), Some(Duration::from_secs(600)));
+    assert_eq!(
+        parse_duration("30d").ok(),
+        Some(Duration::from_secs(2_592_000))
+    );
+    assert!(parse_duration("1y").is_err());
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+
+use crate::output::Record;
+
+/// カタログや Notion に対して行う (行うはずの) 操作
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// 新しい名前でアップロードする
+    Create,
+    /// 既にある名前を新しいバージョンで置き換える
+    Overwrite,
+    /// 名前を変える
+    Move,
+    /// ゴミ箱に入れる
+    Trash,
+    /// Notion 上でアーカイブしてカタログから消す
+    Delete,
+    /// 何もしない
+    Skip,
+}
+
+/// 操作の 1 行
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Change {
+    pub action: Action,
+    pub name: String,
+    /// ローカルのファイルか、移動先の名前
+    pub path: Option<String>,
+    pub size: Option<i64>,
+    pub reason: String,
+}
+
+impl Change {
+    pub fn new(action: Action, name: impl Into<String>, reason: impl Into<String>) -> Change {
+        Change {
+            action,
+            name: name.into(),
+            path: None,
+            size: None,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Change {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn size(mut self, size: Option<i64>) -> Change {
+        self.size = size;
+        self
+    }
+}
+
+impl Record for Change {
+    fn to_text(&self) -> String {
+        let mark = match self.action {
+            Action::Create => '+',
+            Action::Overwrite => '~',
+            Action::Move => '>',
+            Action::Trash => 'x',
+            Action::Delete => '-',
+            Action::Skip => '=',
+        };
+        let mut text = format!("{mark} {}", self.name);
+        if let (Action::Move, Some(path)) = (self.action, &self.path) {
+            text.push_str(&format!(" -> {path}"));
+        }
+        match self.size {
+            Some(size) => text.push_str(&format!(" ({size} bytes, {})", self.reason)),
+            None => text.push_str(&format!(" ({})", self.reason)),
+        }
+        text
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::Write;
+
+use anyhow::{ensure, Result};
+
+use crate::{
+    database::FileRow,
+    output::{Printer, SearchEntry},
+    storage::Storage,
+};
+
+/// 名前、元ファイルのパス、タグとメタデータを全文検索して関連度の高い順に表示する
+pub async fn search(
+    storage: &Storage,
+    terms: &[String],
+    limit: i64,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let lexemes = lexemes(terms);
+    ensure!(!lexemes.is_empty(), "Specify at least one word to search.");
+    for row in FileRow::search(&storage.pool, &lexemes, limit).await? {
+        let tags = FileRow::tags(&storage.pool, &row.file.file_name)
+            .await?
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let entry = SearchEntry {
+            name_match: highlight(&row.file.file_name, &lexemes),
+            origin_match: highlight(&row.file.origin_file_path, &lexemes),
+            tags_match: highlight(&tags, &lexemes),
+            rank: row.rank,
+            name: row.file.file_name,
+            version: row.file.version,
+            origin_path: row.file.origin_file_path,
+            size: row.file.size,
+            created_at: row.file.created_at.and_utc(),
+            tags,
+        };
+        printer.print(&entry)?;
+    }
+    Ok(())
+}
+
+/// 検索語を英数字の並びに分けて小文字にする
+/// データベース側もパスの区切りや記号で語を分けているので、`2023-10.pdf` は `2023`、`10`、`pdf` になる
+fn lexemes(terms: &[String]) -> Vec<String> {
+    let mut lexemes = Vec::new();
+    for term in terms {
+        for word in term.split(|c: char| !c.is_alphanumeric()) {
+            let word = word.to_lowercase();
+            if !word.is_empty() && !lexemes.contains(&word) {
+                lexemes.push(word);
+            }
+        }
+    }
+    lexemes
+}
+
+/// lexemes のどれかで始まる語を «» で囲む (どれも含まなければ None)
+fn highlight(text: &str, lexemes: &[String]) -> Option<String> {
+    let mut highlighted = String::with_capacity(text.len());
+    let mut matched = false;
+    let mut rest = text;
+    while !rest.is_empty() {
+        // 英数字でない文字はそのまま写す
+        let start = rest
+            .find(|c: char| c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        highlighted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        let lower = word.to_lowercase();
+        if !word.is_empty() && lexemes.iter().any(|lexeme| lower.starts_with(lexeme)) {
+            highlighted.push_str(&format!("«{word}»"));
+            matched = true;
+        } else {
+            highlighted.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    matched.then_some(highlighted)
+}
+
+#[test]
+fn test_lexemes() {
+    let terms = [
+        "Report".to_string(),
+        "2023-10.pdf".to_string(),
+        "report".to_string(),
+    ];
+    assert_eq!(lexemes(&terms), ["report", "2023", "10", "pdf"]);
+    assert!(lexemes(&["--".to_string()]).is_empty());
+}
+
+#[test]
+fn test_highlight() {
+    let lexemes = ["rep".to_string(), "pdf".to_string()];
+    assert_eq!(
+        highlight("docs/Reports/2023_q1.pdf", &lexemes).as_deref(),
+        Some("docs/«Reports»/2023_q1.«pdf»")
+    );
+    assert_eq!(highlight("docs/prep.txt", &lexemes), None);
+}
+
+/// 検索語の分け方がデータベース側の分け方と合っているか、実際に登録して検索して確かめる
+/// PostgreSQL は YUKUMO_TEST_DATABASE_URL があるときだけ、SQLite は sqlite feature のときに一時ファイルで試す
+#[tokio::test]
+async fn test_lexemes_match_database() -> Result<()> {
+    use crate::{
+        config::DatabaseConfig,
+        database::{create_pool, Pool},
+        tag::parse_tag,
+    };
+
+    async fn check(pool: Pool) -> Result<()> {
+        let pool = pool.with_bucket(&format!("test-search-{}", std::process::id()));
+        let row = FileRow {
+            file_name: "reports/2023-10_summary.pdf".to_string(),
+            file_url: String::new(),
+            space_id: String::new(),
+            block_id: String::new(),
+            origin_file_path: "/home/me/Q4 (final).pdf".to_string(),
+            created_at: chrono::NaiveDateTime::default(),
+            size: None,
+            mime: None,
+            hash: None,
+            version: 1,
+            source: Default::default(),
+            encoding: None,
+            deleted_at: None,
+            expires_at: None,
+        };
+        row.insert(&pool).await?;
+        FileRow::add_tags(&pool, &row.file_name, &[parse_tag("project=yuku-mo")?]).await?;
+        let result = async {
+            for terms in [
+                &["2023-10"][..],
+                &["summary.pdf"],
+                &["Q4", "final"],
+                &["yuku-mo"],
+            ] {
+                let terms: Vec<String> = terms.iter().map(ToString::to_string).collect();
+                let rows = FileRow::search(&pool, &lexemes(&terms), 10).await?;
+                ensure!(rows.len() == 1, "{terms:?} is not found");
+            }
+            Ok(())
+        }
+        .await;
+        FileRow::delete(&pool, &row.file_name).await?;
+        result
+    }
+
+    if let Ok(url) = std::env::var("YUKUMO_TEST_DATABASE_URL") {
+        check(create_pool(&DatabaseConfig::new(url)).await?).await?;
+    }
+    if cfg!(feature = "sqlite") {
+        let dir = std::env::temp_dir().join(format!("yukumo-test-search-{}", std::process::id()));
+        let host = format!("sqlite://{}", dir.join("catalog.db").display());
+        let result = async { check(create_pool(&DatabaseConfig::new(host)).await?).await }.await;
+        std::fs::remove_dir_all(&dir).ok();
+        result?;
+    }
+    Ok(())
+}
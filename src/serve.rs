@@ -0,0 +1,353 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, Context as _};
+use async_stream::try_stream;
+use axum::{
+    body::StreamBody,
+    extract::{BodyStream, ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bytes::Bytes;
+use futures::{StreamExt as _, TryStreamExt as _};
+use notionfs::{get_file_with_range_by_signed_url, get_signed_file_urls, Client};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt as _;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    codec,
+    database::{AuditAction, AuditRow, FileQuery, FileRow, Rejected},
+    output::FileEntry,
+    parse::parse_duration,
+    storage::Storage,
+};
+
+/// upstream の応答からそのまま返すヘッダー
+const FORWARDED_HEADERS: [header::HeaderName; 5] = [
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+struct Gateway {
+    storage: Storage,
+    /// 署名付きURLから中身を取るのに使い回す
+    client: Client,
+}
+
+/// カタログを HTTP で読み書きできるようにする (Ctrl-C で止める)
+/// 認証はしないので、信頼できるネットワークでだけ listen すること
+pub async fn serve(storage: Storage, listen: SocketAddr) -> anyhow::Result<()> {
+    let gateway = Arc::new(Gateway {
+        storage,
+        client: Client::new(),
+    });
+    let app = Router::new()
+        .route("/files", get(list))
+        .route("/files/*name", get(download).put(upload).delete(remove))
+        .with_state(gateway);
+
+    let server = axum::Server::try_bind(&listen)
+        .with_context(|| format!("Failed to listen on {listen}"))?
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    log::info!("Listening on http://{}", server.local_addr());
+    server
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+            log::info!("Shutting down");
+        })
+        .await?;
+    Ok(())
+}
+
+/// 返すエラー (5xx のものだけログに出す)
+struct Error(StatusCode, anyhow::Error);
+
+impl Error {
+    fn not_found(name: &str) -> Error {
+        Error(
+            StatusCode::NOT_FOUND,
+            anyhow!("file_name ({name}) is not found."),
+        )
+    }
+}
+
+/// 名前が使われているなどで通らなかった書き込みは 4xx にする
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Error {
+        let status = match Rejected::find(&e) {
+            Some(Rejected::Exists(_) | Rejected::Trashed(_)) => StatusCode::CONFLICT,
+            Some(Rejected::InvalidName(_)) => StatusCode::BAD_REQUEST,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Error(status, e)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let Error(status, e) = self;
+        if status.is_server_error() {
+            log::error!("{e:#}");
+        }
+        let body = serde_json::json!({ "error": format!("{e:#}") });
+        (status, Json(body)).into_response()
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Deserialize)]
+struct ListParams {
+    #[serde(default)]
+    prefix: String,
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// `GET /files?prefix=` は query と同じ一覧を JSON の配列で返す
+/// after と limit で query --after --limit と同じようにページに分けられる
+/// 行を全部読んでから返さないように、読んだ順に書き出す
+/// (途中で失敗したら接続を切るので、配列は閉じられない)
+async fn list(State(gateway): State<Arc<Gateway>>, Query(params): Query<ListParams>) -> Response {
+    let query = FileQuery {
+        prefix: params.prefix,
+        after: params.after,
+        limit: params.limit,
+        ..FileQuery::default()
+    };
+    let body = try_stream! {
+        let mut rows = pin!(FileRow::stream(&gateway.storage.pool, &query));
+        let mut separator = "[";
+        while let Some(row) = rows.try_next().await? {
+            let mut chunk = separator.as_bytes().to_vec();
+            serde_json::to_writer(&mut chunk, &FileEntry::from(row))?;
+            separator = ",";
+            yield Bytes::from(chunk);
+        }
+        let end = if separator == "[" { "[]" } else { "]" };
+        yield Bytes::from_static(end.as_bytes());
+    };
+    let body = body.inspect_err(|e: &anyhow::Error| log::error!("{e:#}"));
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
+
+/// `GET /files/{name}` は中身を流す
+/// 署名付きURLと file_token はこちらで付けるので、クライアントは Notion を知らなくてよい
+/// Range はそのまま Notion に渡し、206 や 416 もそのまま返す
+async fn download(
+    State(gateway): State<Arc<Gateway>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let storage = &gateway.storage;
+    let row = FileRow::find(&storage.pool, &name)
+        .await?
+        .ok_or_else(|| Error::not_found(&name))?;
+    let range = headers
+        .get(header::RANGE)
+        .map(|range| range.to_str())
+        .transpose()
+        .map_err(|e| Error(StatusCode::BAD_REQUEST, e.into()))?;
+    if row.encoding.is_some() {
+        return download_decoded(storage, &row).await;
+    }
+
+    let result = async {
+        let signed_urls = get_signed_file_urls(
+            &storage.client,
+            &[(&row.file_url, &row.block_id, &row.space_id)],
+        )
+        .await?;
+        let url = signed_urls.first().context("No signed url is returned")?;
+        get_file_with_range_by_signed_url(
+            &gateway.client,
+            url,
+            &storage.config.notion.file_token,
+            range,
+        )
+        .await
+    }
+    .await;
+    // プレーヤーなどは少しずつ Range で読むので、先頭から読むときだけ記録する
+    if range.is_none_or(|range| range.starts_with("bytes=0-")) {
+        let entry = AuditRow::new(AuditAction::Get, &name)
+            .file(&row)
+            .outcome(&result);
+        storage.audit(entry).await;
+    }
+    let res = result?;
+
+    let mut response_headers = HeaderMap::new();
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = res.headers().get(&name) {
+            response_headers.insert(name, value.clone());
+        }
+    }
+    let content_type = match &row.mime {
+        Some(mime) => HeaderValue::from_str(mime).ok(),
+        None => res.headers().get(header::CONTENT_TYPE).cloned(),
+    };
+    if let Some(content_type) = content_type {
+        response_headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    let status = res.status();
+    Ok((
+        status,
+        response_headers,
+        StreamBody::new(res.bytes_stream()),
+    )
+        .into_response())
+}
+
+/// 圧縮や暗号化したファイルは Range で途中から戻せないので、全体を戻してから返す
+async fn download_decoded(storage: &Storage, row: &FileRow) -> Result<Response> {
+    let spool = codec::Spool::new();
+    storage.download(row, spool.path()).await?;
+    // 開いておけば spool が消しても読める
+    let file = tokio::fs::File::open(spool.path())
+        .await
+        .context("Failed to open the decoded file")?;
+    let len = file
+        .metadata()
+        .await
+        .context("Failed to stat the decoded file")?
+        .len();
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    if let Some(content_type) = row
+        .mime
+        .as_deref()
+        .and_then(|mime| HeaderValue::from_str(mime).ok())
+    {
+        response_headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    Ok((
+        StatusCode::OK,
+        response_headers,
+        StreamBody::new(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct UploadParams {
+    /// 同じ名前があれば新しいバージョンとして置き換える
+    #[serde(default)]
+    overwrite: bool,
+    /// put --expires-in と同じ (`30d` など)
+    expires_in: Option<String>,
+}
+
+/// `PUT /files/{name}` は本文を一時ファイルに書いてから put と同じ手順でアップロードする
+/// 新しく作れば 201、置き換えたら 200 で、登録した行を返す
+/// 元ファイルのパスの代わりに `http:{クライアントのアドレス}` を記録する
+async fn upload(
+    State(gateway): State<Arc<Gateway>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(name): Path<String>,
+    Query(params): Query<UploadParams>,
+    body: BodyStream,
+) -> Result<(StatusCode, Json<FileEntry>)> {
+    let storage = &gateway.storage;
+    let expires_in = params
+        .expires_in
+        .as_deref()
+        .map(parse_duration)
+        .transpose()
+        .map_err(|e| Error(StatusCode::BAD_REQUEST, e))?;
+
+    let spool = Spool::new(&name)?;
+    let origin = format!("http:{remote}");
+    let result = async {
+        spool.write(body).await?;
+        storage
+            .put(
+                &spool.path,
+                &name,
+                params.overwrite,
+                &[],
+                expires_in,
+                Some(&origin),
+            )
+            .await
+    }
+    .await;
+    spool.remove().await;
+    let row = result?;
+
+    let status = if row.version > 1 {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((status, Json(FileEntry::from(row))))
+}
+
+/// `DELETE /files/{name}` は rm と同じくゴミ箱に入れる
+async fn remove(
+    State(gateway): State<Arc<Gateway>>,
+    Path(name): Path<String>,
+) -> Result<Json<FileEntry>> {
+    let storage = &gateway.storage;
+    if FileRow::find(&storage.pool, &name).await?.is_none() {
+        return Err(Error::not_found(&name));
+    }
+    Ok(Json(FileEntry::from(storage.trash(&name).await?)))
+}
+
+/// アップロードする本文を置く一時ファイル
+/// MIME は拡張子から推測されるので、ファイル名は名前の最後の部分にする
+struct Spool {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Spool {
+    fn new(name: &str) -> anyhow::Result<Spool> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let file_name = name.rsplit('/').next().filter(|s| !s.is_empty());
+        let file_name = file_name.ok_or_else(|| Rejected::InvalidName(name.to_string()))?;
+        let dir = std::env::temp_dir().join(format!(
+            "yukumo-serve-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = dir.join(file_name);
+        Ok(Spool { dir, path })
+    }
+
+    async fn write(&self, mut body: BodyStream) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut file = tokio::fs::File::create(&self.path).await?;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Failed to read request body")?;
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn remove(self) {
+        if let Err(e) = tokio::fs::remove_dir_all(&self.dir).await {
+            log::warn!("Failed to remove {:?}: {e}", self.dir);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::FromRow;
+
+/// put したときの元ファイルの状態 (古い行ではすべて None)
+#[derive(FromRow, Default, PartialEq, Clone, Debug)]
+pub struct FileSource {
+    /// put したホスト名
+    #[sqlx(rename = "source_host")]
+    pub host: Option<String>,
+    /// 更新日時
+    #[sqlx(rename = "source_mtime")]
+    pub mtime: Option<NaiveDateTime>,
+    /// Unix のモード (ファイルの種類とパーミッション)
+    #[sqlx(rename = "source_mode")]
+    pub mode: Option<i32>,
+    #[sqlx(rename = "source_uid")]
+    pub uid: Option<i64>,
+    #[sqlx(rename = "source_gid")]
+    pub gid: Option<i64>,
+    /// シンボリックリンクだったときのリンク先 (リンクに書かれているまま)
+    #[sqlx(rename = "source_symlink")]
+    pub symlink: Option<String>,
+}
+
+impl FileSource {
+    /// path の今の状態を読む
+    /// シンボリックリンクなら、リンク先のファイルの更新日時やモードを記録する
+    pub fn read(path: &Path) -> Result<FileSource> {
+        let link = path
+            .symlink_metadata()
+            .with_context(|| format!("Failed to read {path:?}"))?;
+        let symlink = if link.file_type().is_symlink() {
+            let target = fs::read_link(path).with_context(|| format!("Failed to read {path:?}"))?;
+            Some(target.to_string_lossy().to_string())
+        } else {
+            None
+        };
+        let metadata = path
+            .metadata()
+            .with_context(|| format!("Failed to read {path:?}"))?;
+        let mtime: DateTime<Utc> = metadata.modified()?.into();
+        let (mode, uid, gid) = unix_mode(&metadata);
+        Ok(FileSource {
+            host: Some(whoami::hostname()),
+            mtime: Some(mtime.naive_utc()),
+            mode,
+            uid,
+            gid,
+            symlink,
+        })
+    }
+
+    /// output の更新日時とパーミッションを元ファイルに合わせる (記録がないものはそのまま)
+    /// 所有者は root でないと変えられないので戻さない
+    pub fn restore(&self, output: &Path) -> Result<()> {
+        if let Some(mtime) = self.mtime {
+            fs::File::options()
+                .write(true)
+                .open(output)
+                .and_then(|file| file.set_modified(mtime.and_utc().into()))
+                .with_context(|| format!("Failed to set mtime of {output:?}"))?;
+        }
+        // 読み取り専用にすると更新日時を変えられないので最後にする
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt as _;
+            fs::set_permissions(output, fs::Permissions::from_mode(mode as u32 & 0o7777))
+                .with_context(|| format!("Failed to set mode of {output:?}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn unix_mode(metadata: &fs::Metadata) -> (Option<i32>, Option<i64>, Option<i64>) {
+    use std::os::unix::fs::MetadataExt as _;
+    (
+        Some(metadata.mode() as i32),
+        Some(metadata.uid() as i64),
+        Some(metadata.gid() as i64),
+    )
+}
+
+#[cfg(not(unix))]
+fn unix_mode(_: &fs::Metadata) -> (Option<i32>, Option<i64>, Option<i64>) {
+    (None, None, None)
+}
+
+#[cfg(unix)]
+#[test]
+fn test_read_and_restore() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = std::env::temp_dir().join(format!("yukumo-source-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let origin = dir.join("origin.txt");
+    let link = dir.join("link.txt");
+    let output = dir.join("output.txt");
+    fs::write(&origin, "a").unwrap();
+    fs::write(&output, "a").unwrap();
+    fs::set_permissions(&origin, fs::Permissions::from_mode(0o640)).unwrap();
+    let mtime = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&origin)
+        .unwrap()
+        .set_modified(mtime.into())
+        .unwrap();
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink("origin.txt", &link).unwrap();
+
+    let source = FileSource::read(&link).unwrap();
+    assert_eq!(source.mtime, Some(mtime.naive_utc()));
+    assert_eq!(source.mode.map(|mode| mode & 0o7777), Some(0o640));
+    assert_eq!(source.symlink.as_deref(), Some("origin.txt"));
+
+    source.restore(&output).unwrap();
+    let restored = output.metadata().unwrap();
+    let restored_mtime: DateTime<Utc> = restored.modified().unwrap().into();
+    assert_eq!(restored_mtime, mtime);
+    assert_eq!(restored.permissions().mode() & 0o7777, 0o640);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,822 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use indicatif::ProgressBar;
+use notionfs::{
+    archive_block, attach_file_to_block, block_url, create_new_block, get_block,
+    get_block_property, get_blocks, get_file_by_signed_url, get_signed_file_urls,
+    get_signed_put_file,
+    notion::{client::Notion, types::PageDataResponse},
+    put_to_signed_url, rename_block, set_block_property, to_dashed_id, Body,
+};
+use tokio::{fs::File, sync::OnceCell};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    codec::{Codec, Spool},
+    config::{BucketConfig, Config},
+    database::{
+        connect, create_pool, pending_migrations, AuditAction, AuditRow, FileRow, Pool, Rejected,
+    },
+    hash::{hash_file, hash_response, save_response, HashWriter},
+    output::StatEntry,
+    parse::parse_size,
+    plan::{Action, Change},
+    source::FileSource,
+    tag::Tag,
+};
+
+/// カタログと Notion のページをまとめて扱う
+pub struct Storage {
+    pub config: Config,
+    /// カタログはこのバケットの行だけを見る
+    pub pool: Pool,
+    /// ファイルを置くバケットの設定
+    pub bucket: BucketConfig,
+    /// バケットの圧縮と暗号化
+    pub codec: Codec,
+    pub client: Notion,
+    /// Notion にもカタログにも書き込まない
+    pub dry_run: bool,
+    page: OnceCell<PageDataResponse>,
+}
+
+impl Storage {
+    pub async fn open(config: Config, bucket: &str, dry_run: bool) -> Result<Storage> {
+        let bucket_config = config.bucket(bucket)?;
+        let codec = Codec::open(&bucket_config)
+            .with_context(|| format!("Failed to open bucket {bucket}"))?;
+        let pool = if dry_run {
+            // マイグレーションも書き込みなので走らせない
+            let pool = connect(&config.database).await?;
+            let pending = pending_migrations(&pool).await?;
+            if !pending.is_empty() {
+                log::warn!(
+                    "{} migrations are pending; the plan may be wrong.",
+                    pending.len()
+                );
+            }
+            pool
+        } else {
+            create_pool(&config.database).await?
+        }
+        .with_bucket(bucket);
+        let client = Notion::new(
+            config.notion.token_v2.clone(),
+            config.notion.user_agent.clone(),
+        );
+        log::debug!("UserAgent = {}", client.user_agent());
+        Ok(Storage {
+            config,
+            pool,
+            bucket: bucket_config,
+            codec,
+            client,
+            dry_run,
+            page: OnceCell::new(),
+        })
+    }
+
+    /// アップロード先のページの情報 (初回だけ取得する)
+    pub async fn page(&self) -> Result<&PageDataResponse> {
+        self.page
+            .get_or_try_init(|| async {
+                let page_id =
+                    to_dashed_id(&self.bucket.page_id).context("Failed to convert dashed id")?;
+                let page = self.client.get_page_data(page_id).await.with_context(|| {
+                    format!("Failed to get notion page {}", self.bucket.page_id)
+                })?;
+
+                log::debug!("page_id = {}", page.page_id);
+                log::debug!("space_id = {}", page.space_id);
+                log::debug!(
+                    "owner_user_id = {}",
+                    page.owner_user_id.as_deref().unwrap_or("")
+                );
+
+                Ok(page)
+            })
+            .await
+    }
+
+    /// アップロードしてカタログに登録し、タグを付ける
+    /// overwrite なら既にある名前を新しいバージョンで置き換える
+    /// expires_in を指定しなければ、保持の決まりの delete-after で期限を決める
+    /// origin は upload と同じ
+    pub async fn put(
+        &self,
+        source: &Path,
+        name: &str,
+        overwrite: bool,
+        tags: &[Tag],
+        expires_in: Option<Duration>,
+        origin: Option<&str>,
+    ) -> Result<FileRow> {
+        self.ensure_writable()?;
+        let result = async {
+            Rejected::check_name(name)?;
+            let exists = FileRow::is_exists(&self.pool, name).await?;
+            let trashed = exists && FileRow::is_trashed(&self.pool, name).await?;
+            if trashed && !overwrite {
+                return Err(Rejected::Trashed(name.to_string()).into());
+            }
+            if exists && !overwrite {
+                return Err(Rejected::Exists(name.to_string()).into());
+            }
+
+            let expires_in = match expires_in {
+                Some(expires_in) => Some(expires_in),
+                None => self
+                    .config
+                    .retention(name)
+                    .map(|rule| rule.delete_after())
+                    .transpose()?
+                    .flatten(),
+            };
+            let mut row = self.upload(source, name, origin).await?;
+            row.expires_at = expires_in
+                .map(|expires_in| {
+                    chrono::Duration::from_std(expires_in).map(|d| row.created_at + d)
+                })
+                .transpose()?;
+            let registered = async {
+                if exists {
+                    // ゴミ箱にあったものは戻してから古いバージョンにする
+                    if trashed {
+                        FileRow::restore(&self.pool, name).await?;
+                    }
+                    row.version = row.overwrite(&self.pool).await?;
+                } else {
+                    row.insert(&self.pool).await?;
+                }
+                anyhow::Ok(())
+            }
+            .await;
+            if let Err(e) = registered {
+                // カタログに載らなかったブロックを残さない
+                if let Err(e) = self.archive(&row).await {
+                    log::warn!(
+                        "Failed to archive unregistered block {}: {e:#}",
+                        row.block_id
+                    );
+                }
+                return Err(e);
+            }
+            Ok(row)
+        }
+        .await;
+
+        let action = match &result {
+            Ok(row) if row.version > 1 => AuditAction::Overwrite,
+            _ => AuditAction::Put,
+        };
+        self.audit_file(action, name, &result).await;
+        let row = result?;
+
+        if row.version > 1 {
+            if let Some(keep) = self.config.keep_versions(name) {
+                self.prune(name, keep).await?;
+            }
+        }
+        FileRow::add_tags(&self.pool, name, tags).await?;
+        // 新しいブロックにはキャプションがないので付け直す
+        self.mirror_tags(&row).await?;
+
+        Ok(row)
+    }
+
+    /// タグを付け外しして、付いているタグを返す
+    pub async fn tag(&self, name: &str, add: &[Tag], remove: &[String]) -> Result<Vec<Tag>> {
+        self.ensure_writable()?;
+        let row = FileRow::find_one(&self.pool, name).await?;
+        let result = async {
+            FileRow::remove_tags(&self.pool, name, remove).await?;
+            FileRow::add_tags(&self.pool, name, add).await?;
+            self.mirror_tags(&row).await
+        }
+        .await;
+        let changes = add
+            .iter()
+            .map(|tag| format!("+{tag}"))
+            .chain(remove.iter().map(|key| format!("-{key}")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let entry = AuditRow::new(AuditAction::Tag, name)
+            .file(&row)
+            .detail(changes)
+            .outcome(&result);
+        self.audit(entry).await;
+        result?;
+        FileRow::tags(&self.pool, name).await
+    }
+
+    /// 設定されていればタグをブロックのキャプションに書く
+    async fn mirror_tags(&self, row: &FileRow) -> Result<()> {
+        if !self.config.tags.caption {
+            return Ok(());
+        }
+        let tags = FileRow::tags(&self.pool, &row.file_name).await?;
+        let caption = tags
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        set_block_property(
+            &self.client,
+            &row.block_id,
+            &row.space_id,
+            "caption",
+            &caption,
+        )
+        .await
+    }
+
+    /// put したときに起きることを書き込まずに返す
+    pub async fn plan_put(
+        &self,
+        source: &Path,
+        name: &str,
+        overwrite: bool,
+    ) -> Result<Vec<Change>> {
+        let size = source
+            .metadata()
+            .with_context(|| format!("Failed to read {source:?}"))?
+            .len() as i64;
+        let path = source.to_string_lossy();
+        if !FileRow::is_exists(&self.pool, name).await? {
+            return Ok(vec![Change::new(Action::Create, name, "new file")
+                .path(path)
+                .size(Some(size))]);
+        }
+        let reason = if FileRow::is_trashed(&self.pool, name).await? {
+            "in the trash"
+        } else {
+            "already exists"
+        };
+        if !overwrite {
+            return Ok(vec![Change::new(
+                Action::Skip,
+                name,
+                format!("{reason} (use --overwrite)"),
+            )
+            .path(path)
+            .size(Some(size))]);
+        }
+
+        let mut plan = vec![Change::new(Action::Overwrite, name, reason)
+            .path(path)
+            .size(Some(size))];
+        if let Some(keep) = self.config.keep_versions(name) {
+            // 今の最新も古いバージョンになるので 1 つ少なく残す
+            let stale = FileRow::stale_versions(&self.pool, name, keep.saturating_sub(1) as i64);
+            for row in stale.await? {
+                plan.push(version_change(&row, format!("exceeds {keep} versions")));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// 新しいほうから keep 個を残して古いバージョンを消す
+    pub async fn prune(&self, name: &str, keep: u32) -> Result<Vec<FileRow>> {
+        self.ensure_writable()?;
+        let stale = FileRow::stale_versions(&self.pool, name, keep as i64).await?;
+        for row in &stale {
+            let result = async {
+                self.archive(row).await?;
+                FileRow::delete_version(&self.pool, &row.file_name, row.version).await
+            }
+            .await;
+            let entry = AuditRow::new(AuditAction::Gc, &row.file_name)
+                .file(row)
+                .outcome(&result);
+            self.audit(entry).await;
+            result?;
+            log::info!("Pruned {} version {}", row.file_name, row.version);
+        }
+        Ok(stale)
+    }
+
+    /// ゴミ箱に入れる (Notion 上のブロックはそのまま残す)
+    /// 戻せるように、アーカイブするのはゴミ箱を空にするときにする
+    pub async fn trash(&self, name: &str) -> Result<FileRow> {
+        self.ensure_writable()?;
+        let result = FileRow::trash(&self.pool, name, Utc::now().naive_utc())
+            .await
+            .and_then(|row| row.with_context(|| format!("file_name ({name}) is not found.")));
+        self.audit_file(AuditAction::Rm, name, &result).await;
+        result
+    }
+
+    /// trash したときに起きることを書き込まずに返す
+    pub async fn plan_trash(&self, name: &str) -> Result<Change> {
+        Ok(match FileRow::find(&self.pool, name).await? {
+            Some(row) => Change::new(Action::Trash, name, "moved to trash").size(row.size),
+            None => Change::new(Action::Skip, name, "not found"),
+        })
+    }
+
+    /// ゴミ箱から戻す
+    pub async fn restore(&self, name: &str) -> Result<FileRow> {
+        self.ensure_writable()?;
+        let result = async {
+            if !FileRow::restore(&self.pool, name).await? {
+                bail!("file_name ({name}) is not in the trash.");
+            }
+            FileRow::find_one(&self.pool, name).await
+        }
+        .await;
+        self.audit_file(AuditAction::Restore, name, &result).await;
+        result
+    }
+
+    /// 古いバージョンも含めて Notion 上でアーカイブし、カタログから消す
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        self.remove_as(AuditAction::Purge, name).await
+    }
+
+    /// 期限が過ぎたファイルを remove と同じように消す (記録は expire にする)
+    pub async fn expire(&self, name: &str) -> Result<()> {
+        self.remove_as(AuditAction::Expire, name).await
+    }
+
+    async fn remove_as(&self, action: AuditAction, name: &str) -> Result<()> {
+        self.ensure_writable()?;
+        let versions = FileRow::versions(&self.pool, name).await?;
+        let result = async {
+            for row in &versions {
+                self.archive(row).await?;
+            }
+            FileRow::delete(&self.pool, name).await
+        }
+        .await;
+        let mut entry = AuditRow::new(action, name).outcome(&result);
+        if let Some(row) = versions.first() {
+            entry = entry.file(row);
+        }
+        self.audit(entry).await;
+        result
+    }
+
+    /// remove したときに消えるバージョンを返す
+    pub async fn plan_remove(&self, name: &str) -> Result<Vec<Change>> {
+        let versions = FileRow::versions(&self.pool, name).await?;
+        if versions.is_empty() {
+            return Ok(vec![Change::new(Action::Skip, name, "not found")]);
+        }
+        Ok(versions
+            .iter()
+            .map(|row| version_change(row, "removed"))
+            .collect())
+    }
+
+    /// 名前を変えて、Notion 上のブロックのタイトルも合わせる
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.ensure_writable()?;
+        let result = async {
+            if FileRow::is_exists(&self.pool, to).await? {
+                return Err(Rejected::Exists(to.to_string()).into());
+            }
+            let Some(current) = FileRow::find(&self.pool, from).await? else {
+                bail!("file_name ({from}) is not found.");
+            };
+            let versions = FileRow::versions(&self.pool, from).await?;
+            for row in &versions {
+                rename_block(&self.client, &row.block_id, &row.space_id, to).await?;
+            }
+            FileRow::rename(&self.pool, from, to).await?;
+            Ok(current)
+        }
+        .await;
+        let mut entry = AuditRow::new(AuditAction::Mv, from)
+            .detail(format!("to {to}"))
+            .outcome(&result);
+        if let Ok(row) = &result {
+            entry = entry.file(row);
+        }
+        self.audit(entry).await;
+        result.map(|_| ())
+    }
+
+    /// rename したときに起きることを書き込まずに返す
+    pub async fn plan_rename(&self, from: &str, to: &str) -> Result<Change> {
+        let Some(row) = FileRow::find(&self.pool, from).await? else {
+            return Ok(Change::new(Action::Skip, from, "not found"));
+        };
+        let (action, reason) = if FileRow::is_exists(&self.pool, to).await? {
+            (Action::Skip, format!("{to} already exists"))
+        } else {
+            (Action::Move, "renamed".to_string())
+        };
+        Ok(Change::new(action, from, reason).path(to).size(row.size))
+    }
+
+    /// ファイルをアップロードして、カタログに登録する前の行を返す
+    /// source が受け取ったものを置いた一時ファイルなら、origin に出どころ (`http:{addr}` など) を渡す
+    /// そのときは source のパスと状態を記録しない
+    pub async fn upload(&self, source: &Path, name: &str, origin: Option<&str>) -> Result<FileRow> {
+        self.ensure_writable()?;
+        let PageDataResponse {
+            page_id, space_id, ..
+        } = self.page().await?;
+
+        // 読めないファイルのためにブロックを作らないよう、先に元ファイルの状態を読む
+        let source_meta = match origin {
+            Some(_) => FileSource::default(),
+            None => FileSource::read(source)?,
+        };
+        let hash = hash_file(source).await?;
+
+        // 圧縮や暗号化をするなら、変換したものを一時ファイルに書いてアップロードする
+        // size、mime、hash は元のファイルのものを記録する
+        let encoding = self.codec.encoding();
+        let spool = Spool::new();
+        let content = match &encoding {
+            Some(_) => {
+                self.codec.encode(source, spool.path()).await?;
+                spool.path()
+            }
+            None => source,
+        };
+
+        // 最初にブロックを作っとかないといけないっぽい
+        let new_block_id = create_new_block(&self.client, space_id, page_id).await?;
+
+        let (url, mime, content_length) =
+            upload_file(&self.client, content, name, &new_block_id, space_id).await?;
+
+        // ブロックにファイルをくっつける
+        attach_file_to_block(
+            &self.client,
+            &new_block_id,
+            space_id,
+            &url,
+            name,
+            content_length,
+        )
+        .await?;
+
+        let (mime, size) = match &encoding {
+            Some(_) => (
+                mime_guess::from_path(source)
+                    .first_or_text_plain()
+                    .to_string(),
+                tokio::fs::metadata(source).await?.len(),
+            ),
+            None => (mime, content_length),
+        };
+        Ok(FileRow {
+            file_url: url,
+            space_id: space_id.clone(),
+            block_id: new_block_id,
+            file_name: name.to_string(),
+            origin_file_path: match origin {
+                Some(origin) => origin.to_string(),
+                None => source
+                    .canonicalize()
+                    .unwrap_or_else(|_| source.to_path_buf())
+                    .to_string_lossy()
+                    .to_string(),
+            },
+            created_at: Utc::now().naive_utc(),
+            size: Some(size as i64),
+            mime: Some(mime),
+            hash: Some(hash),
+            version: 1,
+            source: source_meta,
+            encoding,
+            deleted_at: None,
+            expires_at: None,
+        })
+    }
+
+    /// ファイルをダウンロードして output に保存する
+    /// 圧縮や暗号化してあれば戻してから保存する
+    pub async fn download(&self, row: &FileRow, output: &Path) -> Result<()> {
+        let result = match &row.encoding {
+            None => download_file(&self.client, &self.config.notion.file_token, row, output).await,
+            Some(encoding) => {
+                let result = self.download_decoded(row, encoding, output).await;
+                if result.is_ok() {
+                    log::info!("Saved {output:?}");
+                }
+                result
+            }
+        };
+        let entry = AuditRow::new(AuditAction::Get, &row.file_name)
+            .file(row)
+            .outcome(&result);
+        self.audit(entry).await;
+        result
+    }
+
+    /// 足りない size、mime、hash を埋める
+    /// まずブロックのプロパティを読む。MIME はタイトル (アップロードしたときの名前) から put と同じく推測し、
+    /// サイズは size プロパティから読む
+    /// ハッシュがないか size プロパティもないときだけ中身を 1 回ダウンロードする
+    /// (ダウンロードしたときは、丸められたプロパティの値ではなく実際のバイト数にする)
+    pub async fn backfill(&self, mut row: FileRow) -> Result<FileRow> {
+        self.ensure_writable()?;
+        let name = row.file_name.clone();
+        let result = async {
+            let block = get_block(&self.client, &row.block_id, &row.space_id).await?;
+            let property = |name| {
+                block
+                    .as_ref()
+                    .and_then(|block| get_block_property(block, name))
+            };
+            if row.mime.is_none() {
+                let title = property("title");
+                let path = title.as_deref().unwrap_or(&row.origin_file_path);
+                let mime = mime_guess::from_path(path).first_or_text_plain();
+                row.mime = Some(mime.to_string());
+            }
+            let notion_size = property("size")
+                .map(|text| parse_size(&text).map(|size| size as i64))
+                .transpose()?;
+            if row.hash.is_none() || (row.size.is_none() && notion_size.is_none()) {
+                let (size, hash) = self.hash_remote(&row).await?;
+                row.size.get_or_insert(size as i64);
+                row.hash.get_or_insert(hash);
+            }
+            row.size = row.size.or(notion_size);
+            row.fill_metadata(&self.pool).await?;
+            Ok(row)
+        }
+        .await;
+        self.audit_file(AuditAction::Backfill, &name, &result).await;
+        result
+    }
+
+    /// Notion 上の中身をダウンロードしながらサイズと SHA-256 を求める (保存はしない)
+    pub async fn hash_remote(&self, row: &FileRow) -> Result<(u64, String)> {
+        let res = self.fetch(row).await?;
+        let Some(encoding) = &row.encoding else {
+            return hash_response(res).await;
+        };
+        // 戻したものは保存せずにハッシュだけ求める
+        let spool = Spool::new();
+        save_response(res, spool.path()).await?;
+        let writer = HashWriter::new(std::io::sink());
+        let (_, size, hash) = self
+            .codec
+            .decode(encoding, spool.path(), writer)
+            .await?
+            .finish();
+        Ok((size, hash))
+    }
+
+    /// Notion に置いてある中身 (圧縮や暗号化してあればそのまま) を取ってくる
+    async fn fetch(&self, row: &FileRow) -> Result<notionfs::Response> {
+        let signed_urls = get_signed_file_urls(
+            &self.client,
+            &[(&row.file_url, &row.block_id, &row.space_id)],
+        )
+        .await?;
+        let url = signed_urls.first().context("Failed to get signed url")?;
+        get_file_by_signed_url(url, &self.config.notion.file_token).await
+    }
+
+    /// 一時ファイルにダウンロードし、戻しながら output に書く (記録は残さない)
+    /// 途中で失敗したら output は作らない
+    pub async fn download_decoded(
+        &self,
+        row: &FileRow,
+        encoding: &str,
+        output: &Path,
+    ) -> Result<()> {
+        let spool = Spool::new();
+        save_response(self.fetch(row).await?, spool.path()).await?;
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut part = output.as_os_str().to_owned();
+        part.push(".part");
+        let part = Path::new(&part);
+        let result = async {
+            let file = std::fs::File::create(part)
+                .with_context(|| format!("Failed to create {part:?}"))?;
+            let writer = HashWriter::new(std::io::BufWriter::new(file));
+            let (_, _, hash) = self
+                .codec
+                .decode(encoding, spool.path(), writer)
+                .await?
+                .finish();
+            if row.hash.as_ref().is_some_and(|expected| *expected != hash) {
+                log::warn!("{output:?} does not match the hash in the catalog");
+            }
+            tokio::fs::rename(part, output)
+                .await
+                .with_context(|| format!("Failed to save {output:?}"))
+        }
+        .await;
+        if result.is_err() {
+            tokio::fs::remove_file(part).await.ok();
+        }
+        result
+    }
+
+    /// カタログの行に Notion 上のブロックの状態を合わせる
+    pub async fn stat(&self, row: FileRow) -> Result<StatEntry> {
+        let tags = FileRow::tags(&self.pool, &row.file_name).await?;
+        let block = get_block(&self.client, &row.block_id, &row.space_id)
+            .await
+            .map_err(|e| log::warn!("Failed to get block {}: {e:#}", row.block_id))
+            .ok();
+        let signed_url = get_signed_file_urls(
+            &self.client,
+            &[(&row.file_url, &row.block_id, &row.space_id)],
+        )
+        .await
+        .map_err(|e| log::warn!("Failed to get signed url: {e:#}"))
+        .ok()
+        .and_then(|urls| urls.into_iter().next());
+
+        let (alive, title, size_text, block_url) = match &block {
+            Some(Some(block)) => (
+                Some(block.alive),
+                get_block_property(block, "title"),
+                get_block_property(block, "size"),
+                block
+                    .rest
+                    .get("parent_id")
+                    .and_then(|id| id.as_str())
+                    .map(|parent_id| block_url(parent_id, &block.id)),
+            ),
+            // 見つからないブロックは消えているものとして扱う
+            Some(None) => (Some(false), None, None, None),
+            None => (None, None, None, None),
+        };
+
+        Ok(StatEntry {
+            name: row.file_name,
+            version: row.version,
+            origin_path: row.origin_file_path,
+            created_at: row.created_at.and_utc(),
+            size: row.size,
+            mime: row.mime,
+            hash: row.hash,
+            encoding: row.encoding,
+            block_id: row.block_id,
+            space_id: row.space_id,
+            file_url: row.file_url,
+            alive,
+            title,
+            size_text,
+            block_url,
+            signed_url,
+            tags: tags.iter().map(ToString::to_string).collect(),
+            expires_at: row.expires_at.map(|expires_at| expires_at.and_utc()),
+            source_host: row.source.host,
+            source_mtime: row.source.mtime.map(|mtime| mtime.and_utc()),
+            source_mode: row.source.mode,
+            source_uid: row.source.uid,
+            source_gid: row.source.gid,
+            source_symlink: row.source.symlink,
+        })
+    }
+
+    /// Notion のブロックの size プロパティ (`1.2MB` など丸めた値) からバイト数を読む
+    /// rows のブロックは 1 回でまとめて取得し、block_id ごとに返す
+    /// ブロックかプロパティがない行は入らない
+    pub async fn notion_sizes(&self, rows: &[FileRow]) -> Result<HashMap<String, i64>> {
+        let blocks: Vec<_> = rows
+            .iter()
+            .map(|row| (row.block_id.as_str(), row.space_id.as_str()))
+            .collect();
+        let mut sizes = HashMap::new();
+        for (block_id, block) in get_blocks(&self.client, &blocks).await? {
+            if let Some(text) = get_block_property(&block, "size") {
+                sizes.insert(block_id, parse_size(&text)? as i64);
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// ファイルのブロックを Notion 上でアーカイブする
+    pub async fn archive(&self, row: &FileRow) -> Result<()> {
+        self.ensure_writable()?;
+        let PageDataResponse { page_id, .. } = self.page().await?;
+        archive_block(&self.client, &row.block_id, &row.space_id, page_id).await
+    }
+
+    /// 操作を audit_log に残す (残せなくても操作は失敗させない)
+    pub async fn audit(&self, entry: AuditRow) {
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = entry.insert(&self.pool).await {
+            log::warn!("{e:#}");
+        }
+    }
+
+    async fn audit_file(&self, action: AuditAction, name: &str, result: &Result<FileRow>) {
+        let mut entry = AuditRow::new(action, name).outcome(result);
+        if let Ok(row) = result {
+            entry = entry.file(row);
+        }
+        self.audit(entry).await;
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        ensure!(!self.dry_run, "Refused to write in dry-run mode.");
+        Ok(())
+    }
+}
+
+/// ファイルを block_id のブロックにアップロードする
+/// `(url, mime, content_length)` を返す
+pub async fn upload_file(
+    client: &Notion,
+    source: &Path,
+    name: &str,
+    block_id: &str,
+    space_id: &str,
+) -> Result<(String, String, u64)> {
+    // 署名付きアップロードURLを取得して
+    let (url, signed_get_url, signed_put_url, mime, content_length) =
+        get_signed_put_file(client, source, name, block_id, space_id).await?;
+
+    log::info!("block_id = {block_id}");
+    log::info!("space_id = {space_id}");
+    log::info!("url = {url}");
+    log::info!("signed_get_url = {signed_get_url}");
+    log::debug!("signed_put_url = {signed_put_url}");
+
+    let file = File::open(source)
+        .await
+        .context("Failed to open input file")?;
+
+    let pb = ProgressBar::new(content_length);
+    let stream = create_upload_stream(file, pb);
+
+    put_to_signed_url(
+        &signed_put_url,
+        content_length,
+        &mime,
+        Body::wrap_stream(stream),
+    )
+    .await?;
+
+    Ok((url, mime, content_length))
+}
+
+/// ファイルをダウンロードして output に保存する
+pub async fn download_file(
+    client: &Notion,
+    file_token: &str,
+    row: &FileRow,
+    output: &Path,
+) -> Result<()> {
+    let FileRow {
+        file_url,
+        space_id,
+        block_id,
+        ..
+    } = row;
+
+    let signed_urls = get_signed_file_urls(client, &[(file_url, block_id, space_id)]).await?;
+
+    if let Some(parent) = output.parent() {
+        tokio::fs::create_dir_all(&parent).await?;
+    }
+
+    for url in signed_urls {
+        let res = get_file_by_signed_url(&url, file_token).await?;
+        let (_, hash) = save_response(res, output).await?;
+        if row.hash.as_ref().is_some_and(|expected| *expected != hash) {
+            log::warn!("{output:?} does not match the hash in the catalog");
+        }
+        log::info!("Saved {output:?}");
+
+        log::debug!("- {url}");
+    }
+
+    Ok(())
+}
+
+/// バージョンを消す操作
+fn version_change(row: &FileRow, reason: impl std::fmt::Display) -> Change {
+    Change::new(
+        Action::Delete,
+        &row.file_name,
+        format!("version {}, {reason}", row.version),
+    )
+    .size(row.size)
+}
+
+fn create_upload_stream(
+    file: File,
+    pb: ProgressBar,
+) -> impl Stream<Item = anyhow::Result<bytes::Bytes>> + 'static {
+    async_stream::try_stream! {
+        let mut stream = ReaderStream::new(file);
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            pb.inc(chunk.len() as u64);
+            yield chunk;
+        }
+        pb.finish();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::pin,
+};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+use futures::{Stream, TryStreamExt as _};
+use walkdir::WalkDir;
+
+use crate::{
+    database::{FileQuery, FileRow},
+    hash::hash_file,
+    output::Printer,
+    plan::{Action, Change},
+    storage::Storage,
+};
+
+pub struct SyncOptions {
+    pub dir: PathBuf,
+    pub prefix: String,
+    /// サイズと更新日時が同じでもハッシュを比べる
+    pub checksum: bool,
+    /// ローカルにないファイルをアーカイブする
+    pub delete: bool,
+    pub skip_on_failure: bool,
+}
+
+/// ローカルのファイル
+struct LocalFile {
+    path: PathBuf,
+    size: u64,
+    modified: NaiveDateTime,
+}
+
+/// dir 以下をカタログの prefix 以下に一方向で同期する
+pub async fn sync(
+    storage: &Storage,
+    options: &SyncOptions,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    if options.delete && options.prefix.is_empty() {
+        bail!("Refusing to --delete without --prefix.");
+    }
+
+    let plan = plan(storage, options).await?;
+
+    let mut uploaded = 0;
+    let mut updated = 0;
+    let mut trashed = 0;
+    let mut unchanged = 0;
+    let mut bytes = 0;
+    for (change, row) in plan {
+        if change.action == Action::Skip {
+            unchanged += 1;
+            continue;
+        }
+        printer.print(&change)?;
+        let result = if storage.dry_run {
+            Ok(())
+        } else {
+            execute(storage, &change, row.as_ref()).await
+        };
+        match result {
+            Ok(()) => match change.action {
+                Action::Create => {
+                    uploaded += 1;
+                    bytes += change.size.unwrap_or_default();
+                }
+                Action::Overwrite => {
+                    updated += 1;
+                    bytes += change.size.unwrap_or_default();
+                }
+                Action::Trash => trashed += 1,
+                Action::Move | Action::Delete | Action::Skip => {}
+            },
+            Err(e) => {
+                log::error!("Failed to sync {}", change.name);
+                log::error!("{e:#?}");
+                if !options.skip_on_failure {
+                    bail!("Aborted by error.");
+                }
+            }
+        }
+    }
+
+    let would = if storage.dry_run { "(dry run) " } else { "" };
+    log::info!(
+        "{would}{uploaded} uploaded, {updated} updated, {trashed} trashed, {unchanged} unchanged ({bytes} bytes transferred)"
+    );
+
+    Ok(())
+}
+
+/// ローカルとカタログを比べて同期計画を立てる
+/// どちらも名前順なので、カタログの行は 1 行ずつ突き合わせてメモリに載せない
+/// 実行するときに使うので、変更のある計画にだけカタログの行を付ける
+async fn plan(storage: &Storage, options: &SyncOptions) -> Result<Vec<(Change, Option<FileRow>)>> {
+    let query = FileQuery {
+        prefix: options.prefix.clone(),
+        ..Default::default()
+    };
+    let mut remote = pin!(FileRow::stream(&storage.pool, &query));
+    let mut local = walk(&options.dir, &options.prefix)?.into_iter().peekable();
+
+    let mut plan = Vec::new();
+    let mut next = remote.try_next().await?;
+    loop {
+        let ordering = match (local.peek(), &next) {
+            (None, None) => break,
+            // ローカルを見終わったら、--delete でなければ残りの行は要らない
+            (None, Some(_)) if !options.delete => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((name, _)), Some(row)) => name.as_str().cmp(&row.file_name),
+        };
+        match ordering {
+            Ordering::Less => {
+                let (name, file) = local.next().context("Missing local file")?;
+                plan.push((local_change(Action::Create, &name, &file, "new file"), None));
+            }
+            Ordering::Equal => {
+                let (name, file) = local.next().context("Missing local file")?;
+                let row = next.take().context("Missing catalog row")?;
+                next = advance(&mut remote, &row.file_name).await?;
+                plan.push(match compare(&file, &row, options.checksum).await? {
+                    Some(reason) => (
+                        local_change(Action::Overwrite, &name, &file, &reason),
+                        Some(row),
+                    ),
+                    None => (local_change(Action::Skip, &name, &file, "unchanged"), None),
+                });
+            }
+            Ordering::Greater => {
+                let row = next.take().context("Missing catalog row")?;
+                next = advance(&mut remote, &row.file_name).await?;
+                if options.delete {
+                    let change = Change::new(Action::Trash, &row.file_name, "deleted locally")
+                        .size(row.size);
+                    plan.push((change, Some(row)));
+                }
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+fn local_change(action: Action, name: &str, file: &LocalFile, reason: &str) -> Change {
+    Change::new(action, name, reason)
+        .path(file.path.to_string_lossy())
+        .size(Some(file.size as i64))
+}
+
+/// カタログの次の行 (名前順になっていなければ突き合わせられないのでエラー)
+async fn advance(
+    remote: &mut (impl Stream<Item = Result<FileRow>> + Unpin),
+    previous: &str,
+) -> Result<Option<FileRow>> {
+    let next = remote.try_next().await?;
+    if let Some(row) = &next {
+        ensure!(
+            row.file_name.as_str() > previous,
+            "Catalog rows are not sorted by name ({previous} -> {}).",
+            row.file_name
+        );
+    }
+    Ok(next)
+}
+
+async fn execute(storage: &Storage, change: &Change, row: Option<&FileRow>) -> Result<()> {
+    match (change.action, row) {
+        (Action::Create, _) => {
+            let path = change.path.as_deref().context("Missing local path")?;
+            // ゴミ箱に同じ名前があれば戻して新しいバージョンにする
+            storage
+                .put(Path::new(path), &change.name, true, &[], None, None)
+                .await?;
+        }
+        (Action::Overwrite, Some(_)) => {
+            let path = change.path.as_deref().context("Missing local path")?;
+            storage
+                .put(Path::new(path), &change.name, true, &[], None, None)
+                .await?;
+        }
+        (Action::Trash, Some(old)) => {
+            storage.trash(&old.file_name).await?;
+        }
+        (Action::Move | Action::Delete | Action::Skip, _) => {}
+        (_, None) => bail!("{} is not in the catalog", change.name),
+    }
+    Ok(())
+}
+
+/// 変更されていればその理由を返す
+async fn compare(file: &LocalFile, row: &FileRow, checksum: bool) -> Result<Option<String>> {
+    if let Some(size) = row.size {
+        if size != file.size as i64 {
+            return Ok(Some(format!("size {size} -> {}", file.size)));
+        }
+    }
+    if checksum {
+        if let Some(hash) = &row.hash {
+            return Ok((*hash != hash_file(&file.path).await?).then(|| "hash differs".to_string()));
+        }
+    }
+    // 記録した更新日時があればそれと比べる (データベースはマイクロ秒までしか持たない)
+    let modified = file.modified.trunc_subsecs(6);
+    match row.source.mtime.map(|mtime| mtime.trunc_subsecs(6)) {
+        Some(mtime) if mtime != modified => Ok(Some(format!("mtime {mtime} -> {modified}"))),
+        Some(_) => Ok(None),
+        None if modified > row.created_at => Ok(Some("modified after upload".to_string())),
+        None => Ok(None),
+    }
+}
+
+/// dir 以下のファイルを `prefix + 相対パス` の名前で集める
+fn walk(dir: &Path, prefix: &str) -> Result<BTreeMap<String, LocalFile>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.context("Failed to read directory.")?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir)?;
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let metadata = entry.metadata()?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+        files.insert(
+            format!("{prefix}{name}"),
+            LocalFile {
+                path: entry.path().to_path_buf(),
+                size: metadata.len(),
+                modified: modified.naive_utc(),
+            },
+        );
+    }
+    Ok(files)
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// ファイルに付けるタグ
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Tag {
+    /// `raw` のような値のないタグ (file_tags)
+    Label(String),
+    /// `project=x` のようなキーと値 (file_meta)
+    Meta(String, String),
+}
+
+impl Tag {
+    /// タグ名かメタデータのキー
+    pub fn key(&self) -> &str {
+        match self {
+            Tag::Label(label) => label,
+            Tag::Meta(key, _) => key,
+        }
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tag::Label(label) => write!(f, "{label}"),
+            Tag::Meta(key, value) => write!(f, "{key}={value}"),
+        }
+    }
+}
+
+/// `raw` や `project=x` を読む
+pub fn parse_tag(text: &str) -> Result<Tag> {
+    let tag = match text.split_once('=') {
+        Some((key, value)) => Tag::Meta(key.trim().to_string(), value.trim().to_string()),
+        None => Tag::Label(text.trim().to_string()),
+    };
+    if tag.key().is_empty() {
+        bail!("Tag must not be empty: {text:?}");
+    }
+    Ok(tag)
+}
+
+#[test]
+fn test_parse_tag() {
+    assert_eq!(parse_tag("raw").unwrap(), Tag::Label("raw".to_string()));
+    assert_eq!(
+        parse_tag("project = x").unwrap(),
+        Tag::Meta("project".to_string(), "x".to_string())
+    );
+    assert_eq!(
+        parse_tag("url=a=b").unwrap(),
+        Tag::Meta("url".to_string(), "a=b".to_string())
+    );
+    assert_eq!(
+        parse_tag("client=").unwrap(),
+        Tag::Meta("client".to_string(), String::new())
+    );
+    assert!(parse_tag("=x").is_err());
+    assert!(parse_tag("").is_err());
+}
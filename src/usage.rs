@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, io::Write, pin::pin};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use futures::TryStreamExt as _;
+
+use crate::{
+    database::FileRow,
+    output::{Printer, UsageEntry},
+    storage::Storage,
+};
+
+/// 使用量をまとめる単位
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum UsageBy {
+    /// 名前の先頭から --depth 個の `/` 区切りまで
+    #[default]
+    PrefixDepth,
+    /// カタログのバケット (--bucket に関係なくすべて)
+    Bucket,
+    /// ファイルを置いた Notion のスペース (space_id、--bucket に関係なくすべて)
+    Space,
+    /// 登録した月 (UTC)、累計も出す
+    Month,
+}
+
+/// size のない行の size プロパティを 1 回で取得する行数
+const NOTION_BATCH: usize = 100;
+
+/// まとめた 1 つのグループ
+#[derive(Default)]
+struct Usage {
+    files: u64,
+    bytes: i64,
+    unknown: u64,
+}
+
+/// Notion に置いてあるファイルの数とバイト数をまとめて表示する
+/// 古いバージョンやゴミ箱のものも Notion の容量を使うので数える
+/// カタログに size がない行は Notion の size プロパティで補う
+/// 圧縮や暗号化した行は元のファイルの size で数える
+pub async fn usage(
+    storage: &Storage,
+    by: UsageBy,
+    prefix: &str,
+    depth: usize,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let mut groups = BTreeMap::<String, Usage>::new();
+    let pools = match by {
+        UsageBy::Bucket | UsageBy::Space => storage
+            .pool
+            .buckets()
+            .await?
+            .into_iter()
+            .map(|bucket| storage.pool.clone().with_bucket(&bucket))
+            .collect(),
+        UsageBy::PrefixDepth | UsageBy::Month => vec![storage.pool.clone()],
+    };
+    // size のない行は NOTION_BATCH 行ずつまとめて Notion に聞く
+    let mut pending = Vec::new();
+    for pool in &pools {
+        let mut rows = pin!(FileRow::stored(pool, prefix));
+        while let Some(row) = rows.try_next().await? {
+            let key = match by {
+                UsageBy::PrefixDepth => group_prefix(&row.file_name, depth),
+                UsageBy::Bucket => pool.bucket().to_string(),
+                UsageBy::Space => row.space_id.clone(),
+                UsageBy::Month => row.created_at.format("%Y-%m").to_string(),
+            };
+            let usage = groups.entry(key.clone()).or_default();
+            usage.files += 1;
+            match row.size {
+                Some(size) => usage.bytes += size,
+                None => pending.push((key, row)),
+            }
+            if pending.len() >= NOTION_BATCH {
+                add_notion_sizes(storage, &mut groups, pending.drain(..)).await;
+            }
+        }
+    }
+    add_notion_sizes(storage, &mut groups, pending.drain(..)).await;
+
+    let mut total_bytes = 0;
+    for (key, usage) in groups {
+        total_bytes += usage.bytes;
+        printer.print(&UsageEntry {
+            key,
+            files: usage.files,
+            bytes: usage.bytes,
+            unknown: usage.unknown,
+            total_bytes: (by == UsageBy::Month).then_some(total_bytes),
+        })?;
+    }
+    Ok(())
+}
+
+/// size のない行を Notion の size プロパティで補う (取れなければ unknown に数える)
+async fn add_notion_sizes(
+    storage: &Storage,
+    groups: &mut BTreeMap<String, Usage>,
+    pending: impl Iterator<Item = (String, FileRow)>,
+) {
+    let (keys, rows): (Vec<_>, Vec<_>) = pending.unzip();
+    if rows.is_empty() {
+        return;
+    }
+    let sizes = storage.notion_sizes(&rows).await.unwrap_or_else(|e| {
+        log::warn!(
+            "Failed to get sizes of {} files from notion: {e:#}",
+            rows.len()
+        );
+        Default::default()
+    });
+    for (key, row) in keys.into_iter().zip(rows) {
+        let usage = groups.entry(key).or_default();
+        match sizes.get(&row.block_id) {
+            Some(size) => usage.bytes += size,
+            None => usage.unknown += 1,
+        }
+    }
+}
+
+/// 名前の先頭から depth 個のディレクトリまで (`/` で終わる)
+/// ディレクトリが depth 個より少なければあるだけ、直下のファイルなら空文字列
+fn group_prefix(name: &str, depth: usize) -> String {
+    let directories = name.split('/').count() - 1;
+    name.split_inclusive('/')
+        .take(depth.min(directories))
+        .collect()
+}
+
+#[test]
+fn test_group_prefix() {
+    assert_eq!(group_prefix("a/b/c.txt", 1), "a/");
+    assert_eq!(group_prefix("a/b/c.txt", 2), "a/b/");
+    assert_eq!(group_prefix("a/b/c.txt", 5), "a/b/");
+    assert_eq!(group_prefix("c.txt", 1), "");
+    assert_eq!(group_prefix("a/b/c.txt", 0), "");
+}
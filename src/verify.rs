@@ -0,0 +1,52 @@
+use std::{io::Write, pin::pin};
+
+use anyhow::{bail, Result};
+use futures::TryStreamExt as _;
+
+use crate::{
+    database::{FileQuery, FileRow},
+    output::{Printer, VerifyEntry},
+    storage::Storage,
+};
+
+/// prefix 以下の今のバージョンをダウンロードして、カタログの size と hash に合っているか確かめる
+/// 中身は保存せずに読みながらハッシュを求め、行もカタログから少しずつ読む
+pub async fn verify(
+    storage: &Storage,
+    prefix: &str,
+    skip_on_failure: bool,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let query = FileQuery {
+        prefix: prefix.to_string(),
+        ..Default::default()
+    };
+    let mut rows = pin!(FileRow::stream(&storage.pool, &query));
+    let (mut verified, mut mismatched, mut failed) = (0, 0, 0);
+    while let Some(row) = rows.try_next().await? {
+        match storage.hash_remote(&row).await {
+            Ok((size, hash)) => {
+                let entry = VerifyEntry::new(row, size, hash);
+                if entry.ok {
+                    verified += 1;
+                } else {
+                    mismatched += 1;
+                }
+                printer.print(&entry)?;
+            }
+            Err(e) => {
+                failed += 1;
+                log::error!("Failed to verify {}", row.file_name);
+                log::error!("{e:#?}");
+                if !skip_on_failure {
+                    bail!("Aborted by error.");
+                }
+            }
+        }
+    }
+    log::info!("{verified} verified, {mismatched} mismatched, {failed} failed");
+    if mismatched > 0 {
+        bail!("{mismatched} files do not match the catalog.");
+    }
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Local};
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+use crate::{
+    database::Rejected,
+    output::{FileEntry, Printer},
+    storage::Storage,
+};
+
+pub struct WatchOptions {
+    pub dir: PathBuf,
+    pub prefix: String,
+    /// 名前のテンプレート
+    pub name_template: String,
+    /// サイズがこの時間変わらなければ書き込みが終わったとみなす
+    pub stable: Duration,
+    /// 起動時に既にあるファイルもアップロードする
+    pub initial_scan: bool,
+    /// 同じ名前があれば新しいバージョンにする
+    pub overwrite: bool,
+    /// アップロード後の扱い
+    pub after: AfterUpload,
+}
+
+/// アップロードが終わったローカルのファイルの扱い
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum AfterUpload {
+    Keep,
+    Remove,
+    MoveTo(PathBuf),
+}
+
+/// アップロードに失敗したファイルを次に試すまでの最長の間隔
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// 書き込み中かもしれないファイル
+struct Pending {
+    size: u64,
+    since: Instant,
+    /// 失敗したアップロードの回数
+    failures: u32,
+    /// 失敗したファイルはこの時刻まで試さない
+    retry_at: Option<Instant>,
+}
+
+impl Pending {
+    fn new(size: u64) -> Pending {
+        Pending {
+            size,
+            since: Instant::now(),
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// 失敗したので、間隔を倍にしながら (最長 MAX_RETRY_INTERVAL) もう一度試す
+    /// 次に試すまでの間隔を返す
+    fn retry(&mut self, stable: Duration) -> Duration {
+        let interval = stable
+            .max(Duration::from_secs(1))
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_RETRY_INTERVAL);
+        self.failures += 1;
+        self.retry_at = Some(Instant::now() + interval);
+        interval
+    }
+}
+
+/// dir に置かれたファイルを書き込みが落ち着いたらアップロードする
+pub async fn watch(
+    storage: &Storage,
+    options: &WatchOptions,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let dir = options
+        .dir
+        .canonicalize()
+        .with_context(|| format!("Failed to open {:?}", options.dir))?;
+    let ignored = match &options.after {
+        AfterUpload::MoveTo(dest) => {
+            tokio::fs::create_dir_all(dest).await?;
+            Some(dest.canonicalize()?)
+        }
+        _ => None,
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })
+    .context("Failed to create watcher")?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {dir:?}"))?;
+    log::info!("Watching {dir:?}");
+
+    let mut pending = HashMap::new();
+    if options.initial_scan {
+        for entry in WalkDir::new(&dir).into_iter().flatten() {
+            if entry.file_type().is_file() {
+                track(&mut pending, &dir, ignored.as_deref(), entry.path());
+            }
+        }
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut ctrl_c = pin!(tokio::signal::ctrl_c());
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                match event {
+                    Ok(event) if is_write(&event.kind) => {
+                        for path in &event.paths {
+                            track(&mut pending, &dir, ignored.as_deref(), path);
+                        }
+                    }
+                    Ok(Event { kind: EventKind::Remove(_), paths, .. }) => {
+                        for path in &paths {
+                            pending.remove(path);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Watch error: {e:#}"),
+                }
+            }
+            _ = interval.tick() => {
+                for (path, mut state) in settle(&mut pending, options.stable).await {
+                    if let Err(e) = upload(storage, options, &dir, &path, printer).await {
+                        log::error!("Failed to upload {}", path.to_string_lossy());
+                        log::error!("{e:#?}");
+                        // 名前がぶつかるなどやり直しても通らないものは、また書き込まれるまで試さない
+                        if Rejected::find(&e).is_some() {
+                            log::warn!("Gave up {} until it is written again", path.to_string_lossy());
+                            continue;
+                        }
+                        // 書き込みがなくても取りこぼさないように、間をおいてまた試す
+                        let interval = state.retry(options.stable);
+                        log::info!("Retry {} in {}s", path.to_string_lossy(), interval.as_secs());
+                        pending.insert(path, state);
+                    }
+                }
+            }
+            result = &mut ctrl_c => {
+                result?;
+                log::info!("Stopped watching {dir:?}");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_write(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+/// 対象のファイルなら書き込み中として記録する
+fn track(pending: &mut HashMap<PathBuf, Pending>, dir: &Path, ignored: Option<&Path>, path: &Path) {
+    if ignored.is_some_and(|ignored| path.starts_with(ignored)) {
+        return;
+    }
+    // 隠しファイルは作業中の一時ファイルのことが多いので無視する
+    let hidden = path
+        .strip_prefix(dir)
+        .map(|relative| {
+            relative
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        })
+        .unwrap_or(true);
+    if hidden || !path.is_file() {
+        return;
+    }
+    let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+    // 書き込まれたら中身が変わっているので、失敗していてもすぐに試す
+    pending.insert(path.to_path_buf(), Pending::new(size));
+}
+
+/// サイズが stable の間変わっていないファイルを取り出す
+/// 失敗したファイルは retry_at を過ぎるまで取り出さない
+async fn settle(
+    pending: &mut HashMap<PathBuf, Pending>,
+    stable: Duration,
+) -> Vec<(PathBuf, Pending)> {
+    let mut settled = Vec::new();
+    let mut vanished = Vec::new();
+    for (path, state) in pending.iter_mut() {
+        let Ok(metadata) = tokio::fs::metadata(path).await else {
+            vanished.push(path.clone());
+            continue;
+        };
+        if metadata.len() != state.size {
+            state.size = metadata.len();
+            state.since = Instant::now();
+        } else if state.since.elapsed() >= stable
+            && state.retry_at.is_none_or(|at| at <= Instant::now())
+        {
+            settled.push(path.clone());
+        }
+    }
+    for path in &vanished {
+        pending.remove(path);
+    }
+    settled.sort();
+    settled
+        .into_iter()
+        .filter_map(|path| pending.remove_entry(&path))
+        .collect()
+}
+
+async fn upload(
+    storage: &Storage,
+    options: &WatchOptions,
+    dir: &Path,
+    path: &Path,
+    printer: &mut Printer<impl Write>,
+) -> Result<()> {
+    let relative = path.strip_prefix(dir)?;
+    let name = format!(
+        "{}{}",
+        options.prefix,
+        render_name(&options.name_template, relative, Local::now())
+    );
+
+    if storage.dry_run {
+        for change in storage.plan_put(path, &name, options.overwrite).await? {
+            printer.print(&change)?;
+        }
+        return Ok(());
+    }
+
+    let row = storage
+        .put(path, &name, options.overwrite, &[], None, None)
+        .await?;
+    printer.print(&FileEntry::from(row))?;
+
+    // アップロードはできているので、ここで失敗してももう一度アップロードはしない
+    if let Err(e) = after_upload(options, relative, path).await {
+        log::error!("{e:#}");
+    }
+    Ok(())
+}
+
+async fn after_upload(options: &WatchOptions, relative: &Path, path: &Path) -> Result<()> {
+    match &options.after {
+        AfterUpload::Keep => {}
+        AfterUpload::Remove => {
+            tokio::fs::remove_file(path)
+                .await
+                .with_context(|| format!("Failed to remove {path:?}"))?;
+        }
+        AfterUpload::MoveTo(dest) => {
+            let dest = dest.join(relative);
+            if let Some(parent) = dest.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(path, &dest)
+                .await
+                .with_context(|| format!("Failed to move {path:?} to {dest:?}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 名前のテンプレートを展開する
+///
+/// - `{path}`: 監視しているディレクトリからの相対パス
+/// - `{name}`: ファイル名
+/// - `{stem}`: 拡張子を除いたファイル名
+/// - `{ext}`: 拡張子
+/// - `{date}`: `YYYY-MM-DD`
+/// - `{time}`: `HHMMSS`
+pub fn render_name(template: &str, relative: &Path, now: DateTime<Local>) -> String {
+    let path = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let part = |s: Option<&std::ffi::OsStr>| {
+        s.map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    template
+        .replace("{path}", &path)
+        .replace("{name}", &part(relative.file_name()))
+        .replace("{stem}", &part(relative.file_stem()))
+        .replace("{ext}", &part(relative.extension()))
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H%M%S").to_string())
+}
+
+#[test]
+fn test_render_name() {
+    use chrono::TimeZone;
+
+    let now = Local.with_ymd_and_hms(2023, 10, 1, 9, 8, 7).unwrap();
+    let relative = Path::new("scans/receipt.pdf");
+    assert_eq!(render_name("{path}", relative, now), "scans/receipt.pdf");
+    assert_eq!(
+        render_name("{date}/{stem}-{time}.{ext}", relative, now),
+        "2023-10-01/receipt-090807.pdf"
+    );
+}
+
+#[test]
+fn test_retry_interval() {
+    let mut state = Pending::new(0);
+    let stable = Duration::from_secs(5);
+    assert_eq!(state.retry(stable), Duration::from_secs(5));
+    assert_eq!(state.retry(stable), Duration::from_secs(10));
+    assert_eq!(state.retry(stable), Duration::from_secs(20));
+    for _ in 0..40 {
+        state.retry(stable);
+    }
+    assert_eq!(state.retry(stable), MAX_RETRY_INTERVAL);
+}